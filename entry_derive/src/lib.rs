@@ -0,0 +1,940 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    Data, DeriveInput, Fields, GenericArgument, LitStr, PathArguments, Type, parse_macro_input,
+    spanned::Spanned,
+};
+
+/// Derives the `Entry` trait for a struct with named fields.
+///
+/// # Container attributes
+/// - `#[entry(table = "users")]` (required): the database table name.
+/// - `#[entry(belongs_to(name = "user", entry = User, foreign_key = "user_id"))]`,
+///   `#[entry(has_many(name = "orders", entry = Order, foreign_key = "user_id"))]` and
+///   `#[entry(many_to_many(name = "roles", entry = Role, pivot = "user_roles",
+///   local_key = "user_id", foreign_key = "role_id"))]`: relations returned by
+///   `relations()`, loaded with `QueryBuilderUtil::with`.
+/// - `#[entry(hooks)]`: leaves out the implementation of `EntryHooks`, whose no-op
+///   lifecycle hooks are derived otherwise, for an `impl EntryHooks` written with the
+///   struct (`before_insert`...).
+/// - `#[entry(crate = "my_api")]`: path of the crate defining `Entry`, to derive it from
+///   another crate. The generated code names `<crate>::core::base::...`, with `crate` by
+///   default: without the attribute, the derive only works inside this crate.
+///
+/// # Field attributes
+/// - `#[entry(primary_key)]` (required on exactly one field): its type becomes `Entry::Id`
+///   and its name `primary_key_column()`.
+/// - `#[entry(timestamp = "created")]` / `#[entry(timestamp = "updated")]`: field written by
+///   `set_created_at` / `set_updated_at`, returned by `created_at_column()` /
+///   `updated_at_column()`.
+/// - `#[entry(timestamp = "deleted")]`: nullable field returned by `soft_delete_column()`;
+///   deleting the entry then sets it instead of removing the row.
+/// - `#[entry(version)]`: integer field returned by `version_column()`, checked and incremented
+///   by repository updates. Left to the database default on insert.
+/// - `#[entry(tenant)]`: field returned by `tenant_column()`, holding the tenant owning the row.
+/// - `#[entry(skip_insert)]`: column generated by the database, left out of `insertable_columns()`.
+/// - `#[entry(skip)]`: field that is not a column at all.
+/// - `#[entry(sql_type = "numeric")]`: SQL type of the column, one of `text`, `integer`,
+///   `float`, `numeric`, `boolean`, `uuid`, `timestamp`, `date` or `json`.
+/// - `#[entry(enum_type = "user_role")]`: the column has the given Postgres enum type.
+///
+/// Primary key and timestamp columns are never insertable, as with the trait's default
+/// `insertable_columns()`.
+///
+/// `column_type()` is inferred from the field types (`String`, `i8` to `i64`, floats, `bool`,
+/// `Uuid`, `DateTime`, `NaiveDateTime`, `NaiveDate`, `serde_json::Value`, `Json<_>` and
+/// `Vec`s of them, optionally in an `Option`); other fields need `sql_type` or
+/// `enum_type`, or their values are bound from their JSON form. Unsigned and 128-bit
+/// integer fields are rejected, as sqlx cannot read them from every database.
+#[proc_macro_derive(Entry, attributes(entry))]
+pub fn derive_entry(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct ContainerOptions {
+    table: LitStr,
+    relations: Vec<RelationOptions>,
+    hooks: bool,
+    // Path of the crate holding the `core` module
+    root: syn::Path,
+}
+
+// `belongs_to(...)`, `has_many(...)` or `many_to_many(...)`
+struct RelationOptions {
+    kind: syn::Ident,
+    name: LitStr,
+    entry: syn::Path,
+    foreign_key: LitStr,
+    // `pivot` and `local_key` of a many to many relation
+    pivot: Option<(LitStr, LitStr)>,
+}
+
+#[derive(Default)]
+struct FieldOptions {
+    primary_key: bool,
+    version: bool,
+    tenant: bool,
+    skip_insert: bool,
+    skip: bool,
+    timestamp: Option<Timestamp>,
+    // `ColumnType` variant
+    column_type: Option<TokenStream2>,
+}
+
+enum Timestamp {
+    Created,
+    Updated,
+    Deleted,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let ContainerOptions {
+        table,
+        relations,
+        hooks,
+        root,
+    } = parse_container(&input)?;
+    let entry_trait = quote! { #root::core::base::generic_repository::entry_trait };
+    let relations: Vec<TokenStream2> = relations
+        .iter()
+        .map(|relation| relation.to_tokens(&root))
+        .collect();
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.span(),
+                    "#[derive(Entry)] requires a struct with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.span(),
+                "#[derive(Entry)] can only be used on structs",
+            ));
+        }
+    };
+
+    let mut columns = Vec::new();
+    let mut column_types = Vec::new();
+    let mut insertable_columns = Vec::new();
+    let mut primary_key: Option<(&Type, String)> = None;
+    let mut created_at = None;
+    let mut updated_at = None;
+    let mut deleted_at = None;
+    let mut version = None;
+    let mut tenant = None;
+
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named field");
+        let options = parse_field_options(field)?;
+        if options.skip {
+            continue;
+        }
+
+        check_integer_type(&field.ty)?;
+        let column = ident.to_string();
+        columns.push(column.clone());
+        if let Some(column_type) = options
+            .column_type
+            .or_else(|| infer_column_type(&field.ty, &root))
+        {
+            column_types.push(quote! {
+                #column => Some(#entry_trait::ColumnType::#column_type)
+            });
+        }
+
+        if options.primary_key {
+            if primary_key.is_some() {
+                return Err(syn::Error::new(
+                    field.span(),
+                    "only one field can be marked #[entry(primary_key)]",
+                ));
+            }
+            primary_key = Some((&field.ty, column.clone()));
+        }
+
+        if options.version {
+            if version.is_some() {
+                return Err(syn::Error::new(
+                    field.span(),
+                    "only one field can be marked #[entry(version)]",
+                ));
+            }
+            version = Some(column.clone());
+        }
+
+        if options.tenant {
+            if tenant.is_some() {
+                return Err(syn::Error::new(
+                    field.span(),
+                    "only one field can be marked #[entry(tenant)]",
+                ));
+            }
+            tenant = Some(column.clone());
+        }
+
+        match options.timestamp {
+            Some(Timestamp::Created) if created_at.is_none() => created_at = Some(ident),
+            Some(Timestamp::Updated) if updated_at.is_none() => updated_at = Some(ident),
+            Some(Timestamp::Deleted) if deleted_at.is_none() => deleted_at = Some(column.clone()),
+            Some(_) => {
+                return Err(syn::Error::new(
+                    field.span(),
+                    "duplicate #[entry(timestamp = ...)] attribute",
+                ));
+            }
+            None => {}
+        }
+
+        if !options.primary_key
+            && !options.version
+            && !options.skip_insert
+            && options.timestamp.is_none()
+        {
+            insertable_columns.push(column);
+        }
+    }
+
+    let (id_type, primary_key_column) = primary_key.ok_or_else(|| {
+        syn::Error::new(
+            input.span(),
+            "#[derive(Entry)] requires one field marked #[entry(primary_key)]",
+        )
+    })?;
+
+    let set_created_at = match created_at {
+        Some(ident) => quote! { self.#ident = created_at.into(); },
+        None => quote! { let _ = created_at; },
+    };
+    let set_updated_at = match updated_at {
+        Some(ident) => quote! { self.#ident = updated_at.into(); },
+        None => quote! { let _ = updated_at; },
+    };
+    let created_at_column = match created_at {
+        Some(ident) => {
+            let column = ident.to_string();
+            quote! { Some(#column) }
+        }
+        None => quote! { None },
+    };
+    let updated_at_column = match updated_at {
+        Some(ident) => {
+            let column = ident.to_string();
+            quote! { Some(#column) }
+        }
+        None => quote! { None },
+    };
+    let soft_delete_column = match deleted_at {
+        Some(column) => quote! { Some(#column) },
+        None => quote! { None },
+    };
+    let version_column = match version {
+        Some(column) => quote! { Some(#column) },
+        None => quote! { None },
+    };
+    let tenant_column = match tenant {
+        Some(column) => quote! { Some(#column) },
+        None => quote! { None },
+    };
+
+    let items = quote! {
+        type Id = #id_type;
+
+        fn set_created_at(&mut self, created_at: ::chrono::DateTime<::chrono::Utc>) {
+            #set_created_at
+        }
+
+        fn set_updated_at(&mut self, updated_at: ::chrono::DateTime<::chrono::Utc>) {
+            #set_updated_at
+        }
+
+        fn table_name() -> &'static str {
+            #table
+        }
+
+        fn columns() -> Vec<&'static str> {
+            vec![#(#columns),*]
+        }
+
+        fn primary_key_column() -> &'static str {
+            #primary_key_column
+        }
+
+        fn created_at_column() -> Option<&'static str> {
+            #created_at_column
+        }
+
+        fn updated_at_column() -> Option<&'static str> {
+            #updated_at_column
+        }
+
+        fn insertable_columns() -> Vec<&'static str> {
+            vec![#(#insertable_columns),*]
+        }
+
+        fn soft_delete_column() -> Option<&'static str> {
+            #soft_delete_column
+        }
+
+        fn version_column() -> Option<&'static str> {
+            #version_column
+        }
+
+        fn tenant_column() -> Option<&'static str> {
+            #tenant_column
+        }
+
+        fn column_type(
+            column: &str,
+        ) -> Option<#entry_trait::ColumnType> {
+            match column {
+                #(#column_types,)*
+                _ => None,
+            }
+        }
+
+        fn relations() -> Vec<#root::core::base::query_builder::relation::Relation> {
+            vec![#(#relations),*]
+        }
+    };
+
+    // With hooks, the struct implements `EntryHooks` itself
+    let entry_hooks = if hooks {
+        quote! {}
+    } else {
+        quote! {
+            impl #impl_generics #entry_trait::EntryHooks for #name #ty_generics #where_clause {}
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics #entry_trait::Entry for #name #ty_generics #where_clause
+        {
+            #items
+        }
+
+        #entry_hooks
+    })
+}
+
+fn parse_container(input: &DeriveInput) -> syn::Result<ContainerOptions> {
+    let mut table = None;
+    let mut relations = Vec::new();
+    let mut hooks = false;
+    let mut root = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("entry")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                table = Some(meta.value()?.parse::<LitStr>()?);
+                Ok(())
+            } else if meta.path.is_ident("belongs_to")
+                || meta.path.is_ident("has_many")
+                || meta.path.is_ident("many_to_many")
+            {
+                relations.push(parse_relation(&meta)?);
+                Ok(())
+            } else if meta.path.is_ident("hooks") {
+                hooks = true;
+                Ok(())
+            } else if meta.path.is_ident("crate") {
+                root = Some(meta.value()?.parse::<LitStr>()?.parse::<syn::Path>()?);
+                Ok(())
+            } else {
+                Err(meta.error(
+                    "unsupported entry attribute, expected `table`, `belongs_to`, `has_many`, `many_to_many`, `hooks` or `crate`",
+                ))
+            }
+        })?;
+    }
+    let table = table.ok_or_else(|| {
+        syn::Error::new(
+            input.span(),
+            "#[derive(Entry)] requires #[entry(table = \"...\")]",
+        )
+    })?;
+    Ok(ContainerOptions {
+        table,
+        relations,
+        hooks,
+        root: root.unwrap_or_else(|| syn::parse_quote!(crate)),
+    })
+}
+
+fn parse_relation(meta: &syn::meta::ParseNestedMeta) -> syn::Result<RelationOptions> {
+    let kind = meta.path.get_ident().expect("relation kind").clone();
+    let mut name = None;
+    let mut entry = None;
+    let mut foreign_key = None;
+    let mut pivot = None;
+    let mut local_key = None;
+    meta.parse_nested_meta(|meta| {
+        if meta.path.is_ident("name") {
+            name = Some(meta.value()?.parse::<LitStr>()?);
+        } else if meta.path.is_ident("entry") {
+            entry = Some(meta.value()?.parse::<syn::Path>()?);
+        } else if meta.path.is_ident("foreign_key") {
+            foreign_key = Some(meta.value()?.parse::<LitStr>()?);
+        } else if meta.path.is_ident("pivot") && kind == "many_to_many" {
+            pivot = Some(meta.value()?.parse::<LitStr>()?);
+        } else if meta.path.is_ident("local_key") && kind == "many_to_many" {
+            local_key = Some(meta.value()?.parse::<LitStr>()?);
+        } else if kind == "many_to_many" {
+            return Err(
+                meta.error("expected `name`, `entry`, `pivot`, `local_key` or `foreign_key`")
+            );
+        } else {
+            return Err(meta.error("expected `name`, `entry` or `foreign_key`"));
+        }
+        Ok(())
+    })?;
+
+    let missing = |argument: &str| meta.error(format!("`{}` requires `{}`", kind, argument));
+    let name = name.ok_or_else(|| missing("name"))?;
+    let entry = entry.ok_or_else(|| missing("entry"))?;
+    let foreign_key = foreign_key.ok_or_else(|| missing("foreign_key"))?;
+
+    let pivot = if kind == "many_to_many" {
+        let pivot = pivot.ok_or_else(|| missing("pivot"))?;
+        let local_key = local_key.ok_or_else(|| missing("local_key"))?;
+        Some((pivot, local_key))
+    } else {
+        None
+    };
+    Ok(RelationOptions {
+        kind,
+        name,
+        entry,
+        foreign_key,
+        pivot,
+    })
+}
+
+impl RelationOptions {
+    // `Relation` constructor call
+    fn to_tokens(&self, root: &syn::Path) -> TokenStream2 {
+        let RelationOptions {
+            kind,
+            name,
+            entry,
+            foreign_key,
+            pivot,
+        } = self;
+        let relation = quote! { #root::core::base::query_builder::relation::Relation };
+        match pivot {
+            Some((pivot, local_key)) => quote! {
+                #relation::many_to_many::<#entry>(#name, #pivot, #local_key, #foreign_key)
+            },
+            None => quote! { #relation::#kind::<#entry>(#name, #foreign_key) },
+        }
+    }
+}
+
+fn parse_field_options(field: &syn::Field) -> syn::Result<FieldOptions> {
+    let mut options = FieldOptions::default();
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("entry")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("primary_key") {
+                options.primary_key = true;
+            } else if meta.path.is_ident("version") {
+                options.version = true;
+            } else if meta.path.is_ident("tenant") {
+                options.tenant = true;
+            } else if meta.path.is_ident("skip_insert") {
+                options.skip_insert = true;
+            } else if meta.path.is_ident("skip") {
+                options.skip = true;
+            } else if meta.path.is_ident("sql_type") {
+                let value = meta.value()?.parse::<LitStr>()?;
+                options.column_type = Some(parse_sql_type(&value)?);
+            } else if meta.path.is_ident("enum_type") {
+                let value = meta.value()?.parse::<LitStr>()?;
+                options.column_type = Some(quote! { Enum(#value) });
+            } else if meta.path.is_ident("timestamp") {
+                let value = meta.value()?.parse::<LitStr>()?;
+                options.timestamp = Some(match value.value().as_str() {
+                    "created" => Timestamp::Created,
+                    "updated" => Timestamp::Updated,
+                    "deleted" => Timestamp::Deleted,
+                    _ => {
+                        return Err(syn::Error::new(
+                            value.span(),
+                            "expected \"created\", \"updated\" or \"deleted\"",
+                        ));
+                    }
+                });
+            } else {
+                return Err(meta.error(
+                    "unsupported entry attribute, expected `primary_key`, `version`, `tenant`, `skip_insert`, `skip`, `timestamp`, `sql_type` or `enum_type`",
+                ));
+            }
+            Ok(())
+        })?;
+    }
+
+    if options.skip
+        && (options.primary_key || options.version || options.tenant || options.timestamp.is_some())
+    {
+        return Err(syn::Error::new(
+            field.span(),
+            "#[entry(skip)] cannot be combined with `primary_key`, `version`, `tenant` or `timestamp`",
+        ));
+    }
+
+    Ok(options)
+}
+
+fn parse_sql_type(value: &LitStr) -> syn::Result<TokenStream2> {
+    Ok(match value.value().as_str() {
+        "text" => quote! { Text },
+        "integer" => quote! { Integer },
+        "float" => quote! { Float },
+        "numeric" => quote! { Numeric },
+        "boolean" => quote! { Boolean },
+        "uuid" => quote! { Uuid },
+        "timestamp" => quote! { Timestamp },
+        "date" => quote! { Date },
+        "json" => quote! { Json },
+        _ => {
+            return Err(syn::Error::new(
+                value.span(),
+                "expected \"text\", \"integer\", \"float\", \"numeric\", \"boolean\", \"uuid\", \"timestamp\", \"date\" or \"json\"",
+            ));
+        }
+    })
+}
+
+// `ColumnType` variant of a field from its Rust type, looking through `Option`
+fn infer_column_type(ty: &Type, root: &syn::Path) -> Option<TokenStream2> {
+    let (name, argument) = last_segment(ty)?;
+    let variant = match name.as_str() {
+        "Option" => return infer_column_type(argument?, root),
+        "Vec" => {
+            let array_type = match last_segment(argument?)?.0.as_str() {
+                "String" => quote! { Text },
+                "i32" => quote! { Int },
+                "i64" => quote! { BigInt },
+                "f64" => quote! { Float },
+                "bool" => quote! { Bool },
+                "Uuid" => quote! { Uuid },
+                _ => return None,
+            };
+            quote! { Array(#root::core::base::query_builder::query_models::ArrayType::#array_type) }
+        }
+        "String" => quote! { Text },
+        "i8" | "i16" | "i32" | "i64" => quote! { Integer },
+        "f32" | "f64" => quote! { Float },
+        "bool" => quote! { Boolean },
+        "Uuid" => quote! { Uuid },
+        "DateTime" | "NaiveDateTime" => quote! { Timestamp },
+        "NaiveDate" => quote! { Date },
+        "Value" | "Json" | "JsonValue" => quote! { Json },
+        "Decimal" | "BigDecimal" => quote! { Numeric },
+        _ => return None,
+    };
+    Some(variant)
+}
+
+// Rejects the integer types sqlx cannot bind or read on every database (unsigned and
+// 128-bit integers), looking through `Option`
+fn check_integer_type(ty: &Type) -> syn::Result<()> {
+    let Some((name, argument)) = last_segment(ty) else {
+        return Ok(());
+    };
+    match name.as_str() {
+        "Option" => argument.map_or(Ok(()), check_integer_type),
+        "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i128" | "isize" => Err(syn::Error::new(
+            ty.span(),
+            format!(
+                "unsupported integer type `{}` for an entry column, expected i8, i16, i32 or i64",
+                name
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Name of the last path segment of a type and its first generic type argument
+fn last_segment(ty: &Type) -> Option<(String, Option<&Type>)> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    let argument = match &segment.arguments {
+        PathArguments::AngleBracketed(arguments) => {
+            arguments.args.iter().find_map(|argument| match argument {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            })
+        }
+        _ => None,
+    };
+    Some((segment.ident.to_string(), argument))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quote::ToTokens;
+    use syn::{ImplItem, Item, ItemImpl, parse_quote};
+
+    // Implementations generated for the struct
+    fn expand_impls(input: DeriveInput) -> Vec<ItemImpl> {
+        let file: syn::File = syn::parse2(expand(input).unwrap()).unwrap();
+        file.items
+            .into_iter()
+            .map(|item| match item {
+                Item::Impl(item) => item,
+                _ => panic!("expected an impl block"),
+            })
+            .collect()
+    }
+
+    // Implementation of `Entry`
+    fn expand_impl(input: DeriveInput) -> ItemImpl {
+        expand_impls(input).remove(0)
+    }
+
+    fn trait_name(item: &ItemImpl) -> String {
+        item.trait_
+            .as_ref()
+            .unwrap()
+            .1
+            .to_token_stream()
+            .to_string()
+    }
+
+    // Body of the method `name` of the implementation
+    fn body(item: &ItemImpl, name: &str) -> String {
+        item.items
+            .iter()
+            .find_map(|item| match item {
+                ImplItem::Fn(method) if method.sig.ident == name => {
+                    Some(method.block.to_token_stream().to_string())
+                }
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("no method {}", name))
+    }
+
+    fn block(tokens: TokenStream2) -> String {
+        quote! { { #tokens } }.to_string()
+    }
+
+    fn error(input: DeriveInput) -> String {
+        match expand(input) {
+            Ok(_) => panic!("expected an error"),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn table_and_columns() {
+        let item = expand_impl(parse_quote! {
+            #[entry(table = "notes")]
+            struct Note {
+                #[entry(primary_key)]
+                id: Uuid,
+                title: String,
+                #[entry(skip_insert)]
+                slug: String,
+                #[entry(skip)]
+                cached: bool,
+                #[entry(timestamp = "created")]
+                created_at: DateTime<Utc>,
+                #[entry(timestamp = "updated")]
+                updated_at: DateTime<Utc>,
+            }
+        });
+
+        assert_eq!(
+            trait_name(&item),
+            quote! { crate::core::base::generic_repository::entry_trait::Entry }.to_string()
+        );
+        assert_eq!(body(&item, "table_name"), block(quote! { "notes" }));
+        assert_eq!(
+            body(&item, "columns"),
+            block(quote! { vec!["id", "title", "slug", "created_at", "updated_at"] })
+        );
+        assert_eq!(
+            body(&item, "insertable_columns"),
+            block(quote! { vec!["title"] })
+        );
+        assert_eq!(
+            body(&item, "set_created_at"),
+            block(quote! { self.created_at = created_at.into(); })
+        );
+        assert_eq!(body(&item, "soft_delete_column"), block(quote! { None }));
+        assert_eq!(body(&item, "version_column"), block(quote! { None }));
+        assert_eq!(body(&item, "tenant_column"), block(quote! { None }));
+    }
+
+    #[test]
+    fn soft_delete_version_and_tenant_columns() {
+        let item = expand_impl(parse_quote! {
+            #[entry(table = "comments")]
+            struct Comment {
+                #[entry(primary_key)]
+                id: i64,
+                #[entry(tenant)]
+                tenant_id: String,
+                #[entry(version)]
+                version: i64,
+                #[entry(timestamp = "deleted")]
+                deleted_at: Option<DateTime<Utc>>,
+            }
+        });
+
+        assert_eq!(
+            body(&item, "soft_delete_column"),
+            block(quote! { Some("deleted_at") })
+        );
+        assert_eq!(
+            body(&item, "version_column"),
+            block(quote! { Some("version") })
+        );
+        assert_eq!(
+            body(&item, "tenant_column"),
+            block(quote! { Some("tenant_id") })
+        );
+        // Without created_at and updated_at fields, the timestamps are ignored
+        assert_eq!(
+            body(&item, "set_updated_at"),
+            block(quote! { let _ = updated_at; })
+        );
+        // The version is left to the database default
+        assert_eq!(
+            body(&item, "insertable_columns"),
+            block(quote! { vec!["tenant_id"] })
+        );
+    }
+
+    #[test]
+    fn column_types_are_inferred_from_the_fields() {
+        let item = expand_impl(parse_quote! {
+            #[entry(table = "items")]
+            struct Item {
+                #[entry(primary_key)]
+                id: uuid::Uuid,
+                name: Option<String>,
+                count: i32,
+                tags: Vec<String>,
+                seen_at: Option<chrono::DateTime<chrono::Utc>>,
+                #[entry(sql_type = "numeric")]
+                price: String,
+                #[entry(enum_type = "item_status")]
+                status: Status,
+                other: Custom,
+            }
+        });
+        let column_type = quote! { crate::core::base::generic_repository::entry_trait::ColumnType };
+        let array_type = quote! { crate::core::base::query_builder::query_models::ArrayType };
+
+        assert_eq!(
+            body(&item, "column_type"),
+            block(quote! {
+                match column {
+                    "id" => Some(#column_type::Uuid),
+                    "name" => Some(#column_type::Text),
+                    "count" => Some(#column_type::Integer),
+                    "tags" => Some(#column_type::Array(#array_type::Text)),
+                    "seen_at" => Some(#column_type::Timestamp),
+                    "price" => Some(#column_type::Numeric),
+                    "status" => Some(#column_type::Enum("item_status")),
+                    _ => None,
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn key_and_timestamp_columns_follow_the_field_names() {
+        let item = expand_impl(parse_quote! {
+            #[entry(table = "accounts")]
+            struct Account {
+                #[entry(primary_key)]
+                account_id: i64,
+                #[entry(timestamp = "created")]
+                opened_at: DateTime<Utc>,
+                #[entry(timestamp = "updated")]
+                changed_at: DateTime<Utc>,
+            }
+        });
+
+        assert_eq!(
+            body(&item, "primary_key_column"),
+            block(quote! { "account_id" })
+        );
+        assert_eq!(
+            body(&item, "created_at_column"),
+            block(quote! { Some("opened_at") })
+        );
+        assert_eq!(
+            body(&item, "updated_at_column"),
+            block(quote! { Some("changed_at") })
+        );
+    }
+
+    #[test]
+    fn unsupported_integer_types_are_rejected() {
+        for ty in [
+            quote! { u32 },
+            quote! { Option<u64> },
+            quote! { i128 },
+            quote! { usize },
+        ] {
+            let input: DeriveInput = parse_quote! {
+                #[entry(table = "notes")]
+                struct Note {
+                    #[entry(primary_key)]
+                    id: i64,
+                    count: #ty,
+                }
+            };
+            assert!(error(input).contains("unsupported integer type"));
+        }
+    }
+
+    #[test]
+    fn relations_are_declared_on_the_struct() {
+        let item = expand_impl(parse_quote! {
+            #[entry(
+                table = "users",
+                has_many(name = "orders", entry = Order, foreign_key = "user_id"),
+                many_to_many(name = "roles", entry = Role, pivot = "user_roles", local_key = "user_id", foreign_key = "role_id")
+            )]
+            struct User {
+                #[entry(primary_key)]
+                id: Uuid,
+            }
+        });
+        let relation = quote! { crate::core::base::query_builder::relation::Relation };
+
+        assert_eq!(
+            body(&item, "relations"),
+            block(quote! {
+                vec![
+                    #relation::has_many::<Order>("orders", "user_id"),
+                    #relation::many_to_many::<Role>("roles", "user_roles", "user_id", "role_id")
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn crate_sets_the_root_of_the_generated_paths() {
+        let item = expand_impl(parse_quote! {
+            #[entry(table = "notes", crate = "my_api")]
+            struct Note {
+                #[entry(primary_key)]
+                id: i64,
+                tags: Vec<i64>,
+            }
+        });
+
+        assert_eq!(
+            trait_name(&item),
+            quote! { my_api::core::base::generic_repository::entry_trait::Entry }.to_string()
+        );
+        assert!(
+            body(&item, "column_type").contains(
+                &quote! { my_api::core::base::query_builder::query_models::ArrayType::BigInt }
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn hooks_are_left_to_the_struct() {
+        let without_hooks = expand_impls(parse_quote! {
+            #[entry(table = "notes")]
+            struct Note {
+                #[entry(primary_key)]
+                id: i64,
+            }
+        });
+        assert_eq!(without_hooks.len(), 2);
+        assert_eq!(
+            trait_name(&without_hooks[1]),
+            quote! { crate::core::base::generic_repository::entry_trait::EntryHooks }.to_string()
+        );
+        assert!(without_hooks[1].items.is_empty());
+
+        let with_hooks = expand_impls(parse_quote! {
+            #[entry(table = "notes", hooks)]
+            struct Note {
+                #[entry(primary_key)]
+                id: i64,
+            }
+        });
+        assert_eq!(with_hooks.len(), 1);
+        assert_eq!(
+            trait_name(&with_hooks[0]),
+            quote! { crate::core::base::generic_repository::entry_trait::Entry }.to_string()
+        );
+    }
+
+    #[test]
+    fn invalid_attributes_are_reported() {
+        assert!(
+            error(parse_quote! {
+                struct Note {
+                    #[entry(primary_key)]
+                    id: i64,
+                }
+            })
+            .contains("requires #[entry(table")
+        );
+        assert!(
+            error(parse_quote! {
+                #[entry(table = "notes")]
+                struct Note {
+                    id: i64,
+                }
+            })
+            .contains("requires one field marked #[entry(primary_key)]")
+        );
+        assert!(
+            error(parse_quote! {
+                #[entry(table = "notes")]
+                struct Note {
+                    #[entry(primary_key, skip)]
+                    id: i64,
+                }
+            })
+            .contains("cannot be combined")
+        );
+        assert!(
+            error(parse_quote! {
+                #[entry(table = "notes")]
+                struct Note {
+                    #[entry(primary_key)]
+                    id: i64,
+                    #[entry(timestamp = "removed")]
+                    removed_at: Option<DateTime<Utc>>,
+                }
+            })
+            .contains("expected \"created\", \"updated\" or \"deleted\"")
+        );
+        assert!(
+            error(parse_quote! {
+                #[entry(table = "notes", has_many(name = "tags", entry = Tag))]
+                struct Note {
+                    #[entry(primary_key)]
+                    id: i64,
+                }
+            })
+            .contains("`has_many` requires `foreign_key`")
+        );
+    }
+}
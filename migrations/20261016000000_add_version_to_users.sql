@@ -0,0 +1,2 @@
+-- Version de la ligne, incrémentée à chaque mise à jour (verrouillage optimiste)
+ALTER TABLE users ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 1;
@@ -0,0 +1,2 @@
+-- Tenant (client) de l'utilisateur, repris dans la claim tenant_id de ses jetons ; NULL hors multi-tenant
+ALTER TABLE users ADD COLUMN IF NOT EXISTS tenant_id VARCHAR(255);
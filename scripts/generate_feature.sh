@@ -160,14 +160,12 @@ mkdir -p "src/api/protected/$feature_snake"
 # Génération du contenu des champs pour le modèle
 model_fields=""
 new_fields=""
-columns_list=""
 field_assignments=""
 
 for field in "${fields[@]}"; do
     IFS=':' read -r field_name field_type <<< "$field"
     model_fields="$model_fields    pub $field_name: $field_type,\n"
     new_fields="$new_fields        $field_name,\n"
-    columns_list="$columns_list            \"$field_name\",\n"
     
     if [[ "$field_type" == "Option<"* ]]; then
         field_assignments="$field_assignments        $field_name: None,\n"
@@ -203,10 +201,14 @@ use sqlx::FromRow;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema, Entry)]
+#[entry(table = "$feature_plural")]
 pub struct $feature_pascal {
+    #[entry(primary_key)]
     pub id: Uuid,
-$(echo -e "$model_fields")    pub created_at: DateTime<Utc>,
+$(echo -e "$model_fields")    #[entry(timestamp = "created")]
+    pub created_at: DateTime<Utc>,
+    #[entry(timestamp = "updated")]
     pub updated_at: DateTime<Utc>,
 }
 
@@ -220,30 +222,6 @@ $(echo -e "$constructor_assignments")            created_at: now,
         }
     }
 }
-
-impl Entry for $feature_pascal {
-    type Id = Uuid;
-
-    fn set_created_at(&mut self, created_at: DateTime<Utc>) {
-        self.created_at = created_at;
-    }
-
-    fn set_updated_at(&mut self, updated_at: DateTime<Utc>) {
-        self.updated_at = updated_at;
-    }
-
-    fn table_name() -> &'static str {
-        "$feature_plural"
-    }
-
-    fn columns() -> Vec<&'static str> {
-        vec![
-            "id",
-$(echo -e "$columns_list")            "created_at",
-            "updated_at",
-        ]
-    }
-}
 EOF
 
 # 2. Génération du repository
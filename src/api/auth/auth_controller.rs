@@ -42,12 +42,14 @@ pub async fn register(
 
     let token = generate_jwt(
         sub.clone(),
+        created_user.tenant_id.clone(),
         config.jwt.secret.as_str(),
         config.jwt.expiration,
     );
 
     let refresh_token = generate_jwt(
         sub.clone(),
+        created_user.tenant_id.clone(),
         config.jwt.refresh_secret.as_str(),
         config.jwt.refresh_expiration,
     );
@@ -94,12 +96,14 @@ pub async fn login(
 
     let token = generate_jwt(
         sub.clone(),
+        authenticated_user.tenant_id.clone(),
         config.jwt.secret.as_str(),
         config.jwt.expiration,
     );
 
     let refresh_token = generate_jwt(
         sub.clone(),
+        authenticated_user.tenant_id.clone(),
         config.jwt.refresh_secret.as_str(),
         config.jwt.refresh_expiration,
     );
@@ -135,14 +139,17 @@ pub async fn refresh(
 
     let user = claims.user;
 
+    // The tenant given at login carries over to the new tokens
     let new_token = generate_jwt(
         user.clone(),
+        claims.tenant_id.clone(),
         config.jwt.secret.as_str(),
         config.jwt.expiration,
     );
 
     let new_refresh_token = generate_jwt(
         user,
+        claims.tenant_id,
         config.jwt.refresh_secret.as_str(),
         config.jwt.refresh_expiration,
     );
@@ -1,7 +1,12 @@
-use crate::{core::errors::errors::ErrorResponse, db::connection::check_connection};
+use crate::{
+    config::models::Repositories,
+    core::{base::generic_repository::cache::CacheStats, errors::errors::ErrorResponse},
+    db::connection::check_connection,
+};
 use actix_web::{Responder, get, web};
 use serde::Serialize;
 use sqlx::PgPool;
+use std::sync::Arc;
 
 #[derive(Serialize, utoipa::ToSchema)]
 pub struct HealthResponse {
@@ -9,6 +14,7 @@ pub struct HealthResponse {
     timestamp: String,
     version: String,
     database: bool,
+    user_cache: CacheStats,
 }
 
 #[utoipa::path(
@@ -21,7 +27,10 @@ pub struct HealthResponse {
     )
 )]
 #[get("")]
-pub async fn health_check(pool: web::Data<PgPool>) -> impl Responder {
+pub async fn health_check(
+    pool: web::Data<PgPool>,
+    repositories: web::Data<Arc<Repositories>>,
+) -> impl Responder {
     let db_status = check_connection(&pool).await.is_ok();
     let status = if db_status { "ok" } else { "error" };
 
@@ -30,5 +39,6 @@ pub async fn health_check(pool: web::Data<PgPool>) -> impl Responder {
         timestamp: chrono::Utc::now().to_rfc3339(),
         version: env!("CARGO_PKG_VERSION").to_string(),
         database: db_status,
+        user_cache: repositories.user_repository.stats(),
     })
 }
@@ -1,3 +1,4 @@
+use crate::core::middlewares::tenant::tenant_middleware;
 use crate::modules::auth::auth_middleware::auth_middleware;
 use actix_web::{middleware, web};
 
@@ -13,6 +14,8 @@ pub fn routes_config(cfg: &mut web::ServiceConfig) {
             .service(web::scope("/auth").configure(auth::routes_config))
             .service(
                 web::scope("/protected")
+                    // Registered last, auth_middleware runs first and provides the claims
+                    .wrap(middleware::from_fn(tenant_middleware))
                     .wrap(middleware::from_fn(auth_middleware))
                     .configure(protected::routes_config),
             ),
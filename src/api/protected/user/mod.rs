@@ -2,7 +2,12 @@ use actix_web::web;
 pub mod user_controller;
 
 pub fn routes_config(cfg: &mut web::ServiceConfig) {
+    // `/search`, `/signups` and `/export` are registered before `/{id}`, which would
+    // otherwise match them
     cfg.service(user_controller::get_users)
+        .service(user_controller::search_users)
+        .service(user_controller::get_signups)
+        .service(user_controller::export_users)
         .service(user_controller::get_user_by_id)
         .service(user_controller::create_user)
         .service(user_controller::update_user)
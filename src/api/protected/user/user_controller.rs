@@ -1,27 +1,134 @@
-use crate::config::models::Services;
+use crate::config::{config::Config, models::Services};
 use crate::core::errors::errors::{ApiError, ErrorResponse};
+use crate::core::utils::etag::{if_match_version, version_etag};
+use crate::core::utils::streaming::{csv_response, ndjson_response};
 use crate::modules::user::user_models::{
-    CreateUserRequest, UpdateUserRequest, UserIdPath, UserResponse,
+    CreateUserRequest, ExportFormat, SignupCountResponse, USER_EXPORT_COLUMNS, UpdateUserRequest,
+    UserDetailResponse, UserExportQuery, UserFilter, UserIdPath, UserListQuery, UserPageResponse,
+    UserResponse, UserSearchQuery, UserSearchResponse,
 };
+use actix_web::http::header::IfMatch;
 use actix_web::{HttpResponse, Responder, delete, get, post, put, web};
+use futures_util::StreamExt;
 use validator::Validate;
 
 #[utoipa::path(
     get,
     path = "/api/protected/user",
     tag = "users",
+    params(UserListQuery, UserFilter),
     responses(
-        (status = 200, description = "Liste des utilisateurs", body = Vec<UserResponse>),
+        (status = 200, description = "Page d'utilisateurs", body = UserPageResponse),
+        (status = 400, description = "Curseur, filtre, tri ou taille de page invalide", body = ErrorResponse),
         (status = 500, description = "Erreur interne du serveur", body = ErrorResponse)
     )
 )]
 #[get("")]
-pub async fn get_users(services: web::Data<Services>) -> Result<impl Responder, ApiError> {
-    let users = services.user_service.get_users().await?;
+pub async fn get_users(
+    services: web::Data<Services>,
+    config: web::Data<Config>,
+    query: web::Query<UserListQuery>,
+    filter: UserFilter,
+) -> Result<impl Responder, ApiError> {
+    // Validate input data
+    if let Err(e) = query.validate() {
+        return Err(ApiError::BadRequest(format!("{}", e)));
+    }
+
+    let page = services
+        .user_service
+        .get_users(
+            filter,
+            query.cursor.as_deref(),
+            config.pagination.cursor_secret.as_bytes(),
+        )
+        .await?;
+
+    Ok(web::Json(UserPageResponse::from(page)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/protected/user/search",
+    tag = "users",
+    params(UserSearchQuery),
+    responses(
+        (status = 200, description = "Utilisateurs trouvés, les plus pertinents d'abord", body = Vec<UserSearchResponse>),
+        (status = 400, description = "Recherche ou limite invalide", body = ErrorResponse),
+        (status = 500, description = "Erreur interne du serveur", body = ErrorResponse)
+    )
+)]
+#[get("/search")]
+pub async fn search_users(
+    services: web::Data<Services>,
+    query: web::Query<UserSearchQuery>,
+) -> Result<impl Responder, ApiError> {
+    if let Err(e) = query.validate() {
+        return Err(ApiError::BadRequest(format!("{}", e)));
+    }
+
+    let results = services
+        .user_service
+        .search_users(&query.q, query.limit.unwrap_or(20))
+        .await?;
 
-    let user_responses: Vec<UserResponse> = users.into_iter().map(|u| u.into()).collect();
+    Ok(web::Json(
+        results
+            .into_iter()
+            .map(UserSearchResponse::from)
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/protected/user/signups",
+    tag = "users",
+    responses(
+        (status = 200, description = "Nombre d'inscriptions par jour, du plus ancien au plus récent", body = Vec<SignupCountResponse>),
+        (status = 500, description = "Erreur interne du serveur", body = ErrorResponse)
+    )
+)]
+#[get("/signups")]
+pub async fn get_signups(services: web::Data<Services>) -> Result<impl Responder, ApiError> {
+    let signups = services.user_service.get_signups_per_day().await?;
+
+    Ok(web::Json(
+        signups
+            .into_iter()
+            .map(SignupCountResponse::from)
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/protected/user/export",
+    tag = "users",
+    params(UserExportQuery),
+    responses(
+        (status = 200, description = "Export de tous les utilisateurs, envoyé au fil de la lecture", content(
+            (Vec<UserResponse> = "application/x-ndjson"),
+            (String = "text/csv")
+        )),
+        (status = 400, description = "Format invalide", body = ErrorResponse),
+        (status = 500, description = "Erreur interne du serveur", body = ErrorResponse)
+    )
+)]
+#[get("/export")]
+pub async fn export_users(
+    services: web::Data<Services>,
+    query: web::Query<UserExportQuery>,
+) -> Result<impl Responder, ApiError> {
+    let users = services
+        .user_service
+        .export_users()
+        .map(|user| user.map(UserResponse::from));
 
-    Ok(web::Json(user_responses))
+    Ok(match query.into_inner().format.unwrap_or_default() {
+        ExportFormat::Ndjson => ndjson_response(users),
+        ExportFormat::Csv => csv_response(users, &USER_EXPORT_COLUMNS),
+    })
 }
 
 #[utoipa::path(
@@ -32,7 +139,9 @@ pub async fn get_users(services: web::Data<Services>) -> Result<impl Responder,
         ("id" = Uuid, Path, description = "ID de l'utilisateur")
     ),
     responses(
-        (status = 200, description = "Utilisateur trouvé", body = UserResponse),
+        (status = 200, description = "Utilisateur trouvé, avec ses rôles", body = UserDetailResponse, headers(
+            ("ETag" = String, description = "Version de l'utilisateur, à renvoyer dans If-Match")
+        )),
         (status = 404, description = "Utilisateur non trouvé", body = ErrorResponse),
         (status = 500, description = "Erreur interne du serveur", body = ErrorResponse)
     )
@@ -43,7 +152,9 @@ pub async fn get_user_by_id(
     services: web::Data<Services>,
 ) -> Result<impl Responder, ApiError> {
     let user = services.user_service.get_user_by_id(path.id).await?;
-    Ok(web::Json(UserResponse::from(user)))
+    Ok(HttpResponse::Ok()
+        .insert_header(version_etag(user.entry.version))
+        .json(UserDetailResponse::try_from(user)?))
 }
 
 #[utoipa::path(
@@ -52,7 +163,9 @@ pub async fn get_user_by_id(
     tag = "users",
     request_body = CreateUserRequest,
     responses(
-        (status = 201, description = "Utilisateur créé", body = UserResponse),
+        (status = 201, description = "Utilisateur créé", body = UserResponse, headers(
+            ("ETag" = String, description = "Version de l'utilisateur")
+        )),
         (status = 400, description = "Données invalides", body = ErrorResponse),
         (status = 409, description = "Email déjà utilisé", body = ErrorResponse),
         (status = 500, description = "Erreur interne du serveur", body = ErrorResponse)
@@ -70,7 +183,9 @@ pub async fn create_user(
 
     let user = services.user_service.create_user(user.into_inner()).await?;
 
-    Ok(HttpResponse::Created().json(UserResponse::from(user)))
+    Ok(HttpResponse::Created()
+        .insert_header(version_etag(user.version))
+        .json(UserResponse::from(user)))
 }
 
 #[utoipa::path(
@@ -78,14 +193,17 @@ pub async fn create_user(
     path = "/api/protected/user/{id}",
     tag = "users",
     params(
-        ("id" = Uuid, Path, description = "ID de l'utilisateur")
+        ("id" = Uuid, Path, description = "ID de l'utilisateur"),
+        ("If-Match" = Option<String>, Header, description = "ETag de la version modifiée ; la mise à jour est refusée si l'utilisateur a changé depuis")
     ),
     request_body = UpdateUserRequest,
     responses(
-        (status = 200, description = "Utilisateur mis à jour", body = UserResponse),
-        (status = 400, description = "Données invalides", body = ErrorResponse),
+        (status = 200, description = "Utilisateur mis à jour", body = UserResponse, headers(
+            ("ETag" = String, description = "Nouvelle version de l'utilisateur")
+        )),
+        (status = 400, description = "Données invalides, ou If-Match qui ne contient pas un seul ETag fort", body = ErrorResponse),
         (status = 404, description = "Utilisateur non trouvé", body = ErrorResponse),
-        (status = 409, description = "Email déjà utilisé", body = ErrorResponse),
+        (status = 409, description = "Email déjà utilisé, ou utilisateur modifié depuis la version de If-Match", body = ErrorResponse),
         (status = 500, description = "Erreur interne du serveur", body = ErrorResponse)
     )
 )]
@@ -93,6 +211,7 @@ pub async fn create_user(
 pub async fn update_user(
     services: web::Data<Services>,
     path: web::Path<UserIdPath>,
+    if_match: Option<web::Header<IfMatch>>,
     req: web::Json<UpdateUserRequest>,
 ) -> Result<impl Responder, ApiError> {
     // Validate input data
@@ -100,6 +219,8 @@ pub async fn update_user(
         return Err(ApiError::BadRequest(format!("{}", e)));
     }
 
+    let expected_version = if_match_version(if_match.as_deref())?;
+
     let user = services
         .user_service
         .update_user(
@@ -107,10 +228,13 @@ pub async fn update_user(
             req.username.clone(),
             req.email.clone(),
             req.password.clone(),
+            expected_version,
         )
         .await?;
 
-    Ok(web::Json(UserResponse::from(user)))
+    Ok(HttpResponse::Ok()
+        .insert_header(version_etag(user.version))
+        .json(UserResponse::from(user)))
 }
 
 #[utoipa::path(
@@ -3,9 +3,15 @@ use crate::api;
 use crate::api::health::health_controller::{HealthResponse};
 use crate::modules::user::user_models::{
     UserResponse, 
+    UserDetailResponse,
+    UserSearchResponse,
+    SignupCountResponse,
+    UserPageResponse,
     CreateUserRequest, 
-    UpdateUserRequest
+    UpdateUserRequest,
+    ExportFormat
 };
+use crate::core::base::generic_repository::cache::CacheStats;
 use crate::core::errors::errors::ErrorResponse;
 
 // OpenAPI configuration for the API
@@ -14,6 +20,9 @@ use crate::core::errors::errors::ErrorResponse;
     paths(
         api::health::health_controller::health_check,
         api::protected::user::user_controller::get_users,
+        api::protected::user::user_controller::search_users,
+        api::protected::user::user_controller::get_signups,
+        api::protected::user::user_controller::export_users,
         api::protected::user::user_controller::get_user_by_id,
         api::protected::user::user_controller::create_user,
         api::protected::user::user_controller::update_user,
@@ -22,9 +31,15 @@ use crate::core::errors::errors::ErrorResponse;
     components(
         schemas(
             UserResponse, 
+            UserDetailResponse,
+            UserSearchResponse,
+            SignupCountResponse,
+            UserPageResponse,
             CreateUserRequest,
             UpdateUserRequest,
+            ExportFormat,
             HealthResponse,
+            CacheStats,
             ErrorResponse
         )
     ),
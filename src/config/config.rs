@@ -1,13 +1,19 @@
 use std::env;
 
+use anyhow::Context;
 use serde::Deserialize;
 
-use crate::config::models::{DatabaseConfig, JwtConfig, ServerConfig};
+use crate::config::models::{
+    CacheConfig, DatabaseConfig, JwtConfig, PaginationConfig, ServerConfig, TenantConfig,
+};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
     pub server: ServerConfig,
     pub database: DatabaseConfig,
+    pub cache: CacheConfig,
+    pub tenant: TenantConfig,
+    pub pagination: PaginationConfig,
     pub jwt: JwtConfig,
 }
 
@@ -39,7 +45,54 @@ impl Config {
                 .unwrap_or_else(|_| "1800".to_string())
                 .parse::<u64>()
                 .unwrap_or(1800),
+            statement_timeout: env::var("DATABASE_STATEMENT_TIMEOUT")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse::<u64>()
+                .unwrap_or(30),
             url: env::var("DATABASE_URL").expect("DATABASE_URL doit être définie"),
+            log_statements: env::var("DATABASE_LOG_STATEMENTS")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            slow_query_threshold: env::var("DATABASE_SLOW_QUERY_THRESHOLD")
+                .unwrap_or_else(|_| "500".to_string())
+                .parse::<u64>()
+                .unwrap_or(500),
+            replica_urls: env::var("DATABASE_REPLICA_URLS")
+                .map(|v| {
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|url| !url.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            read_your_writes: env::var("DATABASE_READ_YOUR_WRITES")
+                .map(|v| v == "true")
+                .unwrap_or(true),
+        };
+
+        let cache = CacheConfig {
+            capacity: env::var("CACHE_CAPACITY")
+                .unwrap_or_else(|_| "10000".to_string())
+                .parse::<usize>()
+                .unwrap_or(10000),
+            ttl: env::var("CACHE_TTL")
+                .unwrap_or_else(|_| "60".to_string())
+                .parse::<u64>()
+                .unwrap_or(60),
+        };
+
+        let tenant = TenantConfig {
+            header: env::var("TENANT_HEADER").ok().filter(|v| !v.is_empty()),
+        };
+
+        let pagination = PaginationConfig {
+            cursor_secret: env::var("CURSOR_SECRET")
+                .ok()
+                .filter(|v| !v.is_empty())
+                .context(
+                    "CURSOR_SECRET doit être définie (clé de signature des curseurs de pagination)",
+                )?,
         };
 
         let jwt = JwtConfig {
@@ -59,6 +112,9 @@ impl Config {
         Ok(Config {
             server,
             database,
+            cache,
+            tenant,
+            pagination,
             jwt,
         })
     }
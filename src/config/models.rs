@@ -1,7 +1,11 @@
 use serde::Deserialize;
 
 use crate::{
-    db::repositories::user_repository::UserRepository,
+    core::{
+        base::generic_repository::{pools::DbPools, unit_of_work::UnitOfWork},
+        errors::errors::ApiError,
+    },
+    db::repositories::user_repository::CachedUserRepository,
     modules::{auth::auth_service::AuthService, user::user_service::UserService},
 };
 
@@ -15,10 +19,31 @@ pub struct ServerConfig {
 #[derive(Debug, Deserialize, Clone)]
 pub struct DatabaseConfig {
     pub max_connections: u32,
-    pub acquire_timeout: u64, // seconds
-    pub idle_timeout: u64,    // seconds
-    pub max_lifetime: u64,    // seconds
+    pub acquire_timeout: u64,   // seconds
+    pub idle_timeout: u64,      // seconds
+    pub max_lifetime: u64,      // seconds
+    pub statement_timeout: u64, // seconds, 0 disables it
     pub url: String,
+    pub log_statements: bool, // debug log of generated SQL, development only
+    pub slow_query_threshold: u64, // milliseconds, warn log of slower statements, 0 disables it
+    pub replica_urls: Vec<String>, // read replicas, reads go to the primary when empty
+    pub read_your_writes: bool, // reads of a request go to the primary once it has written
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CacheConfig {
+    pub capacity: usize, // cached entries per repository, 0 disables the cache
+    pub ttl: u64,        // seconds
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TenantConfig {
+    pub header: Option<String>, // header naming the tenant of requests without a tenant claim
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PaginationConfig {
+    pub cursor_secret: String, // signs the pagination cursors, distinct from the JWT secrets
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -37,5 +62,17 @@ pub struct Services {
 
 #[derive(Clone)]
 pub struct Repositories {
-    pub user_repository: UserRepository,
+    pub pools: DbPools,
+    pub user_repository: CachedUserRepository,
+}
+
+impl Repositories {
+    /// Runs several repository calls atomically: commits when `work` returns `Ok`,
+    /// rolls back on `Err`. See `UnitOfWork`. Runs on the primary.
+    pub async fn transaction<F, R>(&self, work: F) -> Result<R, ApiError>
+    where
+        F: AsyncFnOnce(&mut UnitOfWork<'_>) -> Result<R, ApiError>,
+    {
+        UnitOfWork::run(self.pools.primary(), work).await
+    }
 }
@@ -1,14 +1,20 @@
 use super::config::Config;
 use crate::api::swagger::ApiDoc;
 use crate::config::models::{Repositories, Services};
+use crate::core::base::generic_repository::cached_repository::CachedRepository;
+use crate::core::base::generic_repository::pools::DbPools;
+use crate::core::base::query_builder::sql_writer::set_statement_logging;
+use crate::core::base::query_builder::trace::set_slow_query_threshold;
 use crate::core::middlewares::logger::logger_middleware;
 use crate::core::middlewares::rate_limiter::{RateLimiterConfig, rate_limiter_middleware};
+use crate::core::middlewares::read_your_writes::read_your_writes_middleware;
 use crate::modules::auth::auth_service::AuthService;
 use crate::modules::user::user_service::UserService;
 use crate::{api, db::repositories::user_repository::UserRepository};
 use actix_cors::Cors;
 use actix_web::{App, HttpServer, middleware, web};
-use sqlx::postgres::PgPoolOptions;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{Connection, PgConnection};
 use std::sync::Arc;
 use std::time::Duration;
 use utoipa::OpenApi;
@@ -28,21 +34,44 @@ impl Server {
         let config = self.config.clone();
         println!("Starting server with configuration: {:#?}", config);
 
-        // Database connection pool configuration
-        let pool = PgPoolOptions::new()
+        // Database connection pool configuration, shared by the primary and the replicas
+        let pool_options = PgPoolOptions::new()
             .max_connections(config.database.max_connections)
             .acquire_timeout(Duration::from_secs(config.database.acquire_timeout))
             .idle_timeout(Duration::from_secs(config.database.idle_timeout))
-            .max_lifetime(Duration::from_secs(config.database.max_lifetime))
-            .connect(&config.database.url)
+            .max_lifetime(Duration::from_secs(config.database.max_lifetime));
+        let statement_timeout = Duration::from_secs(config.database.statement_timeout);
+        let pool = pool_options
+            .clone()
+            .connect_with(connect_options(&config.database.url, statement_timeout))
             .await
             .expect("Cannot connect to the database");
 
-        // Run migrations
+        let mut replicas = Vec::with_capacity(config.database.replica_urls.len());
+        for url in &config.database.replica_urls {
+            replicas.push(
+                pool_options
+                    .clone()
+                    .connect_with(connect_options(url, statement_timeout))
+                    .await
+                    .expect("Cannot connect to a database replica"),
+            );
+        }
+        let pools = DbPools::new(pool.clone(), replicas);
+
+        // Run migrations, on a connection of their own: they are not bound by the
+        // statement timeout
+        let mut migration_conn = PgConnection::connect(&config.database.url)
+            .await
+            .expect("Cannot connect to the database");
         sqlx::migrate!("./migrations")
-            .run(&pool)
+            .run(&mut migration_conn)
             .await
             .expect("Failed to run migrations");
+        let _ = migration_conn.close().await;
+
+        set_statement_logging(config.database.log_statements);
+        set_slow_query_threshold(Duration::from_millis(config.database.slow_query_threshold));
 
         // Initialize Logger
         tracing_subscriber::fmt()
@@ -65,10 +94,16 @@ impl Server {
         // Store configuration values in variables before the config is moved into the closure
         let host = config.server.host.clone();
         let port = config.server.port;
+        let read_your_writes = config.database.read_your_writes;
 
         // Create repositories
         let repositories = Arc::new(Repositories {
-            user_repository: UserRepository::new(pool.clone()),
+            pools: pools.clone(),
+            user_repository: CachedRepository::in_memory(
+                UserRepository::new(pools),
+                config.cache.capacity,
+                Duration::from_secs(config.cache.ttl),
+            ),
         });
 
         // Create services
@@ -96,6 +131,10 @@ impl Server {
 
             App::new()
                 .wrap(cors)
+                .wrap(middleware::Condition::new(
+                    read_your_writes,
+                    middleware::from_fn(read_your_writes_middleware),
+                ))
                 .wrap(middleware::from_fn(rate_limiter_middleware))
                 .wrap(middleware::from_fn(logger_middleware))
                 .app_data(web::Data::new(rate_limit_config.clone()))
@@ -116,3 +155,12 @@ impl Server {
         // todo: add a default service for undefined routes
     }
 }
+
+// Connection options setting the server-side statement timeout of every session
+fn connect_options(url: &str, statement_timeout: Duration) -> PgConnectOptions {
+    let options: PgConnectOptions = url.parse().expect("Invalid database URL");
+    options.options([(
+        "statement_timeout",
+        format!("{}ms", statement_timeout.as_millis()),
+    )])
+}
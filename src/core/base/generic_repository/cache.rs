@@ -0,0 +1,194 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// Storage of the entries cached by `CachedRepository`, as JSON values under string keys.
+///
+/// Implement it to share the cache between instances (e.g. Redis). A backend that fails
+/// should behave as a miss: the repository then reads from the database.
+///
+/// The futures are `Send`, so that invalidations can be deferred until a transaction ends
+/// (see `CachedRepository`); `async fn` can still be used to implement the methods.
+pub trait CacheBackend: Send + Sync + 'static {
+    fn get(&self, key: &str) -> impl Future<Output = Option<Value>> + Send;
+
+    fn set(&self, key: &str, value: Value, ttl: Duration) -> impl Future<Output = ()> + Send;
+
+    fn remove(&self, key: &str) -> impl Future<Output = ()> + Send;
+
+    /// Removes every key starting with `prefix`.
+    fn remove_prefix(&self, prefix: &str) -> impl Future<Output = ()> + Send;
+}
+
+/// In-process cache bounded by a number of entries: past `capacity`, the least recently
+/// used entry is evicted. A capacity of 0 disables caching.
+#[derive(Debug)]
+pub struct MemoryCache {
+    capacity: usize,
+    state: Mutex<LruState>,
+}
+
+#[derive(Debug, Default)]
+struct LruState {
+    slots: HashMap<String, Slot>,
+    // Keys by last use, oldest first
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+#[derive(Debug)]
+struct Slot {
+    value: Value,
+    expires_at: Instant,
+    tick: u64,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(LruState::default()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, LruState> {
+        // The state stays consistent even if a holder panicked
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl LruState {
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(slot) = self.slots.get_mut(key) {
+            self.recency.remove(&slot.tick);
+            slot.tick = tick;
+            self.recency.insert(tick, key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(slot) = self.slots.remove(key) {
+            self.recency.remove(&slot.tick);
+        }
+    }
+}
+
+impl CacheBackend for MemoryCache {
+    async fn get(&self, key: &str) -> Option<Value> {
+        let mut state = self.lock();
+        let slot = state.slots.get(key)?;
+        if slot.expires_at <= Instant::now() {
+            state.remove(key);
+            return None;
+        }
+        let value = slot.value.clone();
+        state.touch(key);
+        Some(value)
+    }
+
+    async fn set(&self, key: &str, value: Value, ttl: Duration) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut state = self.lock();
+        state.remove(key);
+        state.tick += 1;
+        let tick = state.tick;
+        state.slots.insert(
+            key.to_string(),
+            Slot {
+                value,
+                expires_at: Instant::now() + ttl,
+                tick,
+            },
+        );
+        state.recency.insert(tick, key.to_string());
+
+        while state.slots.len() > self.capacity {
+            let Some((_, oldest)) = state.recency.pop_first() else {
+                break;
+            };
+            state.slots.remove(&oldest);
+        }
+    }
+
+    async fn remove(&self, key: &str) {
+        self.lock().remove(key);
+    }
+
+    async fn remove_prefix(&self, prefix: &str) {
+        let mut state = self.lock();
+        let keys: Vec<String> = state
+            .slots
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+        for key in keys {
+            state.remove(&key);
+        }
+    }
+}
+
+/// Hit and miss counters of a cache.
+#[derive(Debug, Default)]
+pub struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+impl CacheCounters {
+    pub fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn invalidation(&self) {
+        self.invalidations.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Number of invalidations so far, compared before and after a cache fill.
+    pub fn invalidations(&self) -> u64 {
+        self.invalidations.load(Ordering::SeqCst)
+    }
+
+    pub fn snapshot(&self) -> CacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+        CacheStats {
+            hits,
+            misses,
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+            hit_ratio: if lookups == 0 {
+                0.0
+            } else {
+                hits as f64 / lookups as f64
+            },
+        }
+    }
+}
+
+/// Counters of a `CachedRepository` since startup.
+#[derive(Debug, Clone, Copy, Serialize, utoipa::ToSchema)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub invalidations: u64,
+    pub hit_ratio: f64,
+}
@@ -0,0 +1,608 @@
+use std::{marker::PhantomData, sync::Arc, time::Duration};
+
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{Acquire, Executor, FromRow, Pool, Postgres, TransactionManager};
+use tracing::{Span, field, instrument};
+
+use crate::core::{
+    base::{
+        generic_repository::{
+            cache::{CacheBackend, CacheCounters, CacheStats, MemoryCache},
+            entry_trait::{Entry, EntryHooks},
+            repository_trait::{RepositoryResult, RepositoryTrait},
+            tenant::TenantScope,
+            unit_of_work::{self, on_completion},
+        },
+        query_builder::{backend::Backend, query_builder::QueryBuilderUtil},
+    },
+    errors::errors::ApiError,
+};
+
+/// Read-through cache around a repository.
+///
+/// `find_by_id`, `find_by_id_required` and `find_by_unique` read entries from the cache
+/// `C` before the database. Every write of the trait (`create`, `update`,
+/// `update_partial`, `delete`, `delete_many`, upserts, restores and their `*_in`
+/// variants) invalidates the entries it touches; `delete_by_query` clears the entry's
+/// whole namespace. Entries expire after `ttl`, which bounds how long a write made
+/// elsewhere (raw SQL, another service) can go unnoticed.
+///
+/// Cache misses are read from the primary, as a replica may not have replayed a write
+/// yet when its invalidation runs; hits spare both.
+///
+/// A read between a write and its commit would cache the row as it was: `*_in` writes
+/// made in a `UnitOfWork` are invalidated again once it ends, and `*_in` writes on a
+/// connection in any other transaction are refused, as the cache cannot know when they
+/// commit. A read racing an invalidation does not fill the cache.
+///
+/// Entries of tenant tables (`Entry::tenant_column`) are cached per tenant. Outside of a
+/// tenant scope (admin jobs), reads bypass the cache and writes clear the whole namespace.
+///
+/// The other methods run the trait's default implementation on the inner repository's
+/// pool; overrides of the inner repository are only kept for the methods above.
+pub struct CachedRepository<R, T, DB = Postgres, C = MemoryCache> {
+    inner: R,
+    cache: Arc<C>,
+    ttl: Duration,
+    counters: Arc<CacheCounters>,
+    _phantom: PhantomData<fn() -> (T, DB)>,
+}
+
+impl<R: Clone, T, DB, C> Clone for CachedRepository<R, T, DB, C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            cache: Arc::clone(&self.cache),
+            ttl: self.ttl,
+            counters: Arc::clone(&self.counters),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<R, T: Entry, DB> CachedRepository<R, T, DB, MemoryCache> {
+    /// Caches up to `capacity` entries in process, for `ttl` each.
+    pub fn in_memory(inner: R, capacity: usize, ttl: Duration) -> Self {
+        Self::new(inner, Arc::new(MemoryCache::new(capacity)), ttl)
+    }
+}
+
+impl<R, T: Entry, DB, C> CachedRepository<R, T, DB, C> {
+    /// Caches entries in `cache`, which may be shared with other repositories: keys are
+    /// prefixed by the entry's table.
+    pub fn new(inner: R, cache: Arc<C>, ttl: Duration) -> Self {
+        Self {
+            inner,
+            cache,
+            ttl,
+            counters: Arc::new(CacheCounters::default()),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// The wrapped repository. Writes made through it do not invalidate the cache.
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.counters.snapshot()
+    }
+
+    // None when the entry must not be cached: a tenant table outside of a tenant scope
+    fn key(column: &str, value: &Value) -> Option<String> {
+        if T::tenant_column().is_none() {
+            return Some(format!("{}:{}:{}", T::table_name(), column, value));
+        }
+        match TenantScope::current() {
+            Some(TenantScope::Tenant(tenant)) => Some(format!(
+                "{}:{}:{}:{}",
+                T::table_name(),
+                tenant.id,
+                column,
+                value
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl<R, T, DB, C> CachedRepository<R, T, DB, C>
+where
+    R: RepositoryTrait<T, DB> + Sync,
+    T: EntryHooks + Send + Sync + Unpin + 'static + for<'r> FromRow<'r, DB::Row>,
+    DB: Backend,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    C: CacheBackend,
+{
+    /// Finds the entry whose `column`, holding unique values (e.g. an email), equals
+    /// `value`. The value is cached as a pointer to the entry's id, so that writes by id
+    /// invalidate it as well.
+    #[instrument(skip_all, fields(table = T::table_name(), cache = field::Empty))]
+    pub async fn find_by_unique<V>(&self, column: &str, value: V) -> RepositoryResult<Option<T>>
+    where
+        V: Send + Sync + Serialize,
+    {
+        let value = serde_json::to_value(value).map_err(ApiError::Serialization)?;
+        let lookup_key = Self::key(column, &value);
+
+        if let Some(lookup_key) = &lookup_key
+            && let Some(id) = self.cache.get(lookup_key).await
+            && let Some(entry_key) = Self::key(T::primary_key_column(), &id)
+            && let Some(entry) = self.cache.get(&entry_key).await
+            // The entry may have changed its value since the lookup was cached
+            && entry.get(column) == Some(&value)
+            && let Ok(entry) = serde_json::from_value(entry)
+        {
+            self.counters.hit();
+            Span::current().record("cache", "hit");
+            return Ok(Some(entry));
+        }
+        self.counters.miss();
+        Span::current().record("cache", "miss");
+
+        // A replica may lag behind the writes invalidated: fills read the primary
+        let invalidations = self.counters.invalidations();
+        let entry = self
+            .inner
+            .find_by_column_in(self.inner.get_pool(), column, value)
+            .await?
+            .into_iter()
+            .next();
+        if let Some(entry) = &entry
+            && let Some(lookup_key) = &lookup_key
+            && let Some(id) = self.store(entry, invalidations).await
+        {
+            self.cache.set(lookup_key, id, self.ttl).await;
+        }
+        Ok(entry)
+    }
+
+    /// Drops the cached entry with the given id, and again once the current unit of
+    /// work ends, if any.
+    pub async fn invalidate(&self, id: T::Id) {
+        if let Ok(id) = serde_json::to_value(id) {
+            self.invalidate_write(id).await;
+        }
+    }
+
+    /// Drops every cached entry of this entry type.
+    pub async fn invalidate_all(&self) {
+        self.counters.invalidation();
+        self.cache
+            .remove_prefix(&format!("{}:", T::table_name()))
+            .await;
+    }
+
+    async fn invalidate_key(&self, id: &Value) {
+        // An unscoped write may have touched the entry of any tenant
+        let Some(key) = Self::key(T::primary_key_column(), id) else {
+            return self.invalidate_all().await;
+        };
+        self.counters.invalidation();
+        self.cache.remove(&key).await;
+    }
+
+    // Invalidates the entry written with the given id and, in a unit of work, once it
+    // ends: until the commit, reads see the row as it was and may have cached it again
+    async fn invalidate_write(&self, id: Value) {
+        self.invalidate_key(&id).await;
+
+        let cache = Arc::clone(&self.cache);
+        let counters = Arc::clone(&self.counters);
+        let key = Self::key(T::primary_key_column(), &id);
+        on_completion(Box::pin(async move {
+            counters.invalidation();
+            match key {
+                Some(key) => cache.remove(&key).await,
+                None => cache.remove_prefix(&format!("{}:", T::table_name())).await,
+            }
+        }));
+    }
+
+    async fn invalidate_entry(&self, entry: &T) {
+        if let Ok(entry) = serde_json::to_value(entry)
+            && let Some(id) = entry.get(T::primary_key_column())
+        {
+            self.invalidate_write(id.clone()).await;
+        }
+    }
+
+    // Writes on a connection in a transaction are only invalidated after its commit
+    // within a unit of work
+    fn check_transaction(conn: &DB::Connection) -> RepositoryResult<()> {
+        if DB::TransactionManager::get_transaction_depth(conn) > 0
+            && !unit_of_work::in_unit_of_work()
+        {
+            return Err(ApiError::InvalidQuery(format!(
+                "Writes to the cached {} in a transaction must run in a UnitOfWork",
+                T::table_name()
+            )));
+        }
+        Ok(())
+    }
+
+    async fn cached(&self, id: T::Id) -> Option<T> {
+        let id = serde_json::to_value(id).ok()?;
+        let entry = self
+            .cache
+            .get(&Self::key(T::primary_key_column(), &id)?)
+            .await?;
+        serde_json::from_value(entry).ok()
+    }
+
+    // Caches the entry under its id, which is returned, unless an invalidation ran since
+    // `invalidations` was read, before reading the entry: it may predate the write
+    // invalidated. Checked again after storing, as the invalidation may run in between.
+    async fn store(&self, entry: &T, invalidations: u64) -> Option<Value> {
+        let entry = serde_json::to_value(entry).ok()?;
+        let id = entry.get(T::primary_key_column())?.clone();
+        let key = Self::key(T::primary_key_column(), &id)?;
+        if self.counters.invalidations() != invalidations {
+            return None;
+        }
+        self.cache.set(&key, entry, self.ttl).await;
+        if self.counters.invalidations() != invalidations {
+            self.cache.remove(&key).await;
+            return None;
+        }
+        Some(id)
+    }
+}
+
+impl<R, T, DB, C> RepositoryTrait<T, DB> for CachedRepository<R, T, DB, C>
+where
+    R: RepositoryTrait<T, DB> + Sync,
+    T: EntryHooks + Send + Sync + Unpin + 'static + for<'r> FromRow<'r, DB::Row>,
+    DB: Backend,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB> + Acquire<'c, Database = DB>,
+    C: CacheBackend,
+{
+    fn get_pool(&self) -> &Pool<DB> {
+        self.inner.get_pool()
+    }
+
+    fn get_read_pool(&self) -> &Pool<DB> {
+        self.inner.get_read_pool()
+    }
+
+    #[instrument(skip_all, fields(table = T::table_name(), cache = field::Empty))]
+    async fn find_by_id(&self, id: T::Id) -> RepositoryResult<Option<T>> {
+        if let Some(entry) = self.cached(id).await {
+            self.counters.hit();
+            Span::current().record("cache", "hit");
+            return Ok(Some(entry));
+        }
+        self.counters.miss();
+        Span::current().record("cache", "miss");
+
+        let invalidations = self.counters.invalidations();
+        let entry = self.inner.find_by_id_in(self.inner.get_pool(), id).await?;
+        if let Some(entry) = &entry {
+            self.store(entry, invalidations).await;
+        }
+        Ok(entry)
+    }
+
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    async fn find_by_id_required(&self, id: T::Id) -> RepositoryResult<T> {
+        self.find_by_id(id)
+            .await?
+            .ok_or_else(|| ApiError::NotFound(format!("{:?}", id)))
+    }
+
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    async fn create_in<'c, A>(&self, conn: A, entry: T) -> RepositoryResult<T>
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        let mut conn = conn.acquire().await?;
+        Self::check_transaction(&conn)?;
+        let created = self.inner.create_in(&mut *conn, entry).await?;
+        self.invalidate_entry(&created).await;
+        Ok(created)
+    }
+
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    async fn create_many_in(
+        &self,
+        conn: &mut DB::Connection,
+        entries: Vec<T>,
+    ) -> RepositoryResult<Vec<T>> {
+        Self::check_transaction(conn)?;
+        let created = self.inner.create_many_in(conn, entries).await?;
+        for entry in &created {
+            self.invalidate_entry(entry).await;
+        }
+        Ok(created)
+    }
+
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    async fn upsert_in<'c, A>(
+        &self,
+        conn: A,
+        entry: T,
+        conflict_columns: &[&str],
+        update_columns: &[&str],
+    ) -> RepositoryResult<T>
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        let mut conn = conn.acquire().await?;
+        Self::check_transaction(&conn)?;
+        let upserted = self
+            .inner
+            .upsert_in(&mut *conn, entry, conflict_columns, update_columns)
+            .await?;
+        self.invalidate_entry(&upserted).await;
+        Ok(upserted)
+    }
+
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    async fn upsert_many_in(
+        &self,
+        conn: &mut DB::Connection,
+        entries: Vec<T>,
+        conflict_columns: &[&str],
+        update_columns: &[&str],
+    ) -> RepositoryResult<Vec<T>> {
+        Self::check_transaction(conn)?;
+        let upserted = self
+            .inner
+            .upsert_many_in(conn, entries, conflict_columns, update_columns)
+            .await?;
+        for entry in &upserted {
+            self.invalidate_entry(entry).await;
+        }
+        Ok(upserted)
+    }
+
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    async fn create_if_absent_in<'c, A>(
+        &self,
+        conn: A,
+        entry: T,
+        conflict_columns: &[&str],
+    ) -> RepositoryResult<Option<T>>
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        let mut conn = conn.acquire().await?;
+        Self::check_transaction(&conn)?;
+        let created = self
+            .inner
+            .create_if_absent_in(&mut *conn, entry, conflict_columns)
+            .await?;
+        if let Some(entry) = &created {
+            self.invalidate_entry(entry).await;
+        }
+        Ok(created)
+    }
+
+    // Writes by id invalidate the entry whether or not they succeed: a failed update
+    // (e.g. a version conflict) means the cached copy may be outdated.
+
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    async fn update_in<'c, A>(&self, conn: A, id: T::Id, entry: T) -> RepositoryResult<T>
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        let mut conn = conn.acquire().await?;
+        Self::check_transaction(&conn)?;
+        let result = self.inner.update_in(&mut *conn, id, entry).await;
+        self.invalidate(id).await;
+        result
+    }
+
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    async fn update_partial_in<'c, A>(
+        &self,
+        conn: A,
+        id: T::Id,
+        version: Option<i64>,
+        updates: Vec<(String, Value)>,
+    ) -> RepositoryResult<T>
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        let mut conn = conn.acquire().await?;
+        Self::check_transaction(&conn)?;
+        let result = self
+            .inner
+            .update_partial_in(&mut *conn, id, version, updates)
+            .await;
+        self.invalidate(id).await;
+        result
+    }
+
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    async fn delete_in<'c, A>(&self, conn: A, id: T::Id) -> RepositoryResult<bool>
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        let mut conn = conn.acquire().await?;
+        Self::check_transaction(&conn)?;
+        let result = self.inner.delete_in(&mut *conn, id).await;
+        self.invalidate(id).await;
+        result
+    }
+
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    async fn delete_many_in<'c, A>(&self, conn: A, ids: &[T::Id]) -> RepositoryResult<u64>
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        let mut conn = conn.acquire().await?;
+        Self::check_transaction(&conn)?;
+        let result = self.inner.delete_many_in(&mut *conn, ids).await;
+        for id in ids {
+            self.invalidate(*id).await;
+        }
+        result
+    }
+
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    async fn restore_in<'c, A>(&self, conn: A, id: T::Id) -> RepositoryResult<bool>
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        let mut conn = conn.acquire().await?;
+        Self::check_transaction(&conn)?;
+        let result = self.inner.restore_in(&mut *conn, id).await;
+        self.invalidate(id).await;
+        result
+    }
+
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    async fn force_delete_in<'c, A>(&self, conn: A, id: T::Id) -> RepositoryResult<bool>
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        let mut conn = conn.acquire().await?;
+        Self::check_transaction(&conn)?;
+        let result = self.inner.force_delete_in(&mut *conn, id).await;
+        self.invalidate(id).await;
+        result
+    }
+
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    async fn delete_by_query(&self, query: QueryBuilderUtil<T, DB>) -> RepositoryResult<u64> {
+        let result = self.inner.delete_by_query(query).await;
+        self.invalidate_all().await;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use sqlx::Sqlite;
+
+    use super::*;
+    use crate::core::base::generic_repository::{
+        pools::{DbPools, read_your_writes},
+        test_support::{Note, NoteRepository, note_repository, shared_note_repository},
+        unit_of_work::UnitOfWork,
+    };
+
+    #[tokio::test]
+    async fn misses_are_read_from_the_primary() {
+        let primary = note_repository().await;
+        let replica = note_repository().await;
+        let note = primary.create(Note::new("new", "1")).await.unwrap();
+        replica.create(Note::new("old", "1")).await.unwrap();
+        let repository = NoteRepository {
+            pool: primary.pool,
+            read_pool: Some(replica.pool),
+        };
+        let read = repository.find_by_id(note.id).await.unwrap().unwrap();
+        assert_eq!(read.title, "old");
+
+        let cached = CachedRepository::in_memory(repository, 16, Duration::from_secs(60));
+        let read = cached.find_by_id(note.id).await.unwrap().unwrap();
+        assert_eq!(read.title, "new");
+        cached.invalidate_all().await;
+        let read = cached.find_by_unique("title", "new").await.unwrap();
+        assert_eq!(read.map(|note| note.id), Some(note.id));
+        assert_eq!(cached.stats().misses, 2);
+    }
+
+    /// Notes on `DbPools`, as the application repositories are.
+    struct PooledNoteRepository {
+        pools: DbPools<Sqlite>,
+    }
+
+    impl RepositoryTrait<Note, Sqlite> for PooledNoteRepository {
+        fn get_pool(&self) -> &Pool<Sqlite> {
+            self.pools.primary()
+        }
+
+        fn get_read_pool(&self) -> &Pool<Sqlite> {
+            self.pools.read()
+        }
+    }
+
+    #[tokio::test]
+    async fn misses_do_not_pin_the_reads_to_the_primary() {
+        let primary = note_repository().await;
+        let replica = note_repository().await;
+        let note = primary.create(Note::new("new", "1")).await.unwrap();
+        replica.create(Note::new("old", "1")).await.unwrap();
+        let pools = DbPools::new(primary.pool, vec![replica.pool]);
+        let cached = CachedRepository::in_memory(
+            PooledNoteRepository {
+                pools: pools.clone(),
+            },
+            16,
+            Duration::from_secs(60),
+        );
+
+        read_your_writes(async {
+            let read = cached.find_by_id(note.id).await.unwrap().unwrap();
+            assert_eq!(read.title, "new");
+            let read = cached.inner().find_by_id(note.id).await.unwrap().unwrap();
+            assert_eq!(read.title, "old");
+
+            cached
+                .update_partial(note.id, Some(note.version), retitle("newer"))
+                .await
+                .unwrap();
+            let read = cached.inner().find_by_id(note.id).await.unwrap().unwrap();
+            assert_eq!(read.title, "newer");
+        })
+        .await;
+    }
+
+    fn retitle(title: &str) -> Vec<(String, Value)> {
+        vec![("title".to_string(), json!(title))]
+    }
+
+    #[tokio::test]
+    async fn a_read_before_the_commit_of_a_write_is_not_kept() {
+        let (repository, _dir) = shared_note_repository().await;
+        let cached = CachedRepository::in_memory(repository.clone(), 16, Duration::from_secs(60));
+        let note = cached.create(Note::new("a", "1")).await.unwrap();
+        cached.find_by_id(note.id).await.unwrap();
+
+        UnitOfWork::run(&repository.pool, async |uow| {
+            cached
+                .update_partial_in(uow.conn(), note.id, Some(note.version), retitle("b"))
+                .await?;
+            // Reads the committed row on another connection and caches it
+            let read = cached.find_by_id(note.id).await?.unwrap();
+            assert_eq!(read.title, "a");
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let read = cached.find_by_id(note.id).await.unwrap().unwrap();
+        assert_eq!(read.title, "b");
+    }
+
+    #[tokio::test]
+    async fn a_write_in_another_transaction_is_refused() {
+        let (repository, _dir) = shared_note_repository().await;
+        let cached = CachedRepository::in_memory(repository.clone(), 16, Duration::from_secs(60));
+        let note = cached.create(Note::new("a", "1")).await.unwrap();
+
+        let mut tx = repository.pool.begin().await.unwrap();
+        let result = cached
+            .update_partial_in(&mut *tx, note.id, Some(note.version), retitle("b"))
+            .await;
+        assert!(matches!(result, Err(ApiError::InvalidQuery(_))));
+        tx.rollback().await.unwrap();
+
+        let written = cached
+            .update_partial_in(&repository.pool, note.id, Some(note.version), retitle("b"))
+            .await
+            .unwrap();
+        assert_eq!(written.title, "b");
+        assert_eq!(
+            cached.find_by_id(note.id).await.unwrap().unwrap().title,
+            "b"
+        );
+    }
+}
@@ -1,13 +1,52 @@
-use std::fmt::Debug;
+use std::{collections::HashMap, fmt::Debug};
 
 use chrono::{DateTime, Utc};
 use serde::{Serialize, de::DeserializeOwned};
-use sqlx::{FromRow, postgres::PgRow};
+use serde_json::Value;
+
+use crate::core::{
+    base::query_builder::{query_models::ArrayType, relation::Relation},
+    errors::errors::ApiError,
+};
+
+pub use entry_derive::Entry;
+
+/// SQL type of a column, used to bind the values compared with or written to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Text,
+    /// Any integer column (`smallint`, `integer`, `bigint`)
+    Integer,
+    /// `real` or `double precision`
+    Float,
+    /// Arbitrary precision `numeric`, bound as text and cast by Postgres
+    Numeric,
+    Boolean,
+    Uuid,
+    /// `timestamptz` (or `timestamp`)
+    Timestamp,
+    Date,
+    Json,
+    /// Postgres enum type with the given name, bound as text and cast
+    Enum(&'static str),
+    Array(ArrayType),
+}
+
+impl ColumnType {
+    /// Type the parameter is cast to on Postgres, for types sqlx binds as text.
+    pub fn postgres_cast(&self) -> Option<&'static str> {
+        match self {
+            ColumnType::Numeric => Some("NUMERIC"),
+            ColumnType::Enum(type_name) => Some(type_name),
+            _ => None,
+        }
+    }
+}
 
 /// The `Entry` trait defines a common interface for database entities.
 ///
 /// Types implementing this trait must support serialization, deserialization,
-/// SQL row mapping, and thread safety. The trait provides methods for accessing
+/// and thread safety. The trait provides methods for accessing
 /// and modifying the entity's unique identifier, creation and update timestamps,
 /// as well as static methods for retrieving the table name and column names.
 ///
@@ -19,18 +58,29 @@ use sqlx::{FromRow, postgres::PgRow};
 /// - `set_updated_at(&mut self, updated_at: DateTime<Utc>)`: Sets the last update timestamp.
 /// - `table_name() -> &'static str`: Returns the database table name for the entity.
 /// - `columns() -> Vec<&'static str>`: Returns the list of column names for the entity.
-/// - `insertable_columns() -> Vec<&'static str>`: Returns the list of columns that can be used for insertion, excluding autogenerated colums (by default id).
-pub trait Entry:
-    for<'r> FromRow<'r, PgRow> + Send + Sync + Serialize + DeserializeOwned + std::fmt::Debug
-{
-    type Id: Send
-        + Sync
-        + Copy
-        + 'static
-        + sqlx::Type<sqlx::Postgres>
-        + sqlx::Encode<'static, sqlx::Postgres>
-        + Debug
-        + serde::Serialize;
+/// - `primary_key_column() -> &'static str`: Returns the primary key column (`id` by default).
+/// - `created_at_column() -> Option<&'static str>` / `updated_at_column() -> Option<&'static str>`: Return the creation and update timestamp columns, if any (by default `created_at` and `updated_at` when they are columns).
+/// - `insertable_columns() -> Vec<&'static str>`: Returns the list of columns that can be used for insertion, excluding autogenerated colums (by default the primary key and timestamps).
+/// - `soft_delete_column() -> Option<&'static str>`: Returns the nullable timestamp column marking deleted rows, if the entity is soft-deleted (none by default).
+/// - `version_column() -> Option<&'static str>`: Returns the integer column used for optimistic concurrency control, if any (none by default).
+/// - `tenant_column() -> Option<&'static str>`: Returns the column holding the tenant owning each row, if the table is shared between tenants (none by default). Queries are then restricted to the tenant in scope (see `generic_repository::tenant`).
+/// - `column_type(column: &str) -> Option<ColumnType>`: Returns the SQL type of a column, with which the query builder binds its values (none by default).
+/// - `relations() -> Vec<Relation>`: Returns the relations to other entries that `QueryBuilderUtil::with` can load (none by default).
+///
+/// The lifecycle hooks run by the repository writes are declared by `EntryHooks`.
+///
+/// Values of columns without a type are bound from their JSON form: strings as text,
+/// numbers and booleans natively. A string is never reinterpreted (e.g. as a UUID), so
+/// UUID, timestamp or enum columns need a type to be compared or written.
+///
+/// Reading rows also requires `sqlx::FromRow` for the row type of the database in use
+/// (`#[derive(sqlx::FromRow)]`), which `QueryBuilderUtil` and `RepositoryTrait` ask for
+/// where they fetch entries.
+///
+/// Prefer `#[derive(Entry)]` over a hand-written implementation so that the column
+/// lists always match the struct fields (see the `entry_derive` crate for the attributes).
+pub trait Entry: Send + Sync + Serialize + DeserializeOwned + std::fmt::Debug {
+    type Id: Send + Sync + Copy + 'static + Debug + serde::Serialize;
 
     fn set_created_at(&mut self, created_at: DateTime<Utc>);
 
@@ -40,10 +90,93 @@ pub trait Entry:
 
     fn columns() -> Vec<&'static str>;
 
+    fn primary_key_column() -> &'static str {
+        "id"
+    }
+
+    fn created_at_column() -> Option<&'static str> {
+        Self::columns()
+            .contains(&"created_at")
+            .then_some("created_at")
+    }
+
+    fn updated_at_column() -> Option<&'static str> {
+        Self::columns()
+            .contains(&"updated_at")
+            .then_some("updated_at")
+    }
+
     fn insertable_columns() -> Vec<&'static str> {
+        let generated = [
+            Some(Self::primary_key_column()),
+            Self::created_at_column(),
+            Self::updated_at_column(),
+        ];
         Self::columns()
             .into_iter()
-            .filter(|col| *col != "id" && *col != "created_at" && *col != "updated_at")
+            .filter(|col| !generated.contains(&Some(*col)))
             .collect()
     }
+
+    fn soft_delete_column() -> Option<&'static str> {
+        None
+    }
+
+    fn version_column() -> Option<&'static str> {
+        None
+    }
+
+    fn tenant_column() -> Option<&'static str> {
+        None
+    }
+
+    fn column_type(_column: &str) -> Option<ColumnType> {
+        None
+    }
+
+    fn relations() -> Vec<Relation> {
+        Vec::new()
+    }
+}
+
+/// Lifecycle hooks of an entry, called by every write of `RepositoryTrait` (no-ops by
+/// default). A hook returning an error aborts the write: the `before_*` hooks run before
+/// the statement, the `after_*` hooks in the same transaction, which is then rolled back.
+/// - `before_insert(&mut self)`: Before `create*`, `upsert*` and `create_if_absent*`, once the timestamps are set. Can normalise the entry.
+/// - `after_insert(&self)`: With each row inserted (or updated by an upsert).
+/// - `before_update(id, changes: &mut HashMap<String, Value>)`: Before `update*` and `update_partial*`, with the column values about to be written, which it can modify.
+/// - `after_update(&self)`: With the updated row.
+/// - `before_delete(&self)`: Before `delete*`, `force_delete*` and `delete_by_query`, with each row to delete, locked until the end of the deletion.
+/// - `after_delete(&self)`: With each deleted row, as it was before the deletion.
+///
+/// Bulk statements of `QueryBuilderUtil` (`update`, `delete`...) do not run the hooks.
+/// `#[derive(Entry)]` implements the trait with the no-ops; declare `#[entry(hooks)]` to
+/// implement it yourself. Implementations can use `async fn`, whose futures must be `Send`.
+pub trait EntryHooks: Entry {
+    fn before_insert(&mut self) -> impl Future<Output = Result<(), ApiError>> + Send {
+        async { Ok(()) }
+    }
+
+    fn after_insert(&self) -> impl Future<Output = Result<(), ApiError>> + Send {
+        async { Ok(()) }
+    }
+
+    fn before_update(
+        _id: Self::Id,
+        _changes: &mut HashMap<String, Value>,
+    ) -> impl Future<Output = Result<(), ApiError>> + Send {
+        async { Ok(()) }
+    }
+
+    fn after_update(&self) -> impl Future<Output = Result<(), ApiError>> + Send {
+        async { Ok(()) }
+    }
+
+    fn before_delete(&self) -> impl Future<Output = Result<(), ApiError>> + Send {
+        async { Ok(()) }
+    }
+
+    fn after_delete(&self) -> impl Future<Output = Result<(), ApiError>> + Send {
+        async { Ok(()) }
+    }
 }
@@ -1,2 +1,9 @@
+pub mod cache;
+pub mod cached_repository;
 pub mod entry_trait;
+pub mod pools;
 pub mod repository_trait;
+pub mod tenant;
+#[cfg(test)]
+pub mod test_support;
+pub mod unit_of_work;
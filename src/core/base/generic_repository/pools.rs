@@ -0,0 +1,84 @@
+use std::{
+    cell::Cell,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use sqlx::{Database, Pool, Postgres};
+
+tokio::task_local! {
+    static PINNED_TO_PRIMARY: Cell<bool>;
+}
+
+/// The primary pool of a database and the pools of its read replicas.
+///
+/// Writes and transactions run on `primary()`; reads run on `read()`, which takes the
+/// replicas in turn. Replication is asynchronous: a read on a replica may miss a write
+/// made just before. Inside `read_your_writes`, reads following a write go to the
+/// primary as well.
+#[derive(Debug)]
+pub struct DbPools<DB: Database = Postgres> {
+    primary: Pool<DB>,
+    replicas: Arc<[Pool<DB>]>,
+    next_replica: Arc<AtomicUsize>,
+}
+
+impl<DB: Database> Clone for DbPools<DB> {
+    fn clone(&self) -> Self {
+        Self {
+            primary: self.primary.clone(),
+            replicas: Arc::clone(&self.replicas),
+            next_replica: Arc::clone(&self.next_replica),
+        }
+    }
+}
+
+impl<DB: Database> From<Pool<DB>> for DbPools<DB> {
+    fn from(primary: Pool<DB>) -> Self {
+        Self::new(primary, Vec::new())
+    }
+}
+
+impl<DB: Database> DbPools<DB> {
+    pub fn new(primary: Pool<DB>, replicas: Vec<Pool<DB>>) -> Self {
+        Self {
+            primary,
+            replicas: replicas.into(),
+            next_replica: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Pool for writes and transactions, and for the reads that must not lag behind
+    /// them. Writes call `pin_to_primary` so that the reads following them use it too.
+    pub fn primary(&self) -> &Pool<DB> {
+        &self.primary
+    }
+
+    /// Pool for reads: the next replica, or the primary when there is none or the current
+    /// `read_your_writes` scope has used the primary.
+    pub fn read(&self) -> &Pool<DB> {
+        let pinned = PINNED_TO_PRIMARY
+            .try_with(|pinned| pinned.get())
+            .unwrap_or(false);
+        if pinned || self.replicas.is_empty() {
+            return &self.primary;
+        }
+        let index = self.next_replica.fetch_add(1, Ordering::Relaxed) % self.replicas.len();
+        &self.replicas[index]
+    }
+}
+
+/// Makes `DbPools::read` return the primary for the rest of the current
+/// `read_your_writes` scope, if any. Called by the writes.
+pub fn pin_to_primary() {
+    let _ = PINNED_TO_PRIMARY.try_with(|pinned| pinned.set(true));
+}
+
+/// Runs `future` (typically a request) in a scope where, once a write has called
+/// `pin_to_primary`, `DbPools::read` returns the primary, so that the scope reads its own
+/// writes despite the replication lag.
+pub async fn read_your_writes<F: Future>(future: F) -> F::Output {
+    PINNED_TO_PRIMARY.scope(Cell::new(false), future).await
+}
@@ -1,145 +1,277 @@
 use std::collections::HashMap;
 
 use crate::core::{
-    base::query_builder::{query_builder::QueryBuilderUtil, query_models::OrderDirection},
+    base::query_builder::{
+        backend::Backend,
+        cursor::{Cursor, CursorPage},
+        query_builder::QueryBuilderUtil,
+        query_filter::{FilterSpec, QueryFilter},
+        query_models::{OrderDirection, TextSearch},
+        search::SearchResult,
+        sql_writer::ConnectionSource,
+    },
     errors::errors::ApiError,
 };
 
-use super::entry_trait::Entry;
+use super::entry_trait::{Entry, EntryHooks};
+use super::pools::pin_to_primary;
+use futures_util::Stream;
 use serde_json::Value;
-use sqlx::{Pool, Postgres, QueryBuilder};
+use sqlx::{Acquire, Connection, Executor, FromRow, Pool, Postgres, QueryBuilder};
+use tracing::instrument;
 
 pub type RepositoryResult<T> = Result<T, ApiError>;
 
-pub trait RepositoryTrait<T: Entry + Send + Sync + Unpin + 'static> {
-    /// Returns a reference to the Postgres connection pool.
-    fn get_pool(&self) -> &Pool<Postgres>;
+/// CRUD and query methods of the repository of an entry, on the database `DB`
+/// (Postgres by default; MySQL and SQLite with the `mysql` and `sqlite` features).
+///
+/// Each async method runs in a span named after it, with the entry's table; the spans
+/// of the statements it runs (`QueryTrace`) are nested under it.
+///
+/// Writes run the lifecycle hooks of the entry (`EntryHooks::before_insert`...) in a
+/// transaction, which an error of a hook rolls back.
+pub trait RepositoryTrait<T, DB = Postgres>: Sync
+where
+    T: EntryHooks + Send + Sync + Unpin + 'static + for<'r> FromRow<'r, DB::Row>,
+    DB: Backend,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+{
+    /// Returns a reference to the connection pool, used by writes and transactions.
+    fn get_pool(&self) -> &Pool<DB>;
+
+    /// Returns the pool reads run on, a read replica when the repository has some
+    /// (see `DbPools`). Defaults to `get_pool()`.
+    fn get_read_pool(&self) -> &Pool<DB> {
+        self.get_pool()
+    }
 
     /// Creates a new QueryBuilderUtil instance for building queries.
-    fn query(&self) -> QueryBuilderUtil<T> {
+    fn query(&self) -> QueryBuilderUtil<T, DB> {
         QueryBuilderUtil::new()
     }
 
-    fn build_query(&self, query:String) -> RepositoryResult<QueryBuilder<'_, Postgres>>{
+    fn build_query(&self, query: String) -> RepositoryResult<QueryBuilder<'_, DB>> {
         Ok(QueryBuilder::new(query))
     }
 
     /// Fetches all records of type T from the database.
-    async fn find_all(&self) -> RepositoryResult<Vec<T>> {
-        self.query().fetch_all(self.get_pool()).await
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn find_all(&self) -> impl Future<Output = RepositoryResult<Vec<T>>> + Send {
+        async move { self.query().fetch_all(self.get_read_pool()).await }
     }
 
-    /// Finds a record by its primary key (id). Returns an Option<T>.
-    async fn find_by_id(&self, id: T::Id) -> RepositoryResult<Option<T>> {
+    /// Streams all records of type T, for exports too large to load with `find_all`.
+    /// The stream holds its own handle to the pool, so it can be used as a response body.
+    fn stream_all(&self) -> impl Stream<Item = RepositoryResult<T>> + Send + 'static {
         self.query()
-            .where_eq(
-                "id",
-                serde_json::to_value(id).map_err(|e| ApiError::Serialization(e))?,
-            )?
-            .fetch_optional(self.get_pool())
-            .await
+            .fetch_stream_owned(self.get_read_pool().clone())
+    }
+
+    /// Finds a record by its primary key (id). Returns an Option<T>.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn find_by_id(&self, id: T::Id) -> impl Future<Output = RepositoryResult<Option<T>>> + Send {
+        async move { self.find_by_id_in(self.get_read_pool(), id).await }
+    }
+
+    /// Same as `find_by_id`, using the given executor (e.g. a transaction).
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn find_by_id_in<'c, E>(
+        &self,
+        executor: E,
+        id: T::Id,
+    ) -> impl Future<Output = RepositoryResult<Option<T>>> + Send
+    where
+        E: ConnectionSource<'c, DB>,
+    {
+        async move {
+            self.query()
+                .where_eq(
+                    T::primary_key_column(),
+                    serde_json::to_value(id).map_err(ApiError::Serialization)?,
+                )?
+                .fetch_optional(executor)
+                .await
+        }
     }
 
     /// Finds a record by its primary key (id). Returns an error if not found.
-    async fn find_by_id_required(&self, id: T::Id) -> RepositoryResult<T> {
-        self.query()
-            .where_eq(
-                "id",
-                serde_json::to_value(id).map_err(|e| ApiError::Serialization(e))?,
-            )?
-            .fetch_one(self.get_pool())
-            .await
-            .map_err(|e| match e {
-                ApiError::NotFound(_) => ApiError::NotFound(format!("{:?}", id)),
-                _ => e,
-            })
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn find_by_id_required(&self, id: T::Id) -> impl Future<Output = RepositoryResult<T>> + Send {
+        async move {
+            self.query()
+                .where_eq(
+                    T::primary_key_column(),
+                    serde_json::to_value(id).map_err(ApiError::Serialization)?,
+                )?
+                .fetch_one(self.get_read_pool())
+                .await
+                .map_err(|e| match e {
+                    ApiError::NotFound(_) => ApiError::NotFound(format!("{:?}", id)),
+                    _ => e,
+                })
+        }
     }
 
     /// Finds records by a specific column and value.
-    async fn find_by_column<V>(&self, column: &str, value: V) -> RepositoryResult<Vec<T>>
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn find_by_column<V>(
+        &self,
+        column: &str,
+        value: V,
+    ) -> impl Future<Output = RepositoryResult<Vec<T>>> + Send
     where
         V: Send + Sync + serde::Serialize,
     {
-        let json_value = serde_json::to_value(value).map_err(|e| ApiError::Serialization(e))?;
+        async move {
+            self.find_by_column_in(self.get_read_pool(), column, value)
+                .await
+        }
+    }
 
-        self.query()
-            .where_eq(column, json_value)?
-            .fetch_all(self.get_pool())
-            .await
+    /// Same as `find_by_column`, using the given executor.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn find_by_column_in<'c, E, V>(
+        &self,
+        executor: E,
+        column: &str,
+        value: V,
+    ) -> impl Future<Output = RepositoryResult<Vec<T>>> + Send
+    where
+        E: ConnectionSource<'c, DB>,
+        V: Send + Sync + serde::Serialize,
+    {
+        async move {
+            let json_value = serde_json::to_value(value).map_err(ApiError::Serialization)?;
+
+            self.query()
+                .where_eq(column, json_value)?
+                .fetch_all(executor)
+                .await
+        }
     }
 
     /// Finds records matching a set of criteria (column, value pairs).
-    async fn find_by_criteria(&self, criteria: &[(&str, Value)]) -> RepositoryResult<Vec<T>> {
-        if criteria.is_empty() {
-            return self.find_all().await;
-        }
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn find_by_criteria(
+        &self,
+        criteria: &[(&str, Value)],
+    ) -> impl Future<Output = RepositoryResult<Vec<T>>> + Send {
+        async move {
+            if criteria.is_empty() {
+                return self.find_all().await;
+            }
 
-        let mut query = self.query();
+            let mut query = self.query();
 
-        for (i, (column, value)) in criteria.iter().enumerate() {
-            query = query.where_eq(column, value.clone())?;
-            if i < criteria.len() - 1 {
-                query = query.and();
+            for (i, (column, value)) in criteria.iter().enumerate() {
+                query = query.where_eq(column, value.clone())?;
+                if i < criteria.len() - 1 {
+                    query = query.and();
+                }
             }
-        }
 
-        query.fetch_all(self.get_pool()).await
+            query.fetch_all(self.get_read_pool()).await
+        }
     }
 
     /// Counts the total number of records of type T.
-    async fn count(&self) -> RepositoryResult<i64> {
-        self.query().count(self.get_pool()).await
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn count(&self) -> impl Future<Output = RepositoryResult<i64>> + Send {
+        async move { self.query().count(self.get_read_pool()).await }
     }
 
     /// Fetches a paginated list of records, ordered by id ascending.
-    async fn paginate(&self, page: u32, page_size: u32) -> RepositoryResult<Vec<T>> {
-        self.query()
-            .order_by("id", OrderDirection::Asc)?
-            .paginate(page, page_size)
-            .fetch_all(self.get_pool())
-            .await
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn paginate(
+        &self,
+        page: u32,
+        page_size: u32,
+    ) -> impl Future<Output = RepositoryResult<Vec<T>>> + Send {
+        async move {
+            self.query()
+                .order_by(T::primary_key_column(), OrderDirection::Asc)?
+                .paginate(page, page_size)
+                .fetch_all(self.get_read_pool())
+                .await
+        }
     }
 
-    /// Creates a new record in the database and returns it.
-    async fn create(&self, mut entry: T) -> RepositoryResult<T> {
-        use chrono::Utc;
-
-        let columns = T::insertable_columns();
-        let now = Utc::now();
-        entry.set_created_at(now);
-        entry.set_updated_at(now);
-
-        let entry_json = serde_json::to_value(&entry).map_err(|e| ApiError::Serialization(e))?;
-
-        let mut insert_data = HashMap::new();
-        for col in &columns {
-            let value = entry_json.get(*col).cloned().unwrap_or(Value::Null);
-            insert_data.insert(col.to_string(), value);
+    /// Fetches a page of records using keyset (cursor) pagination on `sort_columns` plus id.
+    /// `cursor` is a token from a previous page's `next_cursor`/`prev_cursor`, signed with `secret`.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn paginate_cursor(
+        &self,
+        sort_columns: &[&str],
+        direction: OrderDirection,
+        cursor: Option<&str>,
+        limit: u32,
+        secret: &[u8],
+    ) -> impl Future<Output = RepositoryResult<CursorPage<T>>> + Send {
+        async move {
+            let cursor = cursor
+                .map(|token| Cursor::decode(token, secret))
+                .transpose()?;
+
+            self.query()
+                .cursor_paginate(sort_columns, direction, cursor, limit)?
+                .fetch_cursor_page(self.get_read_pool(), secret)
+                .await
         }
+    }
 
-        self.query()
-            .values(insert_data)?
-            .insert_returning(self.get_pool())
-            .await
+    /// Fetches a page of the records matching a query-string filter, with keyset
+    /// pagination on its sort order. `cursor` works as in `paginate_cursor`.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn paginate_filtered<S>(
+        &self,
+        filter: QueryFilter<S, DB>,
+        cursor: Option<&str>,
+        secret: &[u8],
+    ) -> impl Future<Output = RepositoryResult<CursorPage<T>>> + Send
+    where
+        S: FilterSpec<Entry = T>,
+    {
+        async move {
+            let cursor = cursor
+                .map(|token| Cursor::decode(token, secret))
+                .transpose()?;
+
+            filter
+                .into_cursor_query(cursor)?
+                .fetch_cursor_page(self.get_read_pool(), secret)
+                .await
+        }
     }
 
-    /// Creates multiple records in the database and returns them.
-    async fn create_many(&self, entries: Vec<T>) -> RepositoryResult<Vec<T>> {
-        if entries.is_empty() {
-            return Ok(vec![]);
+    /// Creates a new record in the database and returns it.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn create(&self, entry: T) -> impl Future<Output = RepositoryResult<T>> + Send {
+        async move {
+            pin_to_primary();
+            self.create_in(self.get_pool(), entry).await
         }
+    }
 
-        use chrono::Utc;
-        let columns = T::insertable_columns();
-        let now = Utc::now();
-        let mut results = Vec::new();
+    /// Same as `create`, on the given pool, connection or transaction.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn create_in<'c, A>(
+        &self,
+        conn: A,
+        mut entry: T,
+    ) -> impl Future<Output = RepositoryResult<T>> + Send
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        async move {
+            use chrono::Utc;
 
-        for mut entry in entries {
+            let columns = T::insertable_columns();
+            let now = Utc::now();
             entry.set_created_at(now);
             entry.set_updated_at(now);
+            entry.before_insert().await?;
 
-            let entry_json =
-                serde_json::to_value(&entry).map_err(|e| ApiError::Serialization(e))?;
+            let entry_json = serde_json::to_value(&entry).map_err(ApiError::Serialization)?;
 
             let mut insert_data = HashMap::new();
             for col in &columns {
@@ -147,282 +279,1383 @@ pub trait RepositoryTrait<T: Entry + Send + Sync + Unpin + 'static> {
                 insert_data.insert(col.to_string(), value);
             }
 
-            let created_entry = self
+            let query = self.query().values(insert_data)?;
+
+            let mut conn = conn.acquire().await?;
+            let mut tx = conn.begin().await?;
+            let created = query.insert_returning_on(&mut tx).await?;
+            created.after_insert().await?;
+            tx.commit().await?;
+
+            Ok(created)
+        }
+    }
+
+    /// Creates multiple records in the database and returns them.
+    ///
+    /// The whole batch is inserted atomically in one transaction, see
+    /// `QueryBuilderUtil::insert_many_returning`.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn create_many(
+        &self,
+        entries: Vec<T>,
+    ) -> impl Future<Output = RepositoryResult<Vec<T>>> + Send {
+        async move {
+            pin_to_primary();
+            let mut conn = self.get_pool().acquire().await?;
+            self.create_many_in(&mut conn, entries).await
+        }
+    }
+
+    /// Same as `create_many`, on the given connection. Inside a transaction the batch
+    /// runs in a savepoint.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn create_many_in(
+        &self,
+        conn: &mut DB::Connection,
+        entries: Vec<T>,
+    ) -> impl Future<Output = RepositoryResult<Vec<T>>> + Send {
+        async move {
+            if entries.is_empty() {
+                return Ok(vec![]);
+            }
+
+            let now = chrono::Utc::now();
+            let mut rows = Vec::with_capacity(entries.len());
+            for mut entry in entries {
+                entry.set_created_at(now);
+                entry.set_updated_at(now);
+                entry.before_insert().await?;
+                rows.push(insert_data(&entry)?);
+            }
+
+            let query = self.query().values_many(rows)?;
+
+            let mut tx = conn.begin().await?;
+            let results = query.insert_many_returning(&mut tx).await?;
+            for created in &results {
+                created.after_insert().await?;
+            }
+            tx.commit().await?;
+
+            Ok(results)
+        }
+    }
+
+    /// Inserts a record, or updates `update_columns` of the row that conflicts on
+    /// `conflict_columns` (INSERT ... ON CONFLICT DO UPDATE), and returns the resulting row.
+    /// With no `update_columns`, the existing row is returned unchanged.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn upsert(
+        &self,
+        entry: T,
+        conflict_columns: &[&str],
+        update_columns: &[&str],
+    ) -> impl Future<Output = RepositoryResult<T>> + Send {
+        async move {
+            pin_to_primary();
+            self.upsert_in(self.get_pool(), entry, conflict_columns, update_columns)
+                .await
+        }
+    }
+
+    /// Same as `upsert`, on the given pool, connection or transaction.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn upsert_in<'c, A>(
+        &self,
+        conn: A,
+        mut entry: T,
+        conflict_columns: &[&str],
+        update_columns: &[&str],
+    ) -> impl Future<Output = RepositoryResult<T>> + Send
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        async move {
+            let now = chrono::Utc::now();
+            entry.set_created_at(now);
+            entry.set_updated_at(now);
+            entry.before_insert().await?;
+
+            // A no-op assignment still lets RETURNING yield the existing row
+            let update_columns = match update_columns {
+                [] => &conflict_columns[..conflict_columns.len().min(1)],
+                columns => columns,
+            };
+
+            let query = self
                 .query()
-                .values(insert_data)?
-                .insert_returning(self.get_pool())
-                .await?;
+                .values(insert_data(&entry)?)?
+                .on_conflict(conflict_columns.iter().copied())?
+                .do_update(update_columns.iter().copied())?;
 
-            results.push(created_entry);
+            let mut conn = conn.acquire().await?;
+            let mut tx = conn.begin().await?;
+            let upserted = query.insert_returning_on(&mut tx).await?;
+            upserted.after_insert().await?;
+            tx.commit().await?;
+
+            Ok(upserted)
         }
+    }
 
-        Ok(results)
+    /// Upserts multiple records atomically and returns the resulting rows.
+    /// Two entries of the batch must not conflict with each other.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn upsert_many(
+        &self,
+        entries: Vec<T>,
+        conflict_columns: &[&str],
+        update_columns: &[&str],
+    ) -> impl Future<Output = RepositoryResult<Vec<T>>> + Send {
+        async move {
+            pin_to_primary();
+            let mut conn = self.get_pool().acquire().await?;
+            self.upsert_many_in(&mut conn, entries, conflict_columns, update_columns)
+                .await
+        }
     }
 
-    /// Updates a record by its id with the provided entry data.
-    async fn update(&self, id: T::Id, mut entry: T) -> RepositoryResult<T> {
-        use chrono::Utc;
+    /// Same as `upsert_many`, on the given connection.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn upsert_many_in(
+        &self,
+        conn: &mut DB::Connection,
+        entries: Vec<T>,
+        conflict_columns: &[&str],
+        update_columns: &[&str],
+    ) -> impl Future<Output = RepositoryResult<Vec<T>>> + Send {
+        async move {
+            if entries.is_empty() {
+                return Ok(vec![]);
+            }
 
-        let columns = T::columns();
-        let now = Utc::now();
-        entry.set_updated_at(now);
+            let now = chrono::Utc::now();
+            let mut rows = Vec::with_capacity(entries.len());
+            for mut entry in entries {
+                entry.set_created_at(now);
+                entry.set_updated_at(now);
+                entry.before_insert().await?;
+                rows.push(insert_data(&entry)?);
+            }
 
-        let entry_json = serde_json::to_value(&entry).map_err(|e| ApiError::Serialization(e))?;
+            let update_columns = match update_columns {
+                [] => &conflict_columns[..conflict_columns.len().min(1)],
+                columns => columns,
+            };
+            let query = self
+                .query()
+                .values_many(rows)?
+                .on_conflict(conflict_columns.iter().copied())?
+                .do_update(update_columns.iter().copied())?;
+
+            let mut tx = conn.begin().await?;
+            let results = query.insert_many_returning(&mut tx).await?;
+            for upserted in &results {
+                upserted.after_insert().await?;
+            }
+            tx.commit().await?;
 
-        let mut update_data = HashMap::new();
-        for col in &columns {
-            if *col == "id" || *col == "created_at" {
-                continue;
+            Ok(results)
+        }
+    }
+
+    /// Inserts a record unless it conflicts on `conflict_columns` (ON CONFLICT DO NOTHING).
+    /// Returns None when a conflicting row already exists.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn create_if_absent(
+        &self,
+        entry: T,
+        conflict_columns: &[&str],
+    ) -> impl Future<Output = RepositoryResult<Option<T>>> + Send {
+        async move {
+            pin_to_primary();
+            self.create_if_absent_in(self.get_pool(), entry, conflict_columns)
+                .await
+        }
+    }
+
+    /// Same as `create_if_absent`, on the given pool, connection or transaction.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn create_if_absent_in<'c, A>(
+        &self,
+        conn: A,
+        mut entry: T,
+        conflict_columns: &[&str],
+    ) -> impl Future<Output = RepositoryResult<Option<T>>> + Send
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        async move {
+            let now = chrono::Utc::now();
+            entry.set_created_at(now);
+            entry.set_updated_at(now);
+            entry.before_insert().await?;
+
+            let query = self
+                .query()
+                .values(insert_data(&entry)?)?
+                .on_conflict(conflict_columns.iter().copied())?
+                .do_nothing();
+
+            let mut conn = conn.acquire().await?;
+            let mut tx = conn.begin().await?;
+            let created = query.insert_returning_optional_on(&mut tx).await?;
+            if let Some(created) = &created {
+                created.after_insert().await?;
             }
-            let value = entry_json.get(*col).cloned().unwrap_or(Value::Null);
-            update_data.insert(col.to_string(), value);
+            tx.commit().await?;
+
+            Ok(created)
         }
+    }
 
-        let updated_entries = self
-            .query()
-            .where_eq(
-                "id",
-                serde_json::to_value(id).map_err(|e| ApiError::Serialization(e))?,
-            )?
-            .set_multiple(update_data)?
-            .update_returning(self.get_pool())
-            .await?;
+    /// Updates a record by its id with the provided entry data.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn update(&self, id: T::Id, entry: T) -> impl Future<Output = RepositoryResult<T>> + Send {
+        async move {
+            pin_to_primary();
+            self.update_in(self.get_pool(), id, entry).await
+        }
+    }
+
+    /// Same as `update`, on the given pool, connection or transaction.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn update_in<'c, A>(
+        &self,
+        conn: A,
+        id: T::Id,
+        mut entry: T,
+    ) -> impl Future<Output = RepositoryResult<T>> + Send
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        async move {
+            use chrono::Utc;
+
+            let columns = T::columns();
+            let now = Utc::now();
+            entry.set_updated_at(now);
 
-        updated_entries
-            .into_iter()
-            .next()
-            .ok_or_else(|| ApiError::NotFound(format!("No record found with id: {:?}", id)))
+            let entry_json = serde_json::to_value(&entry).map_err(ApiError::Serialization)?;
+
+            let mut update_data = HashMap::new();
+            for col in &columns {
+                if *col == T::primary_key_column()
+                    || Some(*col) == T::created_at_column()
+                    || Some(*col) == T::soft_delete_column()
+                {
+                    continue;
+                }
+                let value = entry_json.get(*col).cloned().unwrap_or(Value::Null);
+                update_data.insert(col.to_string(), value);
+            }
+            // The version the entry was read with, which hooks cannot change
+            let expected = version_of::<T>(&update_data);
+            T::before_update(id, &mut update_data).await?;
+
+            let version = bump_version::<T>(&mut update_data, expected)?;
+            let mut query = self.query().where_eq(
+                T::primary_key_column(),
+                serde_json::to_value(id).map_err(ApiError::Serialization)?,
+            )?;
+            if let Some((column, expected)) = version {
+                query = query.where_eq(column, expected)?;
+            }
+
+            let query = query.set_multiple(update_data)?;
+
+            let mut conn = conn.acquire().await?;
+            let mut tx = conn.begin().await?;
+            let updated = query
+                .update_returning_on(&mut tx)
+                .await?
+                .into_iter()
+                .next()
+                .ok_or_else(|| not_updated(id, version.is_some()))?;
+            updated.after_update().await?;
+            tx.commit().await?;
+
+            Ok(updated)
+        }
     }
 
     /// Partially updates a record by its id with the provided updates.
-    async fn update_partial(
+    /// `version` is the version the record was read with, required for versioned
+    /// entries. The primary key, created_at, tenant, soft delete and version columns
+    /// cannot be updated: `updates` holding one of them fails with `InvalidColumn`.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn update_partial(
         &self,
         id: T::Id,
+        version: Option<i64>,
         updates: Vec<(String, Value)>,
-    ) -> RepositoryResult<T> {
-        if updates.is_empty() {
-            return self.find_by_id_required(id).await;
+    ) -> impl Future<Output = RepositoryResult<T>> + Send {
+        async move {
+            pin_to_primary();
+            self.update_partial_in(self.get_pool(), id, version, updates)
+                .await
         }
+    }
 
-        use chrono::Utc;
-        let now = Utc::now();
+    /// Same as `update_partial`, on the given pool, connection or transaction.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn update_partial_in<'c, A>(
+        &self,
+        conn: A,
+        id: T::Id,
+        version: Option<i64>,
+        updates: Vec<(String, Value)>,
+    ) -> impl Future<Output = RepositoryResult<T>> + Send
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        async move {
+            if version.is_some() && T::version_column().is_none() {
+                return Err(ApiError::InvalidQuery(format!(
+                    "{} has no version column",
+                    T::table_name()
+                )));
+            }
+            if updates.is_empty() {
+                let mut conn = conn.acquire().await?;
+                return self
+                    .find_by_id_in(&mut *conn, id)
+                    .await?
+                    .ok_or_else(|| ApiError::NotFound(format!("{:?}", id)));
+            }
+
+            use chrono::Utc;
+            let now = Utc::now();
+
+            let mut update_data = HashMap::new();
 
-        let mut update_data = HashMap::new();
+            for (column, value) in updates.into_iter() {
+                if !T::columns().contains(&column.as_str()) {
+                    return Err(ApiError::InvalidColumn(column.clone()));
+                }
 
-        for (column, value) in updates.into_iter() {
-            if !T::columns().contains(&column.as_str()) {
-                return Err(ApiError::InvalidColumn(column.clone()));
+                // Columns the repository maintains itself
+                if column == T::primary_key_column()
+                    || Some(column.as_str()) == T::created_at_column()
+                    || Some(column.as_str()) == T::tenant_column()
+                    || Some(column.as_str()) == T::soft_delete_column()
+                    || Some(column.as_str()) == T::version_column()
+                {
+                    return Err(ApiError::InvalidColumn(column));
+                }
+
+                update_data.insert(column, value);
             }
 
-            if column == "id" || column == "created_at" {
-                continue;
+            if let Some(column) = T::updated_at_column() {
+                update_data.insert(
+                    column.to_string(),
+                    serde_json::to_value(now).map_err(ApiError::Serialization)?,
+                );
+            }
+            T::before_update(id, &mut update_data).await?;
+
+            let version = bump_version::<T>(&mut update_data, version)?;
+            let mut query = self.query().where_eq(
+                T::primary_key_column(),
+                serde_json::to_value(id).map_err(ApiError::Serialization)?,
+            )?;
+            if let Some((column, expected)) = version {
+                query = query.where_eq(column, expected)?;
             }
 
-            update_data.insert(column, value);
+            let query = query.set_multiple(update_data)?;
+
+            let mut conn = conn.acquire().await?;
+            let mut tx = conn.begin().await?;
+            let updated = query
+                .update_returning_on(&mut tx)
+                .await?
+                .into_iter()
+                .next()
+                .ok_or_else(|| not_updated(id, version.is_some()))?;
+            updated.after_update().await?;
+            tx.commit().await?;
+
+            Ok(updated)
         }
+    }
 
-        update_data.insert(
-            "updated_at".to_string(),
-            serde_json::to_value(now).map_err(|e| ApiError::Serialization(e))?,
-        );
+    /// Deletes a record by its id. Returns true if a record was deleted.
+    /// Entries with a soft delete column are only marked as deleted.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn delete(&self, id: T::Id) -> impl Future<Output = RepositoryResult<bool>> + Send {
+        async move {
+            pin_to_primary();
+            self.delete_in(self.get_pool(), id).await
+        }
+    }
 
-        let updated_entries = self
-            .query()
-            .where_eq(
-                "id",
-                serde_json::to_value(id.clone()).map_err(|e| ApiError::Serialization(e))?,
-            )?
-            .set_multiple(update_data)?
-            .update_returning(self.get_pool())
-            .await?;
+    /// Same as `delete`, on the given pool, connection or transaction.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn delete_in<'c, A>(
+        &self,
+        conn: A,
+        id: T::Id,
+    ) -> impl Future<Output = RepositoryResult<bool>> + Send
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        async move {
+            let query = self.query().where_eq(
+                T::primary_key_column(),
+                serde_json::to_value(id).map_err(ApiError::Serialization)?,
+            )?;
+            let mut conn = conn.acquire().await?;
+            let rows_affected = delete_with_hooks(&mut conn, query, false).await?;
+
+            Ok(rows_affected > 0)
+        }
+    }
 
-        updated_entries
-            .into_iter()
-            .next()
-            .ok_or_else(|| ApiError::NotFound(format!("No record found with id: {:?}", id)))
+    /// Deletes multiple records by their ids (soft delete when the entry supports it).
+    /// Returns the number of records deleted.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn delete_many(&self, ids: &[T::Id]) -> impl Future<Output = RepositoryResult<u64>> + Send {
+        async move {
+            pin_to_primary();
+            self.delete_many_in(self.get_pool(), ids).await
+        }
     }
 
-    /// Deletes a record by its id. Returns true if a record was deleted.
-    async fn delete(&self, id: T::Id) -> RepositoryResult<bool> {
-        let rows_affected = self
-            .query()
-            .where_eq(
-                "id",
-                serde_json::to_value(id).map_err(|e| ApiError::Serialization(e))?,
-            )?
-            .delete(self.get_pool())
-            .await?;
+    /// Same as `delete_many`, on the given pool, connection or transaction.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn delete_many_in<'c, A>(
+        &self,
+        conn: A,
+        ids: &[T::Id],
+    ) -> impl Future<Output = RepositoryResult<u64>> + Send
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        async move {
+            if ids.is_empty() {
+                return Ok(0);
+            }
 
-        Ok(rows_affected > 0)
+            let id_values: Result<Vec<Value>, _> = ids.iter().map(serde_json::to_value).collect();
+            let id_values = id_values.map_err(ApiError::Serialization)?;
+
+            let query = self.query().where_in(T::primary_key_column(), id_values)?;
+            let mut conn = conn.acquire().await?;
+            delete_with_hooks(&mut conn, query, false).await
+        }
     }
 
-    /// Deletes multiple records by their ids. Returns the number of records deleted.
-    async fn delete_many(&self, ids: &[T::Id]) -> RepositoryResult<u64> {
-        if ids.is_empty() {
-            return Ok(0);
+    /// Restores a soft-deleted record. Returns true if a deleted record was found.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn restore(&self, id: T::Id) -> impl Future<Output = RepositoryResult<bool>> + Send {
+        async move {
+            pin_to_primary();
+            self.restore_in(self.get_pool(), id).await
         }
+    }
 
-        let id_values: Result<Vec<Value>, _> =
-            ids.iter().map(|id| serde_json::to_value(id)).collect();
-        let id_values = id_values.map_err(|e| ApiError::Serialization(e))?;
+    /// Same as `restore`, on the given pool, connection or transaction.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn restore_in<'c, A>(
+        &self,
+        conn: A,
+        id: T::Id,
+    ) -> impl Future<Output = RepositoryResult<bool>> + Send
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        async move {
+            let query = self.query().where_eq(
+                T::primary_key_column(),
+                serde_json::to_value(id).map_err(ApiError::Serialization)?,
+            )?;
+            let mut conn = conn.acquire().await?;
+            let rows_affected = query.restore(&mut *conn).await?;
+
+            Ok(rows_affected > 0)
+        }
+    }
 
-        self.query()
-            .where_in("id", id_values)?
-            .delete(self.get_pool())
-            .await
+    /// Deletes a record for good, whether or not it is soft-deleted.
+    /// Returns true if a record was deleted.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn force_delete(&self, id: T::Id) -> impl Future<Output = RepositoryResult<bool>> + Send {
+        async move {
+            pin_to_primary();
+            self.force_delete_in(self.get_pool(), id).await
+        }
+    }
+
+    /// Same as `force_delete`, on the given pool, connection or transaction.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn force_delete_in<'c, A>(
+        &self,
+        conn: A,
+        id: T::Id,
+    ) -> impl Future<Output = RepositoryResult<bool>> + Send
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        async move {
+            let query = self.query().with_trashed().where_eq(
+                T::primary_key_column(),
+                serde_json::to_value(id).map_err(ApiError::Serialization)?,
+            )?;
+            let mut conn = conn.acquire().await?;
+            let rows_affected = delete_with_hooks(&mut conn, query, true).await?;
+
+            Ok(rows_affected > 0)
+        }
     }
 
     /// Checks if a record exists by its id.
-    async fn exists(&self, id: T::Id) -> RepositoryResult<bool> {
-        let count = self
-            .query()
-            .where_eq(
-                "id",
-                serde_json::to_value(id).map_err(|e| ApiError::Serialization(e))?,
-            )?
-            .limit(1)
-            .count(self.get_pool())
-            .await?;
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn exists(&self, id: T::Id) -> impl Future<Output = RepositoryResult<bool>> + Send {
+        async move { self.exists_in(self.get_read_pool(), id).await }
+    }
 
-        Ok(count > 0)
+    /// Same as `exists`, using the given executor.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn exists_in<'c, E>(
+        &self,
+        executor: E,
+        id: T::Id,
+    ) -> impl Future<Output = RepositoryResult<bool>> + Send
+    where
+        E: ConnectionSource<'c, DB>,
+    {
+        async move {
+            let count = self
+                .query()
+                .where_eq(
+                    T::primary_key_column(),
+                    serde_json::to_value(id).map_err(ApiError::Serialization)?,
+                )?
+                .limit(1)
+                .count(executor)
+                .await?;
+
+            Ok(count > 0)
+        }
     }
 
     /// Fetches records using a custom QueryBuilderUtil instance.
-    async fn find_with_query(&self, query: QueryBuilderUtil<T>) -> RepositoryResult<Vec<T>> {
-        query.fetch_all(self.get_pool()).await
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn find_with_query(
+        &self,
+        query: QueryBuilderUtil<T, DB>,
+    ) -> impl Future<Output = RepositoryResult<Vec<T>>> + Send {
+        async move { query.fetch_all(self.get_read_pool()).await }
     }
 
     /// Counts records using a custom QueryBuilderUtil instance.
-    async fn count_with_query(&self, query: QueryBuilderUtil<T>) -> RepositoryResult<i64> {
-        query.count(self.get_pool()).await
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn count_with_query(
+        &self,
+        query: QueryBuilderUtil<T, DB>,
+    ) -> impl Future<Output = RepositoryResult<i64>> + Send {
+        async move { query.count(self.get_read_pool()).await }
     }
 
     /// Fetches an optional record using a custom QueryBuilderUtil instance.
-    async fn find_one_with_query(&self, query: QueryBuilderUtil<T>) -> RepositoryResult<Option<T>> {
-        query.fetch_optional(self.get_pool()).await
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn find_one_with_query(
+        &self,
+        query: QueryBuilderUtil<T, DB>,
+    ) -> impl Future<Output = RepositoryResult<Option<T>>> + Send {
+        async move { query.fetch_optional(self.get_read_pool()).await }
     }
 
     /// Fetches a required record using a custom QueryBuilderUtil instance.
-    async fn find_one_required_with_query(
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn find_one_required_with_query(
         &self,
-        query: QueryBuilderUtil<T>,
-    ) -> RepositoryResult<T> {
-        query.fetch_one(self.get_pool()).await
+        query: QueryBuilderUtil<T, DB>,
+    ) -> impl Future<Output = RepositoryResult<T>> + Send {
+        async move { query.fetch_one(self.get_read_pool()).await }
     }
 
     /// Deletes records using a custom QueryBuilderUtil instance.
-    async fn delete_by_query(&self, query: QueryBuilderUtil<T>) -> RepositoryResult<u64> {
-        query.delete(self.get_pool()).await
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn delete_by_query(
+        &self,
+        query: QueryBuilderUtil<T, DB>,
+    ) -> impl Future<Output = RepositoryResult<u64>> + Send {
+        async move {
+            pin_to_primary();
+            let mut conn = self.get_pool().acquire().await?;
+            delete_with_hooks(&mut conn, query, false).await
+        }
     }
 
     /// Finds records with advanced options: conditions, ordering, limit, and offset.
-    async fn find_advanced(
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn find_advanced(
         &self,
         conditions: &[(&str, Value)],
         order_by: Option<(&str, OrderDirection)>,
         limit: Option<u32>,
         offset: Option<u32>,
-    ) -> RepositoryResult<Vec<T>> {
-        let mut query = self.query();
+    ) -> impl Future<Output = RepositoryResult<Vec<T>>> + Send {
+        async move {
+            let mut query = self.query();
+
+            for (i, (column, value)) in conditions.iter().enumerate() {
+                query = query.where_eq(column, value.clone())?;
+                if i < conditions.len() - 1 {
+                    query = query.and();
+                }
+            }
 
-        for (i, (column, value)) in conditions.iter().enumerate() {
-            query = query.where_eq(column, value.clone())?;
-            if i < conditions.len() - 1 {
-                query = query.and();
+            if let Some((column, direction)) = order_by {
+                query = query.order_by(column, direction)?;
             }
-        }
 
-        if let Some((column, direction)) = order_by {
-            query = query.order_by(column, direction)?;
-        }
+            if let Some(l) = limit {
+                query = query.limit(l);
+            }
 
-        if let Some(l) = limit {
-            query = query.limit(l);
-        }
+            if let Some(o) = offset {
+                query = query.offset(o);
+            }
 
-        if let Some(o) = offset {
-            query = query.offset(o);
+            query.fetch_all(self.get_read_pool()).await
         }
-
-        query.fetch_all(self.get_pool()).await
     }
 
     /// Searches for records where a column matches a pattern (LIKE/ILIKE).
-    async fn search_by_pattern(
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn search_by_pattern(
         &self,
         column: &str,
         pattern: &str,
         case_sensitive: bool,
         limit: Option<u32>,
-    ) -> RepositoryResult<Vec<T>> {
-        let search_pattern = format!("%{}%", pattern);
-        let mut query = self.query();
-
-        query = if case_sensitive {
-            query.where_like(column, search_pattern)?
-        } else {
-            query.where_ilike(column, search_pattern)?
-        };
+    ) -> impl Future<Output = RepositoryResult<Vec<T>>> + Send {
+        async move {
+            let search_pattern = format!("%{}%", pattern);
+            let mut query = self.query();
+
+            query = if case_sensitive {
+                query.where_like(column, search_pattern)?
+            } else {
+                query.where_ilike(column, search_pattern)?
+            };
+
+            if let Some(l) = limit {
+                query = query.limit(l);
+            }
 
-        if let Some(l) = limit {
-            query = query.limit(l);
+            query.fetch_all(self.get_read_pool()).await
         }
+    }
 
-        query.fetch_all(self.get_pool()).await
+    /// Full-text search over `columns`, best matches first, each result with its
+    /// `ts_rank` and a `ts_headline` snippet. `config` is the text search configuration
+    /// (e.g. "english"); an expression index on `to_tsvector(config, ...)` speeds it up.
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn full_text_search(
+        &self,
+        columns: &[&str],
+        query: &str,
+        config: &str,
+        limit: Option<u32>,
+    ) -> impl Future<Output = RepositoryResult<Vec<SearchResult<T>>>> + Send
+    where
+        SearchResult<T>: for<'r> FromRow<'r, DB::Row>,
+    {
+        async move {
+            let search = TextSearch::new(columns.iter().copied(), query).config(config);
+            let mut builder = self
+                .query()
+                .where_text_search(&search)?
+                .select_rank(&search)?
+                .select_headline(&search)?
+                .order_by_rank(&search)?;
+
+            if let Some(l) = limit {
+                builder = builder.limit(l);
+            }
+
+            builder
+                .fetch_all_as::<SearchResult<T>, _>(self.get_read_pool())
+                .await
+        }
     }
 
     /// Finds records where a column value is within a specified range.
-    async fn find_by_range<V>(&self, column: &str, start: V, end: V) -> RepositoryResult<Vec<T>>
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn find_by_range<V>(
+        &self,
+        column: &str,
+        start: V,
+        end: V,
+    ) -> impl Future<Output = RepositoryResult<Vec<T>>> + Send
     where
-        V: serde::Serialize,
+        V: Send + serde::Serialize,
     {
-        let start_value = serde_json::to_value(start).map_err(|e| ApiError::Serialization(e))?;
-        let end_value = serde_json::to_value(end).map_err(|e| ApiError::Serialization(e))?;
-
-        self.query()
-            .where_between(column, start_value, end_value)?
-            .fetch_all(self.get_pool())
-            .await
+        async move {
+            let start_value = serde_json::to_value(start).map_err(ApiError::Serialization)?;
+            let end_value = serde_json::to_value(end).map_err(ApiError::Serialization)?;
+
+            self.query()
+                .where_between(column, start_value, end_value)?
+                .fetch_all(self.get_read_pool())
+                .await
+        }
     }
 
     /// Finds records where a column value is in a list of values.
-    async fn find_by_values<V>(&self, column: &str, values: Vec<V>) -> RepositoryResult<Vec<T>>
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn find_by_values<V>(
+        &self,
+        column: &str,
+        values: Vec<V>,
+    ) -> impl Future<Output = RepositoryResult<Vec<T>>> + Send
     where
-        V: serde::Serialize,
+        V: Send + serde::Serialize,
     {
-        if values.is_empty() {
-            return Ok(vec![]);
-        }
+        async move {
+            if values.is_empty() {
+                return Ok(vec![]);
+            }
 
-        let json_values: Result<Vec<Value>, _> = values
-            .into_iter()
-            .map(|v| serde_json::to_value(v))
-            .collect();
-        let json_values = json_values.map_err(|e| ApiError::Serialization(e))?;
+            let json_values: Result<Vec<Value>, _> = values
+                .into_iter()
+                .map(|v| serde_json::to_value(v))
+                .collect();
+            let json_values = json_values.map_err(ApiError::Serialization)?;
 
-        self.query()
-            .where_in(column, json_values)?
-            .fetch_all(self.get_pool())
-            .await
+            self.query()
+                .where_in(column, json_values)?
+                .fetch_all(self.get_read_pool())
+                .await
+        }
     }
 
     /// Fetches a paginated and optionally sorted list of records.
-    async fn paginate_sorted(
+    #[instrument(skip_all, fields(table = T::table_name()))]
+    fn paginate_sorted(
         &self,
         page: u32,
         page_size: u32,
         sort_column: Option<&str>,
         sort_direction: Option<OrderDirection>,
-    ) -> RepositoryResult<Vec<T>> {
-        let mut query = self.query().paginate(page, page_size);
+    ) -> impl Future<Output = RepositoryResult<Vec<T>>> + Send {
+        async move {
+            let mut query = self.query().paginate(page, page_size);
+
+            if let Some(column) = sort_column {
+                let direction = sort_direction.unwrap_or(OrderDirection::Asc);
+                query = query.order_by(column, direction)?;
+            } else {
+                query = query.order_by(T::primary_key_column(), OrderDirection::Asc)?;
+            }
+
+            query.fetch_all(self.get_read_pool()).await
+        }
+    }
+}
+
+// Insertable column values of an entry
+fn insert_data<T: Entry>(entry: &T) -> RepositoryResult<HashMap<String, Value>> {
+    let entry_json = serde_json::to_value(entry).map_err(ApiError::Serialization)?;
+
+    Ok(T::insertable_columns()
+        .into_iter()
+        .map(|col| {
+            let value = entry_json.get(col).cloned().unwrap_or(Value::Null);
+            (col.to_string(), value)
+        })
+        .collect())
+}
+
+/// Deletes the rows matched by `query` (for good with `force`) with the delete hooks of
+/// the entry: in a transaction, the rows are locked and passed to `before_delete`, then
+/// deleted and passed to `after_delete`. Returns the number of rows deleted.
+async fn delete_with_hooks<T, DB>(
+    conn: &mut DB::Connection,
+    query: QueryBuilderUtil<T, DB>,
+    force: bool,
+) -> RepositoryResult<u64>
+where
+    T: EntryHooks + Send + Sync + Unpin + 'static + for<'r> FromRow<'r, DB::Row>,
+    DB: Backend,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+{
+    let mut tx = conn.begin().await?;
+
+    let rows = query.fetch_for_update(&mut *tx).await?;
+    if rows.is_empty() {
+        tx.commit().await?;
+        return Ok(0);
+    }
+    for row in &rows {
+        row.before_delete().await?;
+    }
+
+    // Only the locked rows, which the hooks have seen
+    let ids = rows
+        .iter()
+        .map(|row| {
+            serde_json::to_value(row).map(|json| {
+                json.get(T::primary_key_column())
+                    .cloned()
+                    .unwrap_or(Value::Null)
+            })
+        })
+        .collect::<Result<Vec<Value>, _>>()
+        .map_err(ApiError::Serialization)?;
+    let query = locked_rows_query(&query, ids)?;
+    let rows_affected = if force {
+        query.force_delete(&mut *tx).await?
+    } else {
+        query.delete(&mut *tx).await?
+    };
+
+    for row in &rows {
+        row.after_delete().await?;
+    }
+    tx.commit().await?;
+
+    Ok(rows_affected)
+}
+
+/// Query of the rows with the given ids, in the same tenant, trashed scope and timeout as
+/// `query`. Its conditions are left out: ANDed to them, the ids would only bind their
+/// last OR operand.
+fn locked_rows_query<T, DB>(
+    query: &QueryBuilderUtil<T, DB>,
+    ids: Vec<Value>,
+) -> RepositoryResult<QueryBuilderUtil<T, DB>>
+where
+    T: Entry + Send + Sync + Unpin + 'static,
+    DB: Backend,
+{
+    let mut locked = QueryBuilderUtil::new().where_in(T::primary_key_column(), ids)?;
+    locked.trashed = query.trashed;
+    locked.tenant = query.tenant.clone();
+    locked.timeout = query.timeout;
+    Ok(locked)
+}
+
+/// Version given in `update_data`, for entries with a version column.
+fn version_of<T: Entry>(update_data: &HashMap<String, Value>) -> Option<i64> {
+    update_data
+        .get(T::version_column()?)
+        .and_then(Value::as_i64)
+}
 
-        if let Some(column) = sort_column {
-            let direction = sort_direction.unwrap_or(OrderDirection::Asc);
-            query = query.order_by(column, direction)?;
-        } else {
-            query = query.order_by("id", OrderDirection::Asc)?;
+/// For entries with a version column, sets the version in `update_data` to the one
+/// following `expected`, the version the record was read with. Returns the column and
+/// the expected version, which the update must match.
+fn bump_version<T: Entry>(
+    update_data: &mut HashMap<String, Value>,
+    expected: Option<i64>,
+) -> RepositoryResult<Option<(&'static str, i64)>> {
+    let Some(column) = T::version_column() else {
+        return Ok(None);
+    };
+
+    let expected = expected.ok_or_else(|| {
+        ApiError::InvalidQuery(format!(
+            "Updating {} requires the current `{}`",
+            T::table_name(),
+            column
+        ))
+    })?;
+    update_data.insert(column.to_string(), Value::from(expected + 1));
+
+    Ok(Some((column, expected)))
+}
+
+/// Error of an update that matched no row. For a versioned update, the record was
+/// modified (or deleted) since it was read.
+fn not_updated<Id: std::fmt::Debug>(id: Id, versioned: bool) -> ApiError {
+    if versioned {
+        ApiError::PreconditionFailed(format!("Record {:?} was modified since it was read", id))
+    } else {
+        ApiError::NotFound(format!("No record found with id: {:?}", id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use sqlx::Sqlite;
+
+    use super::*;
+    use crate::core::base::{
+        generic_repository::{
+            tenant::{TenantContext, with_tenant},
+            test_support::{Comment, Note, comment_repository, note_repository},
+        },
+        query_builder::query_models::StatementKind,
+    };
+
+    #[tokio::test]
+    async fn create_returns_the_inserted_row() {
+        let repository = note_repository().await;
+
+        let created = repository.create(Note::new("first", "body")).await.unwrap();
+
+        assert!(created.id > 0);
+        assert_eq!(created.title, "first");
+        assert_eq!(created.version, 1);
+        assert_eq!(repository.count().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn create_many_inserts_every_entry() {
+        let repository = note_repository().await;
+
+        let created = repository
+            .create_many(vec![Note::new("a", "1"), Note::new("b", "2")])
+            .await
+            .unwrap();
+
+        assert_eq!(created.len(), 2);
+        assert_eq!(repository.count().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn find_by_id_and_column() {
+        let repository = note_repository().await;
+        let created = repository.create(Note::new("first", "body")).await.unwrap();
+        repository
+            .create(Note::new("second", "body"))
+            .await
+            .unwrap();
+
+        let found = repository.find_by_id(created.id).await.unwrap();
+        assert_eq!(found.as_ref().map(|note| note.id), Some(created.id));
+        assert!(
+            repository
+                .find_by_id(created.id + 100)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(matches!(
+            repository.find_by_id_required(created.id + 100).await,
+            Err(ApiError::NotFound(_))
+        ));
+
+        let by_title = repository.find_by_column("title", "second").await.unwrap();
+        assert_eq!(by_title.len(), 1);
+        assert_eq!(
+            repository
+                .find_by_column("body", "body")
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn update_bumps_the_version() {
+        let repository = note_repository().await;
+        let mut note = repository.create(Note::new("first", "body")).await.unwrap();
+
+        note.title = "renamed".to_string();
+        let updated = repository.update(note.id, note.clone()).await.unwrap();
+
+        assert_eq!(updated.title, "renamed");
+        assert_eq!(updated.version, 2);
+        assert_eq!(updated.created_at, note.created_at);
+    }
+
+    #[tokio::test]
+    async fn update_with_a_stale_version_fails() {
+        let repository = note_repository().await;
+        let note = repository.create(Note::new("first", "body")).await.unwrap();
+        repository.update(note.id, note.clone()).await.unwrap();
+
+        let stale = repository.update(note.id, note).await;
+
+        assert!(matches!(stale, Err(ApiError::PreconditionFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn update_partial_writes_the_given_columns() {
+        let repository = note_repository().await;
+        let note = repository.create(Note::new("first", "body")).await.unwrap();
+
+        let updated = repository
+            .update_partial(
+                note.id,
+                Some(note.version),
+                vec![("body".to_string(), Value::from("changed"))],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(updated.title, "first");
+        assert_eq!(updated.body, "changed");
+        assert_eq!(updated.version, 2);
+        assert!(matches!(
+            repository
+                .update_partial(
+                    note.id,
+                    Some(updated.version),
+                    vec![("missing".to_string(), Value::from(1))]
+                )
+                .await,
+            Err(ApiError::InvalidColumn(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn update_partial_refuses_the_columns_the_repository_maintains() {
+        let notes = note_repository().await;
+        let note = notes.create(Note::new("first", "body")).await.unwrap();
+        for column in ["id", "created_at", "version"] {
+            let result = notes
+                .update_partial(
+                    note.id,
+                    Some(note.version),
+                    vec![(column.to_string(), Value::from(7))],
+                )
+                .await;
+            assert!(
+                matches!(result, Err(ApiError::InvalidColumn(_))),
+                "{}",
+                column
+            );
         }
+        let stale = notes
+            .update_partial(note.id, None, vec![("body".to_string(), Value::from("b"))])
+            .await;
+        assert!(matches!(stale, Err(ApiError::InvalidQuery(_))));
+
+        let comments = comment_repository().await;
+        with_tenant(TenantContext::new("t1"), async {
+            let comment = comments.create(comment("t1", "first")).await.unwrap();
+            for (column, value) in [("tenant_id", "t2"), ("deleted_at", "2024-01-01")] {
+                let result = comments
+                    .update_partial(
+                        comment.id,
+                        None,
+                        vec![(column.to_string(), Value::from(value))],
+                    )
+                    .await;
+                assert!(
+                    matches!(result, Err(ApiError::InvalidColumn(_))),
+                    "{}",
+                    column
+                );
+            }
+            let unchanged = comments.find_by_id(comment.id).await.unwrap().unwrap();
+            assert_eq!(unchanged.tenant_id, "t1");
+        })
+        .await;
+    }
+
+    fn comment(tenant_id: &str, body: &str) -> Comment {
+        Comment {
+            id: 0,
+            note_id: 1,
+            tenant_id: tenant_id.to_string(),
+            body: body.to_string(),
+            deleted_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn repositories_only_reach_the_rows_of_the_tenant_in_scope() {
+        let repository = comment_repository().await;
+        let own = with_tenant(
+            TenantContext::new("t1"),
+            repository.create(comment("t1", "own")),
+        )
+        .await
+        .unwrap();
+        // The tenant in scope wins over the one of the entry
+        let other = with_tenant(
+            TenantContext::new("t2"),
+            repository.create(comment("t1", "other")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(other.tenant_id, "t2");
+
+        with_tenant(TenantContext::new("t1"), async {
+            let bodies: Vec<String> = repository
+                .find_all()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|comment| comment.body)
+                .collect();
+            assert_eq!(bodies, ["own"]);
+            assert!(repository.find_by_id(other.id).await.unwrap().is_none());
+            assert!(matches!(
+                repository
+                    .update_partial(other.id, None, vec![("body".to_string(), Value::from("x"))])
+                    .await,
+                Err(ApiError::NotFound(_))
+            ));
+            assert!(!repository.delete(other.id).await.unwrap());
+            assert!(repository.delete(own.id).await.unwrap());
+        })
+        .await;
+
+        // Nothing is reached without a tenant in scope
+        assert!(repository.find_all().await.is_err());
+        let other = with_tenant(TenantContext::new("t2"), repository.find_by_id(other.id))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(other.body, "other");
+    }
+
+    #[tokio::test]
+    async fn update_partial_only_stamps_entries_with_an_updated_at() {
+        let repository = comment_repository().await;
+        with_tenant(TenantContext::new("t1"), async {
+            let comment = repository
+                .create(Comment {
+                    id: 0,
+                    note_id: 1,
+                    tenant_id: "t1".to_string(),
+                    body: "first".to_string(),
+                    deleted_at: None,
+                })
+                .await
+                .unwrap();
+
+            let updated = repository
+                .update_partial(
+                    comment.id,
+                    None,
+                    vec![("body".to_string(), Value::from("changed"))],
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(updated.body, "changed");
+        })
+        .await;
+    }
 
-        query.fetch_all(self.get_pool()).await
+    #[tokio::test]
+    async fn delete_removes_the_row() {
+        let repository = note_repository().await;
+        let first = repository.create(Note::new("first", "body")).await.unwrap();
+        let second = repository
+            .create(Note::new("second", "body"))
+            .await
+            .unwrap();
+
+        assert!(repository.delete(first.id).await.unwrap());
+        assert!(!repository.delete(first.id).await.unwrap());
+        assert!(!repository.exists(first.id).await.unwrap());
+        assert!(repository.exists(second.id).await.unwrap());
+
+        assert_eq!(
+            repository
+                .delete_many(&[second.id, first.id])
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(repository.count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn delete_by_query_only_deletes_the_matching_rows() {
+        let repository = note_repository().await;
+        for title in ["a", "b", "c"] {
+            repository.create(Note::new(title, "body")).await.unwrap();
+        }
+
+        let query = repository
+            .query()
+            .where_eq("title", "a")
+            .unwrap()
+            .or()
+            .where_eq("title", "b")
+            .unwrap();
+        assert_eq!(repository.delete_by_query(query).await.unwrap(), 2);
+
+        let left = repository.find_all().await.unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].title, "c");
+    }
+
+    #[test]
+    fn locked_rows_are_deleted_by_id_only() {
+        let query = QueryBuilderUtil::<Note, Sqlite>::new()
+            .where_eq("title", "a")
+            .unwrap()
+            .or()
+            .where_eq("title", "b")
+            .unwrap()
+            .timeout(Duration::from_secs(1));
+
+        let locked = locked_rows_query(&query, vec![Value::from(1), Value::from(2)]).unwrap();
+
+        assert_eq!(
+            locked.to_sql(StatementKind::Delete).unwrap(),
+            (
+                "DELETE FROM notes WHERE id IN (?, ?)".to_string(),
+                vec![Value::from(1), Value::from(2)]
+            )
+        );
+        assert_eq!(locked.timeout, query.timeout);
+    }
+
+    #[tokio::test]
+    async fn hooks_run_on_writes() {
+        let repository = note_repository().await;
+
+        let mut note = repository
+            .create(Note::new("  padded ", "body"))
+            .await
+            .unwrap();
+        assert_eq!(note.title, "padded");
+
+        note.title = " locked ".to_string();
+        let note = repository.update(note.id, note).await.unwrap();
+        assert_eq!(note.title, "locked");
+
+        // The error of before_delete rolls the deletion back
+        assert!(matches!(
+            repository.delete(note.id).await,
+            Err(ApiError::Conflict(_))
+        ));
+        assert!(repository.exists(note.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn hooks_run_in_spawned_tasks() {
+        let note = tokio::spawn(async {
+            let mut note = Note::new("  padded ", "body");
+            note.before_insert().await.map(|_| note)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(note.title, "padded");
+    }
+
+    #[tokio::test]
+    async fn paginate_orders_by_id() {
+        let repository = note_repository().await;
+        for title in ["a", "b", "c", "d", "e"] {
+            repository.create(Note::new(title, "body")).await.unwrap();
+        }
+
+        let titles =
+            |notes: Vec<Note>| notes.into_iter().map(|note| note.title).collect::<Vec<_>>();
+        assert_eq!(titles(repository.paginate(1, 2).await.unwrap()), ["a", "b"]);
+        assert_eq!(titles(repository.paginate(3, 2).await.unwrap()), ["e"]);
+        assert!(repository.paginate(4, 2).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn paginate_cursor_walks_every_row() {
+        let repository = note_repository().await;
+        for title in ["a", "b", "c"] {
+            repository.create(Note::new(title, "body")).await.unwrap();
+        }
+
+        let secret = b"secret";
+        let first = repository
+            .paginate_cursor(&["title"], OrderDirection::Desc, None, 2, secret)
+            .await
+            .unwrap();
+        let next = repository
+            .paginate_cursor(
+                &["title"],
+                OrderDirection::Desc,
+                first.next_cursor.as_deref(),
+                2,
+                secret,
+            )
+            .await
+            .unwrap();
+
+        let titles = |notes: &[Note]| {
+            notes
+                .iter()
+                .map(|note| note.title.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(titles(&first.items), ["c", "b"]);
+        assert_eq!(titles(&next.items), ["a"]);
+        assert!(next.next_cursor.is_none());
+    }
+
+    /// Entry whose primary key and update timestamp are not named `id` and `updated_at`.
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, FromRow, Entry)]
+    #[entry(table = "accounts")]
+    struct Account {
+        #[entry(primary_key)]
+        account_id: i64,
+        name: String,
+        #[entry(timestamp = "updated")]
+        changed_at: chrono::DateTime<chrono::Utc>,
+    }
+
+    struct AccountRepository {
+        pool: Pool<Sqlite>,
+    }
+
+    impl RepositoryTrait<Account, Sqlite> for AccountRepository {
+        fn get_pool(&self) -> &Pool<Sqlite> {
+            &self.pool
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_use_the_declared_key_and_timestamp_columns() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::query(
+            "CREATE TABLE accounts (
+                account_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                changed_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let repository = AccountRepository { pool };
+        let account = |name: &str| Account {
+            account_id: 0,
+            name: name.to_string(),
+            changed_at: chrono::Utc::now(),
+        };
+
+        let first = repository.create(account("first")).await.unwrap();
+        let second = repository.create(account("second")).await.unwrap();
+        assert_ne!(first.account_id, second.account_id);
+
+        let renamed = repository
+            .update_partial(
+                first.account_id,
+                None,
+                vec![("name".to_string(), Value::from("renamed"))],
+            )
+            .await
+            .unwrap();
+        assert_eq!(renamed.name, "renamed");
+        assert!(renamed.changed_at >= first.changed_at);
+
+        assert!(repository.delete(second.account_id).await.unwrap());
+        let remaining = repository.find_all().await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].account_id, first.account_id);
     }
 }
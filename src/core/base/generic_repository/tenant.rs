@@ -0,0 +1,48 @@
+use serde_json::Value;
+
+tokio::task_local! {
+    static TENANT_SCOPE: TenantScope;
+}
+
+/// The tenant (customer) whose rows the current task reads and writes, compared with
+/// the `Entry::tenant_column` of the tables that have one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TenantContext {
+    pub id: Value,
+}
+
+impl TenantContext {
+    pub fn new(id: impl Into<Value>) -> Self {
+        Self { id: id.into() }
+    }
+}
+
+/// Scope under which the queries of tenant tables run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TenantScope {
+    /// The rows of one tenant
+    Tenant(TenantContext),
+    /// The rows of every tenant, for admin jobs
+    Unscoped,
+}
+
+impl TenantScope {
+    /// Scope of the current task, None outside of `with_tenant` and `without_tenant_scope`.
+    pub fn current() -> Option<TenantScope> {
+        TENANT_SCOPE.try_with(Clone::clone).ok()
+    }
+}
+
+/// Runs `future` (typically a request) in the scope of `tenant`: the query builders and
+/// repositories it creates only see and write the rows of that tenant.
+pub async fn with_tenant<F: Future>(tenant: TenantContext, future: F) -> F::Output {
+    TENANT_SCOPE
+        .scope(TenantScope::Tenant(tenant), future)
+        .await
+}
+
+/// Runs `future` across all tenants. Reserved for admin jobs (migrations of data,
+/// reports...): never call it with a tenant-controlled input.
+pub async fn without_tenant_scope<F: Future>(future: F) -> F::Output {
+    TENANT_SCOPE.scope(TenantScope::Unscoped, future).await
+}
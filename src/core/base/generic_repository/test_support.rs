@@ -0,0 +1,240 @@
+//! Entry and repository on an in-memory SQLite database, for the tests of the
+//! generic repository and query builder, and a Postgres transaction for the
+//! Postgres-only features.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{
+    FromRow, PgPool, Pool, Postgres, Sqlite, Transaction,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+};
+use tempfile::TempDir;
+
+use super::{
+    entry_trait::{Entry, EntryHooks},
+    repository_trait::RepositoryTrait,
+};
+use crate::core::errors::errors::ApiError;
+
+/// Versioned note, whose hooks trim the title and refuse to delete notes titled `locked`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow, Entry)]
+#[entry(table = "notes", hooks)]
+#[entry(has_many(name = "comments", entry = Comment, foreign_key = "note_id"))]
+pub struct Note {
+    #[entry(primary_key)]
+    pub id: i64,
+    pub title: String,
+    pub body: String,
+    #[entry(timestamp = "created")]
+    pub created_at: DateTime<Utc>,
+    #[entry(timestamp = "updated")]
+    pub updated_at: DateTime<Utc>,
+    #[entry(version)]
+    pub version: i64,
+}
+
+impl EntryHooks for Note {
+    async fn before_insert(&mut self) -> Result<(), ApiError> {
+        self.title = self.title.trim().to_string();
+        Ok(())
+    }
+
+    async fn before_update(_id: i64, changes: &mut HashMap<String, Value>) -> Result<(), ApiError> {
+        if let Some(Value::String(title)) = changes.get_mut("title") {
+            *title = title.trim().to_string();
+        }
+        Ok(())
+    }
+
+    async fn before_delete(&self) -> Result<(), ApiError> {
+        if self.title == "locked" {
+            return Err(ApiError::Conflict("Note is locked".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl Note {
+    pub fn new(title: &str, body: &str) -> Self {
+        let now = Utc::now();
+        Self {
+            id: 0,
+            title: title.to_string(),
+            body: body.to_string(),
+            created_at: now,
+            updated_at: now,
+            version: 1,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NoteRepository {
+    pub pool: Pool<Sqlite>,
+    /// Stands for a read replica, which may lag behind `pool`.
+    pub read_pool: Option<Pool<Sqlite>>,
+}
+
+impl RepositoryTrait<Note, Sqlite> for NoteRepository {
+    fn get_pool(&self) -> &Pool<Sqlite> {
+        &self.pool
+    }
+
+    fn get_read_pool(&self) -> &Pool<Sqlite> {
+        self.read_pool.as_ref().unwrap_or(&self.pool)
+    }
+}
+
+const CREATE_NOTES: &str = "CREATE TABLE notes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    version INTEGER NOT NULL DEFAULT 1
+)";
+
+/// Repository on a fresh in-memory database. Its single connection is never closed,
+/// as the database lives as long as it does.
+pub async fn note_repository() -> NoteRepository {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with("sqlite::memory:".parse::<SqliteConnectOptions>().unwrap())
+        .await
+        .unwrap();
+    sqlx::query(CREATE_NOTES).execute(&pool).await.unwrap();
+    NoteRepository {
+        pool,
+        read_pool: None,
+    }
+}
+
+/// Repository on a fresh database file in write-ahead logging mode, whose connections
+/// read the last committed rows while another one writes in a transaction. The
+/// database is deleted with the returned directory.
+pub async fn shared_note_repository() -> (NoteRepository, TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let options = SqliteConnectOptions::new()
+        .filename(dir.path().join("notes.db"))
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(4)
+        .connect_with(options)
+        .await
+        .unwrap();
+    sqlx::query(CREATE_NOTES).execute(&pool).await.unwrap();
+    (
+        NoteRepository {
+            pool,
+            read_pool: None,
+        },
+        dir,
+    )
+}
+
+/// Soft-deleted comment of a note, shared between tenants.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow, Entry)]
+#[entry(table = "comments")]
+#[entry(belongs_to(name = "note", entry = Note, foreign_key = "note_id"))]
+pub struct Comment {
+    #[entry(primary_key)]
+    pub id: i64,
+    pub note_id: i64,
+    #[entry(tenant)]
+    pub tenant_id: String,
+    pub body: String,
+    #[entry(timestamp = "deleted")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone)]
+pub struct CommentRepository {
+    pub pool: Pool<Sqlite>,
+}
+
+impl RepositoryTrait<Comment, Sqlite> for CommentRepository {
+    fn get_pool(&self) -> &Pool<Sqlite> {
+        &self.pool
+    }
+}
+
+/// Repository of comments on a fresh in-memory database, as `note_repository`.
+pub async fn comment_repository() -> CommentRepository {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with("sqlite::memory:".parse::<SqliteConnectOptions>().unwrap())
+        .await
+        .unwrap();
+    sqlx::query(
+        "CREATE TABLE comments (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            note_id INTEGER NOT NULL,
+            tenant_id TEXT NOT NULL,
+            body TEXT NOT NULL,
+            deleted_at TEXT
+        )",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    CommentRepository { pool }
+}
+
+/// URL of the Postgres database of the Postgres tests. They are ignored by default and
+/// fail when run without `TEST_DATABASE_URL`.
+pub fn pg_url() -> String {
+    std::env::var("TEST_DATABASE_URL")
+        .expect("the Postgres tests need TEST_DATABASE_URL, see the README")
+}
+
+/// Transaction on the Postgres database of `TEST_DATABASE_URL`, in a schema of its own
+/// holding the migrated tables and the `notes` and `comments` tables, all dropped
+/// with the transaction.
+pub async fn pg_transaction() -> Transaction<'static, Postgres> {
+    let pool = PgPool::connect(&pg_url()).await.unwrap();
+    let mut tx = pool.begin().await.unwrap();
+    let schema = format!("test_{}", uuid::Uuid::new_v4().simple());
+    sqlx::query(&format!("CREATE SCHEMA {}", schema))
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+    sqlx::query(&format!("SET LOCAL search_path TO {}, public", schema))
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+    sqlx::migrate!("./migrations").run(&mut *tx).await.unwrap();
+    sqlx::query(
+        "CREATE TABLE notes (
+            id BIGSERIAL PRIMARY KEY,
+            title TEXT NOT NULL,
+            body TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            version BIGINT NOT NULL DEFAULT 1
+        )",
+    )
+    .execute(&mut *tx)
+    .await
+    .unwrap();
+    sqlx::query(
+        "CREATE TABLE comments (
+            id BIGSERIAL PRIMARY KEY,
+            note_id BIGINT NOT NULL,
+            tenant_id TEXT NOT NULL,
+            body TEXT NOT NULL,
+            deleted_at TIMESTAMPTZ
+        )",
+    )
+    .execute(&mut *tx)
+    .await
+    .unwrap();
+    tx
+}
@@ -0,0 +1,198 @@
+use std::cell::RefCell;
+
+use futures_util::future::BoxFuture;
+use sqlx::{Connection, Database, Pool, Postgres, Transaction};
+
+use super::pools::pin_to_primary;
+use crate::core::errors::errors::ApiError;
+
+tokio::task_local! {
+    // Work to run once the outermost unit of work of the task has ended
+    static ON_COMPLETION: RefCell<Vec<BoxFuture<'static, ()>>>;
+}
+
+/// A database transaction shared by several repository calls.
+///
+/// Repositories run against it through their `*_in` methods:
+///
+/// ```ignore
+/// repositories
+///     .transaction(async |uow| {
+///         if user_repository.find_user_for_update(uow.conn(), id).await?.is_none() {
+///             return Err(ApiError::NotFound(id.to_string()));
+///         }
+///         user_repository.delete_user_in(uow.conn(), id).await
+///     })
+///     .await?;
+/// ```
+///
+/// The work is committed when the closure returns `Ok` and rolled back on `Err`.
+/// Nested units of work, started with `transaction`, run in a savepoint of the same
+/// connection. Work registered with `on_completion` during the closure (e.g. cache
+/// invalidations) runs once the outermost transaction has ended.
+pub struct UnitOfWork<'c, DB: Database = Postgres> {
+    tx: Transaction<'c, DB>,
+}
+
+impl<DB: Database> UnitOfWork<'static, DB> {
+    /// Runs `work` in a new transaction on `pool`. Inside a unit of work, nest with
+    /// `transaction` instead: a second transaction would wait on the locks of the first.
+    pub async fn run<F, R>(pool: &Pool<DB>, work: F) -> Result<R, ApiError>
+    where
+        F: AsyncFnOnce(&mut UnitOfWork<'_, DB>) -> Result<R, ApiError>,
+    {
+        if in_unit_of_work() {
+            return Err(ApiError::InvalidQuery(
+                "A unit of work cannot be run inside another one, use UnitOfWork::transaction"
+                    .to_string(),
+            ));
+        }
+
+        pin_to_primary();
+        ON_COMPLETION
+            .scope(RefCell::new(Vec::new()), async {
+                let result = match pool.begin().await {
+                    Ok(tx) => UnitOfWork { tx }.complete(work).await,
+                    Err(e) => Err(e.into()),
+                };
+                let deferred = ON_COMPLETION.with(RefCell::take);
+                for future in deferred {
+                    future.await;
+                }
+                result
+            })
+            .await
+    }
+}
+
+impl<DB: Database> UnitOfWork<'_, DB> {
+    /// The transaction's connection, to pass as executor to repositories and query builders.
+    pub fn conn(&mut self) -> &mut DB::Connection {
+        &mut self.tx
+    }
+
+    /// Runs `work` in a savepoint: an `Err` rolls back only what `work` did, and a
+    /// rollback of this unit of work discards it as well.
+    pub async fn transaction<F, R>(&mut self, work: F) -> Result<R, ApiError>
+    where
+        F: AsyncFnOnce(&mut UnitOfWork<'_, DB>) -> Result<R, ApiError>,
+    {
+        let tx = self.tx.begin().await?;
+        UnitOfWork { tx }.complete(work).await
+    }
+
+    async fn complete<F, R>(mut self, work: F) -> Result<R, ApiError>
+    where
+        F: AsyncFnOnce(&mut UnitOfWork<'_, DB>) -> Result<R, ApiError>,
+    {
+        match work(&mut self).await {
+            Ok(result) => {
+                self.tx.commit().await?;
+                Ok(result)
+            }
+            Err(e) => {
+                // The error of the work matters more than a failed rollback, after which
+                // the connection is discarded anyway
+                let _ = self.tx.rollback().await;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Whether the current task runs in a unit of work.
+pub fn in_unit_of_work() -> bool {
+    ON_COMPLETION.try_with(|_| ()).is_ok()
+}
+
+/// Runs `future` once the unit of work of the current task has ended, committed or
+/// rolled back. Returns false, dropping it, outside of a unit of work.
+pub fn on_completion(future: BoxFuture<'static, ()>) -> bool {
+    ON_COMPLETION
+        .try_with(|deferred| deferred.borrow_mut().push(future))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::base::generic_repository::{
+        repository_trait::RepositoryTrait,
+        test_support::{Note, NoteRepository, note_repository},
+    };
+
+    async fn titles(repository: &NoteRepository) -> Vec<String> {
+        let mut titles: Vec<_> = repository
+            .find_all()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|note| note.title)
+            .collect();
+        titles.sort();
+        titles
+    }
+
+    #[tokio::test]
+    async fn a_failed_nested_unit_rolls_back_only_its_own_work() {
+        // A single connection: a nested unit on another one would never get it
+        let repository = note_repository().await;
+
+        UnitOfWork::run(&repository.pool, async |uow| {
+            repository
+                .create_in(uow.conn(), Note::new("outer", "1"))
+                .await?;
+            let nested = uow
+                .transaction(async |uow| {
+                    repository
+                        .create_in(uow.conn(), Note::new("failed", "2"))
+                        .await?;
+                    Err::<(), _>(ApiError::Conflict("nested".to_string()))
+                })
+                .await;
+            assert!(matches!(nested, Err(ApiError::Conflict(_))));
+            uow.transaction(async |uow| {
+                repository
+                    .create_in(uow.conn(), Note::new("inner", "3"))
+                    .await
+            })
+            .await?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(titles(&repository).await, ["inner", "outer"]);
+    }
+
+    #[tokio::test]
+    async fn an_outer_rollback_discards_the_nested_work() {
+        let repository = note_repository().await;
+
+        let result = UnitOfWork::run(&repository.pool, async |uow| {
+            uow.transaction(async |uow| {
+                repository
+                    .create_in(uow.conn(), Note::new("inner", "1"))
+                    .await
+            })
+            .await?;
+            Err::<(), _>(ApiError::Conflict("outer".to_string()))
+        })
+        .await;
+
+        assert!(matches!(result, Err(ApiError::Conflict(_))));
+        assert!(titles(&repository).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_unit_of_work_cannot_be_run_inside_another() {
+        let repository = note_repository().await;
+
+        let result = UnitOfWork::run(&repository.pool, async |_| {
+            UnitOfWork::run(&repository.pool, async |_| Ok(())).await
+        })
+        .await;
+
+        assert!(matches!(result, Err(ApiError::InvalidQuery(_))));
+    }
+}
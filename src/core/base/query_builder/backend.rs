@@ -0,0 +1,378 @@
+use std::future::Future;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde_json::Value;
+use sqlx::{Arguments, Database, IntoArguments, Postgres, Row, error::BoxDynError};
+use uuid::Uuid;
+
+use crate::core::{
+    base::{
+        generic_repository::entry_trait::ColumnType,
+        query_builder::{generic_query_builder::DbType, query_models::ArrayType},
+    },
+    errors::errors::ApiError,
+};
+
+/// A value bound as a statement parameter, with the type it is encoded as.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    /// NULL, with the type of its column when known
+    Null(Option<ColumnType>),
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    /// Arbitrary precision number, bound as text
+    Numeric(String),
+    Text(String),
+    /// Label of the Postgres enum type with the given name, bound as text
+    Enum(&'static str, String),
+    Uuid(Uuid),
+    Timestamp(DateTime<Utc>),
+    Date(NaiveDate),
+    Json(Value),
+    TextArray(Vec<String>),
+    IntArray(Vec<i32>),
+    BigIntArray(Vec<i64>),
+    FloatArray(Vec<f64>),
+    BoolArray(Vec<bool>),
+    UuidArray(Vec<Uuid>),
+}
+
+impl From<SqlValue> for Value {
+    fn from(value: SqlValue) -> Self {
+        match value {
+            SqlValue::Null(_) => Value::Null,
+            SqlValue::Bool(b) => Value::Bool(b),
+            SqlValue::Int(i) => Value::from(i),
+            SqlValue::Float(f) => Value::from(f),
+            SqlValue::Numeric(s) | SqlValue::Text(s) | SqlValue::Enum(_, s) => Value::String(s),
+            SqlValue::Uuid(uuid) => Value::String(uuid.to_string()),
+            SqlValue::Timestamp(timestamp) => Value::String(timestamp.to_rfc3339()),
+            SqlValue::Date(date) => Value::String(date.to_string()),
+            SqlValue::Json(value) => value,
+            SqlValue::TextArray(array) => Value::from(array),
+            SqlValue::IntArray(array) => Value::from(array),
+            SqlValue::BigIntArray(array) => Value::from(array),
+            SqlValue::FloatArray(array) => Value::from(array),
+            SqlValue::BoolArray(array) => Value::from(array),
+            SqlValue::UuidArray(array) => {
+                Value::from_iter(array.into_iter().map(|uuid| uuid.to_string()))
+            }
+        }
+    }
+}
+
+impl SqlValue {
+    /// Converts a JSON value bound for a column of the given type. A value that does not
+    /// fit the type is bound as with `untyped`, and the database reports the mismatch.
+    pub fn typed(value: Value, column_type: ColumnType) -> Self {
+        if value.is_null() {
+            return SqlValue::Null(Some(column_type));
+        }
+
+        let typed = match column_type {
+            ColumnType::Text => match &value {
+                Value::String(s) => Some(SqlValue::Text(s.clone())),
+                Value::Number(_) | Value::Bool(_) => Some(SqlValue::Text(value.to_string())),
+                _ => None,
+            },
+            ColumnType::Integer => value
+                .as_i64()
+                .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+                .map(SqlValue::Int),
+            ColumnType::Float => value
+                .as_f64()
+                .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+                .map(SqlValue::Float),
+            ColumnType::Numeric => match &value {
+                Value::Number(n) => Some(SqlValue::Numeric(n.to_string())),
+                Value::String(s) => Some(SqlValue::Numeric(s.clone())),
+                _ => None,
+            },
+            ColumnType::Boolean => match &value {
+                Value::Bool(b) => Some(*b),
+                Value::String(s) if s == "true" || s == "false" => Some(s == "true"),
+                Value::Number(n) if n.as_u64().is_some_and(|i| i <= 1) => {
+                    Some(n.as_u64() == Some(1))
+                }
+                _ => None,
+            }
+            .map(SqlValue::Bool),
+            ColumnType::Uuid => value
+                .as_str()
+                .and_then(|s| Uuid::parse_str(s).ok())
+                .map(SqlValue::Uuid),
+            ColumnType::Timestamp => value
+                .as_str()
+                .and_then(parse_timestamp)
+                .map(SqlValue::Timestamp),
+            ColumnType::Date => value.as_str().and_then(parse_date).map(SqlValue::Date),
+            ColumnType::Json => return SqlValue::Json(value),
+            ColumnType::Enum(type_name) => value
+                .as_str()
+                .map(|s| SqlValue::Enum(type_name, s.to_string())),
+            ColumnType::Array(array_type) => value
+                .as_array()
+                .map(|values| SqlValue::array(array_type, values)),
+        };
+
+        typed.unwrap_or_else(|| SqlValue::untyped(value))
+    }
+
+    /// Converts a JSON value bound without a known column type: strings as text, numbers
+    /// and booleans natively, objects and arrays as JSON.
+    pub fn untyped(value: Value) -> Self {
+        match value {
+            Value::Null => SqlValue::Null(None),
+            Value::Bool(b) => SqlValue::Bool(b),
+            Value::Number(ref n) => {
+                if let Some(i) = n.as_i64() {
+                    SqlValue::Int(i)
+                } else if let Some(f) = n.as_f64() {
+                    SqlValue::Float(f)
+                } else {
+                    SqlValue::Json(value)
+                }
+            }
+            Value::String(s) => SqlValue::Text(s),
+            _ => SqlValue::Json(value),
+        }
+    }
+
+    /// An array of the given element type; values of another type are left out.
+    pub fn array(array_type: ArrayType, values: &[Value]) -> Self {
+        match array_type {
+            ArrayType::Text => SqlValue::TextArray(
+                values
+                    .iter()
+                    .filter_map(|v| v.as_str().map(str::to_string))
+                    .collect(),
+            ),
+            ArrayType::Int => SqlValue::IntArray(
+                values
+                    .iter()
+                    .filter_map(|v| v.as_i64().and_then(|i| i32::try_from(i).ok()))
+                    .collect(),
+            ),
+            ArrayType::BigInt => {
+                SqlValue::BigIntArray(values.iter().filter_map(Value::as_i64).collect())
+            }
+            ArrayType::Float => {
+                SqlValue::FloatArray(values.iter().filter_map(Value::as_f64).collect())
+            }
+            ArrayType::Bool => {
+                SqlValue::BoolArray(values.iter().filter_map(Value::as_bool).collect())
+            }
+            ArrayType::Uuid => SqlValue::UuidArray(
+                values
+                    .iter()
+                    .filter_map(|v| v.as_str().and_then(|s| Uuid::parse_str(s).ok()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Type the placeholder is cast to on Postgres (see `ColumnType::postgres_cast`).
+    pub fn postgres_cast(&self) -> Option<&'static str> {
+        match self {
+            SqlValue::Null(Some(column_type)) => column_type.postgres_cast(),
+            SqlValue::Numeric(_) => ColumnType::Numeric.postgres_cast(),
+            SqlValue::Enum(type_name, _) => Some(type_name),
+            _ => None,
+        }
+    }
+}
+
+// RFC 3339, or a date and time without offset taken as UTC
+fn parse_timestamp(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+                .iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+                .map(|timestamp| timestamp.and_utc())
+        })
+}
+
+fn parse_date(s: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .or_else(|| parse_timestamp(s).map(|timestamp| timestamp.date_naive()))
+}
+
+/// Databases whose arguments can be executed for any lifetime; see `Backend`.
+pub trait HasArguments<'q>: Database<Arguments<'q>: IntoArguments<'q, Self>> {}
+
+impl<'q, DB> HasArguments<'q> for DB where DB: Database<Arguments<'q>: IntoArguments<'q, DB>> {}
+
+/// A database `QueryBuilderUtil` and `RepositoryTrait` can run on.
+///
+/// Implemented for Postgres, and for MySQL and SQLite with the `mysql` and `sqlite`
+/// features (always in tests). The SQL differences (placeholders, RETURNING...) are described by
+/// `DB_TYPE`; the methods cover what differs in the sqlx types of each driver.
+pub trait Backend: Database + for<'q> HasArguments<'q> {
+    const DB_TYPE: DbType;
+
+    /// Adds `value` to the arguments of a statement, encoded as this database stores it.
+    fn bind(arguments: &mut Self::Arguments<'_>, value: SqlValue) -> Result<(), BoxDynError>;
+
+    fn rows_affected(result: &Self::QueryResult) -> u64;
+
+    /// Id generated by the last INSERT, for databases without RETURNING.
+    fn last_insert_id(_result: &Self::QueryResult) -> Option<i64> {
+        None
+    }
+
+    /// Reads an integer column, e.g. the result of COUNT(*).
+    fn get_i64(row: &Self::Row, index: usize) -> Result<i64, sqlx::Error>;
+
+    /// Sends `data` to a `COPY ... FROM STDIN` statement. Only Postgres supports it.
+    fn copy_in<'a>(
+        _conn: &'a mut Self::Connection,
+        _statement: &'a str,
+        _data: Vec<u8>,
+    ) -> impl Future<Output = Result<(), ApiError>> + Send + 'a {
+        async {
+            Err(ApiError::InvalidQuery(format!(
+                "COPY is not supported by {:?}",
+                Self::DB_TYPE
+            )))
+        }
+    }
+}
+
+/// Encodes the parameters of a statement for `DB`.
+pub fn arguments<'q, DB: Backend>(params: Vec<SqlValue>) -> Result<DB::Arguments<'q>, ApiError> {
+    let mut arguments = DB::Arguments::default();
+    for value in params {
+        DB::bind(&mut arguments, value).map_err(|e| ApiError::Database(sqlx::Error::Encode(e)))?;
+    }
+    Ok(arguments)
+}
+
+impl Backend for Postgres {
+    const DB_TYPE: DbType = DbType::Postgres;
+
+    fn bind(arguments: &mut Self::Arguments<'_>, value: SqlValue) -> Result<(), BoxDynError> {
+        match value {
+            // Kept as a JSON null when the column is unknown, typed otherwise
+            SqlValue::Null(None) => arguments.add(Value::Null),
+            SqlValue::Null(Some(column_type)) => bind_postgres_null(arguments, column_type),
+            SqlValue::Bool(b) => arguments.add(b),
+            SqlValue::Int(i) => arguments.add(i),
+            SqlValue::Float(f) => arguments.add(f),
+            SqlValue::Numeric(s) | SqlValue::Text(s) | SqlValue::Enum(_, s) => arguments.add(s),
+            SqlValue::Uuid(uuid) => arguments.add(uuid),
+            SqlValue::Timestamp(timestamp) => arguments.add(timestamp),
+            SqlValue::Date(date) => arguments.add(date),
+            SqlValue::Json(value) => arguments.add(value),
+            SqlValue::TextArray(array) => arguments.add(array),
+            SqlValue::IntArray(array) => arguments.add(array),
+            SqlValue::BigIntArray(array) => arguments.add(array),
+            SqlValue::FloatArray(array) => arguments.add(array),
+            SqlValue::BoolArray(array) => arguments.add(array),
+            SqlValue::UuidArray(array) => arguments.add(array),
+        }
+    }
+
+    fn rows_affected(result: &Self::QueryResult) -> u64 {
+        result.rows_affected()
+    }
+
+    fn get_i64(row: &Self::Row, index: usize) -> Result<i64, sqlx::Error> {
+        row.try_get(index)
+    }
+
+    async fn copy_in(
+        conn: &mut Self::Connection,
+        statement: &str,
+        data: Vec<u8>,
+    ) -> Result<(), ApiError> {
+        let mut copy = conn.copy_in_raw(statement).await?;
+        copy.send(data).await?;
+        copy.finish().await?;
+        Ok(())
+    }
+}
+
+fn bind_postgres_null(
+    arguments: &mut <Postgres as Database>::Arguments<'_>,
+    column_type: ColumnType,
+) -> Result<(), BoxDynError> {
+    match column_type {
+        ColumnType::Text | ColumnType::Numeric | ColumnType::Enum(_) => {
+            arguments.add(None::<String>)
+        }
+        ColumnType::Integer => arguments.add(None::<i64>),
+        ColumnType::Float => arguments.add(None::<f64>),
+        ColumnType::Boolean => arguments.add(None::<bool>),
+        ColumnType::Uuid => arguments.add(None::<Uuid>),
+        ColumnType::Timestamp => arguments.add(None::<DateTime<Utc>>),
+        ColumnType::Date => arguments.add(None::<NaiveDate>),
+        ColumnType::Json => arguments.add(None::<Value>),
+        ColumnType::Array(ArrayType::Text) => arguments.add(None::<Vec<String>>),
+        ColumnType::Array(ArrayType::Int) => arguments.add(None::<Vec<i32>>),
+        ColumnType::Array(ArrayType::BigInt) => arguments.add(None::<Vec<i64>>),
+        ColumnType::Array(ArrayType::Float) => arguments.add(None::<Vec<f64>>),
+        ColumnType::Array(ArrayType::Bool) => arguments.add(None::<Vec<bool>>),
+        ColumnType::Array(ArrayType::Uuid) => arguments.add(None::<Vec<Uuid>>),
+    }
+}
+
+// MySQL and SQLite have no array type: arrays, only used by Postgres-specific
+// conditions, are bound as JSON. Their columns accept a NULL of any type.
+#[cfg(any(feature = "mysql", feature = "sqlite", test))]
+macro_rules! impl_backend_without_arrays {
+    ($database:ty, $db_type:expr, $last_insert_id:expr) => {
+        impl Backend for $database {
+            const DB_TYPE: DbType = $db_type;
+
+            fn bind(
+                arguments: &mut Self::Arguments<'_>,
+                value: SqlValue,
+            ) -> Result<(), BoxDynError> {
+                match value {
+                    SqlValue::Null(_) => arguments.add(None::<String>),
+                    SqlValue::Bool(b) => arguments.add(b),
+                    SqlValue::Int(i) => arguments.add(i),
+                    SqlValue::Float(f) => arguments.add(f),
+                    SqlValue::Numeric(s) | SqlValue::Text(s) | SqlValue::Enum(_, s) => {
+                        arguments.add(s)
+                    }
+                    SqlValue::Uuid(uuid) => arguments.add(uuid),
+                    SqlValue::Timestamp(timestamp) => arguments.add(timestamp),
+                    SqlValue::Date(date) => arguments.add(date),
+                    SqlValue::Json(value) => arguments.add(sqlx::types::Json(value)),
+                    array => arguments.add(sqlx::types::Json(Value::from(array))),
+                }
+            }
+
+            fn rows_affected(result: &Self::QueryResult) -> u64 {
+                result.rows_affected()
+            }
+
+            fn last_insert_id(result: &Self::QueryResult) -> Option<i64> {
+                let last_insert_id: fn(&Self::QueryResult) -> Option<i64> = $last_insert_id;
+                last_insert_id(result)
+            }
+
+            fn get_i64(row: &Self::Row, index: usize) -> Result<i64, sqlx::Error> {
+                row.try_get(index)
+            }
+        }
+    };
+}
+
+#[cfg(any(feature = "mysql", test))]
+impl_backend_without_arrays!(sqlx::MySql, DbType::MySQL, |result| {
+    i64::try_from(result.last_insert_id())
+        .ok()
+        .filter(|id| *id > 0)
+});
+
+#[cfg(any(feature = "sqlite", test))]
+impl_backend_without_arrays!(sqlx::Sqlite, DbType::SQLite, |result| {
+    Some(result.last_insert_rowid()).filter(|id| *id > 0)
+});
@@ -0,0 +1,78 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::core::{base::generic_repository::entry_trait::Entry, errors::errors::ApiError};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Position in a keyset-paginated result set.
+///
+/// Holds the values of the sort columns (always ending with `id`) of the row the
+/// page starts after. `backward` is set for cursors pointing to the previous page.
+/// Cursors are handed to clients as opaque, HMAC-signed tokens so they cannot be forged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cursor {
+    pub values: Vec<Value>,
+    #[serde(default)]
+    pub backward: bool,
+}
+
+impl Cursor {
+    /// Builds a cursor from the values of `columns` in the given entry.
+    pub fn from_entry<T: Entry>(
+        entry: &T,
+        columns: &[String],
+        backward: bool,
+    ) -> Result<Self, ApiError> {
+        let entry_json = serde_json::to_value(entry).map_err(ApiError::Serialization)?;
+        let values = columns
+            .iter()
+            .map(|col| entry_json.get(col).cloned().unwrap_or(Value::Null))
+            .collect();
+
+        Ok(Self { values, backward })
+    }
+
+    /// Encodes the cursor as `<payload>.<signature>`, both base64url.
+    pub fn encode(&self, secret: &[u8]) -> Result<String, ApiError> {
+        let payload = serde_json::to_vec(self).map_err(ApiError::Serialization)?;
+        let payload = URL_SAFE_NO_PAD.encode(payload);
+        let signature = new_mac(payload.as_bytes(), secret)?.finalize().into_bytes();
+        let signature = URL_SAFE_NO_PAD.encode(signature);
+
+        Ok(format!("{}.{}", payload, signature))
+    }
+
+    /// Decodes a token produced by `encode`, rejecting it if the signature does not match.
+    pub fn decode(token: &str, secret: &[u8]) -> Result<Self, ApiError> {
+        let invalid = || ApiError::BadRequest("Invalid cursor".to_string());
+
+        let (payload, signature) = token.split_once('.').ok_or_else(invalid)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+
+        new_mac(payload.as_bytes(), secret)?
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+
+        let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| invalid())?;
+        serde_json::from_slice(&payload).map_err(|_| invalid())
+    }
+}
+
+fn new_mac(payload: &[u8], secret: &[u8]) -> Result<HmacSha256, ApiError> {
+    let mut mac =
+        HmacSha256::new_from_slice(secret).map_err(|e| ApiError::InternalServer(e.to_string()))?;
+    mac.update(payload);
+    Ok(mac)
+}
+
+/// A page of results fetched with keyset pagination.
+#[derive(Debug, Serialize)]
+pub struct CursorPage<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
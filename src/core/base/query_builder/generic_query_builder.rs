@@ -1,9 +1,10 @@
 use sqlx::{Database, Pool, FromRow};
 use std::marker::PhantomData;
 use crate::core::base::query_builder::parameterizedQuery::ParameterizedQuery;
+use crate::core::errors::errors::ApiError;
 
 // Enum to handle differences between DBs
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum DbType {
     Postgres,
     MySQL,
@@ -18,6 +19,42 @@ impl DbType {
             Self::MySQL | Self::SQLite => "?".to_string(),
         }
     }
+
+    // INSERT/UPDATE/DELETE ... RETURNING (SQLite since 3.35, not MySQL)
+    pub fn supports_returning(&self) -> bool {
+        match self {
+            Self::Postgres | Self::SQLite => true,
+            Self::MySQL => false,
+        }
+    }
+
+    // Maximum number of bind parameters in one statement
+    pub fn max_bind_params(&self) -> usize {
+        match self {
+            Self::Postgres | Self::MySQL => 65_535,
+            Self::SQLite => 32_766,
+        }
+    }
+
+    // LIMIT standing for "no limit", as MySQL and SQLite need one before OFFSET
+    pub fn unbounded_limit(&self) -> Option<&'static str> {
+        match self {
+            Self::Postgres => None,
+            Self::MySQL => Some("18446744073709551615"),
+            Self::SQLite => Some("-1"),
+        }
+    }
+
+    // Features that only exist in Postgres are rejected when building for another database
+    pub fn require_postgres(&self, feature: &str) -> Result<(), ApiError> {
+        if *self != Self::Postgres {
+            return Err(ApiError::InvalidQuery(format!(
+                "{} is only supported on Postgres",
+                feature
+            )));
+        }
+        Ok(())
+    }
 }
 
 // Simple QueryBuilder for raw SQL
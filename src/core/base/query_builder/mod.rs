@@ -1,4 +1,16 @@
+pub mod backend;
+pub mod cursor;
+pub mod generic_query_builder;
+pub mod postgres;
 pub mod query_builder;
+pub mod query_filter;
 pub mod query_models;
-mod generic_query_builder;
-mod parameterizedQuery;
\ No newline at end of file
+pub mod relation;
+pub mod returning;
+pub mod search;
+pub mod sql_writer;
+pub mod stream;
+pub mod trace;
+mod parameterizedQuery;
+#[cfg(test)]
+mod tests;
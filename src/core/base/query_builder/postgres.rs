@@ -0,0 +1,616 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+use sqlx::{Acquire, FromRow, PgConnection, Postgres, postgres::PgRow};
+
+use crate::core::{
+    base::{
+        generic_repository::entry_trait::Entry,
+        query_builder::{
+            generic_query_builder::DbType,
+            query_builder::QueryBuilderUtil,
+            query_models::{ArrayType, QueryResult},
+            relation::{Relation, RelationKind, WithRelations},
+            sql_writer::{ConnectionSource, SqlWriter},
+        },
+    },
+    errors::errors::ApiError,
+};
+
+// Postgres-only statements: EXPLAIN, joined rows and relations, which rely on jsonb
+impl<T: Entry + Send + Sync + Unpin + 'static> QueryBuilderUtil<T, Postgres> {
+    /// Plan Postgres chooses for the SELECT, as returned by `EXPLAIN (FORMAT JSON)`.
+    pub async fn explain<'c, E>(&self, executor: E) -> QueryResult<Value>
+    where
+        E: ConnectionSource<'c, Postgres>,
+    {
+        self.fetch_plan("EXPLAIN (FORMAT JSON) ", executor).await
+    }
+
+    /// Like `explain`, but runs the SELECT and adds actual timings and row counts.
+    pub async fn explain_analyze<'c, E>(&self, executor: E) -> QueryResult<Value>
+    where
+        E: ConnectionSource<'c, Postgres>,
+    {
+        self.fetch_plan("EXPLAIN (ANALYZE, FORMAT JSON) ", executor)
+            .await
+    }
+
+    async fn fetch_plan<'c, E>(&self, prefix: &str, executor: E) -> QueryResult<Value>
+    where
+        E: ConnectionSource<'c, Postgres>,
+    {
+        self.check_tenant_scope()?;
+        let mut query_builder = SqlWriter::new(DbType::Postgres, prefix);
+        self.push_select_query(&mut query_builder);
+
+        let plan: (Value,) = query_builder
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .fetch_one(executor)
+            .await?;
+
+        Ok(plan.0)
+    }
+
+    /// Fetches each row of this table with the matching row of the joined `O` table.
+    /// Example: inner_join::<Order>("id", "user_id")?.fetch_joined::<Order, _>(&pool)
+    pub async fn fetch_joined<'c, O, E>(&self, executor: E) -> QueryResult<Vec<(T, O)>>
+    where
+        O: Entry,
+        E: ConnectionSource<'c, Postgres>,
+    {
+        let rows = self.fetch_joined_rows::<O, E>(executor).await?;
+        rows.into_iter()
+            .map(|(entry, joined)| {
+                let joined = joined.ok_or_else(|| {
+                    ApiError::InvalidQuery(format!(
+                        "No {} row joined, use fetch_left_joined for outer joins",
+                        O::table_name()
+                    ))
+                })?;
+                Ok((entry, joined))
+            })
+            .collect()
+    }
+
+    /// Same as `fetch_joined` for outer joins, where the joined row may be missing.
+    pub async fn fetch_left_joined<'c, O, E>(&self, executor: E) -> QueryResult<Vec<(T, Option<O>)>>
+    where
+        O: Entry,
+        E: ConnectionSource<'c, Postgres>,
+    {
+        self.fetch_joined_rows::<O, E>(executor).await
+    }
+
+    // Each side is selected as a whole-row jsonb value, which keeps same-named columns
+    // (id, created_at...) of both tables apart
+    async fn fetch_joined_rows<'c, O, E>(&self, executor: E) -> QueryResult<Vec<(T, Option<O>)>>
+    where
+        O: Entry,
+        E: ConnectionSource<'c, Postgres>,
+    {
+        if !self.joins.iter().any(|join| join.table == O::table_name()) {
+            return Err(ApiError::InvalidQuery(format!(
+                "Table {} is not joined",
+                O::table_name()
+            )));
+        }
+
+        let select_list = format!(
+            "to_jsonb({}.*), to_jsonb({}.*)",
+            T::table_name(),
+            O::table_name()
+        );
+        self.check_tenant_scope()?;
+        let mut query_builder = SqlWriter::new(DbType::Postgres, "");
+        self.push_select_query_with(&select_list, &mut query_builder);
+
+        let rows: Vec<(Option<Value>, Option<Value>)> = query_builder
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .fetch_all(executor)
+            .await?;
+
+        rows.into_iter()
+            .map(|(entry, joined)| {
+                // Only right and full outer joins yield rows without this table's side
+                let entry = entry.ok_or_else(|| {
+                    ApiError::InvalidQuery(format!(
+                        "No {} row, use fetch_all_as for right or full joins",
+                        T::table_name()
+                    ))
+                })?;
+                let entry = serde_json::from_value(entry).map_err(ApiError::Serialization)?;
+                let joined = joined
+                    .map(serde_json::from_value)
+                    .transpose()
+                    .map_err(ApiError::Serialization)?;
+                Ok((entry, joined))
+            })
+            .collect()
+    }
+
+    /// Fetches the entries with the relations named by `with`. Each relation is loaded
+    /// with one query joining `unnest($1)` on the same connection.
+    pub async fn fetch_all_with_relations<'c, A>(
+        &self,
+        conn: A,
+    ) -> QueryResult<Vec<WithRelations<T>>>
+    where
+        T: for<'r> FromRow<'r, PgRow>,
+        A: Acquire<'c, Database = Postgres> + Send,
+    {
+        let mut conn = conn.acquire().await?;
+        let entries = self
+            .select_writer()?
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .fetch_all::<Postgres, _, _>(&mut *conn)
+            .await?;
+        self.load_relations(entries, &mut conn).await
+    }
+
+    pub async fn fetch_optional_with_relations<'c, A>(
+        &self,
+        conn: A,
+    ) -> QueryResult<Option<WithRelations<T>>>
+    where
+        T: for<'r> FromRow<'r, PgRow>,
+        A: Acquire<'c, Database = Postgres> + Send,
+    {
+        let mut conn = conn.acquire().await?;
+        let entry = self
+            .select_writer()?
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .fetch_optional::<Postgres, _, _>(&mut *conn)
+            .await?;
+        let loaded = self
+            .load_relations(entry.into_iter().collect(), &mut conn)
+            .await?;
+        Ok(loaded.into_iter().next())
+    }
+
+    /// Attaches the relations named by `with` to entries fetched separately, e.g. a page.
+    pub async fn load_relations(
+        &self,
+        entries: Vec<T>,
+        conn: &mut PgConnection,
+    ) -> QueryResult<Vec<WithRelations<T>>> {
+        self.check_tenant_scope()?;
+        let rows = entries
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(ApiError::Serialization)?;
+        let mut loaded: Vec<WithRelations<T>> = entries
+            .into_iter()
+            .map(|entry| WithRelations {
+                entry,
+                relations: HashMap::new(),
+            })
+            .collect();
+
+        for relation in &self.relations {
+            // Distinct keys of the entries, and the position of each entry's key. The
+            // keys all come from the entries, so their JSON forms can be compared.
+            let mut keys: Vec<Value> = Vec::new();
+            let mut positions: HashMap<String, usize> = HashMap::new();
+            let key_positions: Vec<Option<usize>> = rows
+                .iter()
+                .map(|row| {
+                    let key = row
+                        .get(relation.local_column::<T>())
+                        .filter(|key| !key.is_null())?;
+                    let position = *positions.entry(key.to_string()).or_insert_with(|| {
+                        keys.push(key.clone());
+                        keys.len() - 1
+                    });
+                    Some(position)
+                })
+                .collect();
+            let related = if keys.is_empty() {
+                HashMap::new()
+            } else {
+                self.fetch_related(relation, &keys, &mut *conn).await?
+            };
+
+            for (position, item) in key_positions.into_iter().zip(loaded.iter_mut()) {
+                let matching = position
+                    .and_then(|position| related.get(&position))
+                    .cloned();
+                let value = match relation.kind {
+                    RelationKind::BelongsTo => matching
+                        .and_then(|rows| rows.into_iter().next())
+                        .unwrap_or(Value::Null),
+                    _ => Value::Array(matching.unwrap_or_default()),
+                };
+                item.relations.insert(relation.name.to_string(), value);
+            }
+        }
+
+        Ok(loaded)
+    }
+
+    // Related rows as jsonb, grouped by the position in `keys` of the key they match.
+    // Postgres compares the keys and returns the position: the JSON forms of the two
+    // sides may differ for equal keys (e.g. `to_jsonb` of a jsonb or numeric column).
+    async fn fetch_related(
+        &self,
+        relation: &Relation,
+        keys: &[Value],
+        conn: &mut PgConnection,
+    ) -> QueryResult<HashMap<usize, Vec<Value>>> {
+        let mut query_builder =
+            SqlWriter::new(DbType::Postgres, "SELECT entry_keys.position, to_jsonb(");
+        query_builder
+            .push(relation.table)
+            .push(".*) FROM ")
+            .push(relation.table);
+        if let RelationKind::ManyToMany { pivot_table, .. } = relation.kind {
+            query_builder.push(format_args!(
+                " INNER JOIN {} ON {}.{} = {}.{}",
+                pivot_table,
+                pivot_table,
+                relation.foreign_key,
+                relation.table,
+                relation.primary_key
+            ));
+        }
+        query_builder.push(" INNER JOIN unnest(");
+        let array_type = Self::key_array_type(relation).unwrap_or(ArrayType::Text);
+        self.bind_array(&mut query_builder, array_type, Some(keys));
+        query_builder
+            .push(") WITH ORDINALITY AS entry_keys(key, position) ON ")
+            .push(relation.key_column())
+            .push(" = entry_keys.key");
+        if let Some(column) = relation.soft_delete_column {
+            query_builder.push(format_args!(" AND {}.{} IS NULL", relation.table, column));
+        }
+        self.push_tenant_condition(relation.table, relation.tenant_column, &mut query_builder);
+        query_builder.push(format_args!(
+            " ORDER BY {}.{}",
+            relation.table, relation.primary_key
+        ));
+
+        let rows: Vec<(i64, Value)> = query_builder
+            .into_statement()
+            .with_table(relation.table)
+            .with_timeout(self.timeout)
+            .fetch_all::<Postgres, _, _>(conn)
+            .await?;
+
+        let mut related: HashMap<usize, Vec<Value>> = HashMap::new();
+        for (position, row) in rows {
+            // Positions start at 1
+            related.entry(position as usize - 1).or_default().push(row);
+        }
+        Ok(related)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use serde_json::{Value, json};
+    use sqlx::{Connection, PgConnection, Postgres, postgres::PgPoolOptions};
+
+    use crate::{
+        core::{
+            base::{
+                generic_repository::{
+                    entry_trait::Entry,
+                    tenant::{TenantContext, with_tenant},
+                    test_support::{Comment, Note, pg_transaction, pg_url},
+                },
+                query_builder::{
+                    generic_query_builder::DbType,
+                    query_builder::{COPY_THRESHOLD, QueryBuilderUtil},
+                    sql_writer::{ConnectionSource, SqlWriter},
+                },
+            },
+            errors::errors::ApiError,
+        },
+        db::models::{role::Role, user::User},
+    };
+
+    async fn insert_note(conn: &mut PgConnection, title: &str) -> i64 {
+        sqlx::query_scalar("INSERT INTO notes (title, body) VALUES ($1, '') RETURNING id")
+            .bind(title)
+            .fetch_one(conn)
+            .await
+            .unwrap()
+    }
+
+    async fn insert_comment(conn: &mut PgConnection, note_id: i64, tenant_id: &str, body: &str) {
+        sqlx::query("INSERT INTO comments (note_id, tenant_id, body) VALUES ($1, $2, $3)")
+            .bind(note_id)
+            .bind(tenant_id)
+            .bind(body)
+            .execute(conn)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn belongs_to_loads_the_referenced_row() {
+        let mut tx = pg_transaction().await;
+        let note_id = insert_note(&mut tx, "first").await;
+        insert_comment(&mut tx, note_id, "t1", "one").await;
+        insert_comment(&mut tx, note_id, "t1", "two").await;
+        insert_comment(&mut tx, note_id + 1, "t1", "orphan").await;
+
+        let comments = with_tenant(TenantContext::new("t1"), async {
+            QueryBuilderUtil::<Comment, Postgres>::new()
+                .with("note")
+                .unwrap()
+                .order_by_asc("id")
+                .unwrap()
+                .fetch_all_with_relations(&mut *tx)
+                .await
+                .unwrap()
+        })
+        .await;
+
+        let notes: Vec<Option<String>> = comments
+            .iter()
+            .map(|comment| comment.one::<Note>("note").unwrap().map(|note| note.title))
+            .collect();
+        assert_eq!(
+            notes,
+            [Some("first".to_string()), Some("first".to_string()), None]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn has_many_skips_deleted_rows_and_other_tenants() {
+        let mut tx = pg_transaction().await;
+        let first = insert_note(&mut tx, "first").await;
+        insert_note(&mut tx, "second").await;
+        insert_comment(&mut tx, first, "t1", "kept").await;
+        insert_comment(&mut tx, first, "t1", "deleted").await;
+        insert_comment(&mut tx, first, "t2", "other tenant").await;
+        sqlx::query("UPDATE comments SET deleted_at = NOW() WHERE body = 'deleted'")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+
+        let notes = with_tenant(TenantContext::new("t1"), async {
+            QueryBuilderUtil::<Note, Postgres>::new()
+                .with("comments")
+                .unwrap()
+                .order_by_asc("id")
+                .unwrap()
+                .fetch_all_with_relations(&mut *tx)
+                .await
+                .unwrap()
+        })
+        .await;
+
+        let comments: Vec<Vec<String>> = notes
+            .iter()
+            .map(|note| {
+                let comments = note.many::<Comment>("comments").unwrap();
+                comments.into_iter().map(|comment| comment.body).collect()
+            })
+            .collect();
+        assert_eq!(comments, [vec!["kept".to_string()], vec![]]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn many_to_many_matches_uuid_keys() {
+        let mut tx = pg_transaction().await;
+        sqlx::query(
+            "INSERT INTO users (username, email, password_hash)
+             VALUES ('alice', 'alice@example.com', ''), ('bob', 'bob@example.com', '')",
+        )
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO roles (name) VALUES ('admin'), ('editor')")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO user_roles (user_id, role_id)
+             SELECT users.id, roles.id FROM users, roles WHERE users.username = 'alice'",
+        )
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+
+        let users = QueryBuilderUtil::<User, Postgres>::new()
+            .with("roles")
+            .unwrap()
+            .order_by_asc("username")
+            .unwrap()
+            .fetch_all_with_relations(&mut *tx)
+            .await
+            .unwrap();
+
+        let roles: Vec<Vec<String>> = users
+            .iter()
+            .map(|user| {
+                let mut roles: Vec<String> = user
+                    .many::<Role>("roles")
+                    .unwrap()
+                    .into_iter()
+                    .map(|role| role.name)
+                    .collect();
+                roles.sort();
+                roles
+            })
+            .collect();
+        assert_eq!(
+            roles,
+            [vec!["admin".to_string(), "editor".to_string()], vec![]]
+        );
+    }
+
+    fn note_rows(count: usize) -> Vec<HashMap<String, Value>> {
+        (0..count)
+            .map(|i| {
+                HashMap::from([
+                    ("title".to_string(), Value::from(format!("note {}", i))),
+                    ("body".to_string(), Value::from("")),
+                ])
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn insert_many_returning_copies_large_batches() {
+        let mut tx = pg_transaction().await;
+
+        let notes = QueryBuilderUtil::<Note, Postgres>::new()
+            .values_many(note_rows(COPY_THRESHOLD))
+            .unwrap()
+            .insert_many_returning(&mut tx)
+            .await
+            .unwrap();
+
+        assert_eq!(notes.len(), COPY_THRESHOLD);
+        // Dropped once the rows are moved, before the end of the test transaction
+        let staging_tables: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pg_tables
+             WHERE schemaname LIKE 'pg_temp%' AND tablename LIKE 'notes_copy_%'",
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+        assert_eq!(staging_tables, 0);
+    }
+
+    /// Entry with an array column.
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, sqlx::FromRow, Entry)]
+    #[entry(table = "tagged_notes")]
+    struct TaggedNote {
+        #[entry(primary_key)]
+        id: i64,
+        tags: Vec<String>,
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn array_columns_are_inserted_without_copy() {
+        let mut tx = pg_transaction().await;
+        sqlx::query("CREATE TABLE tagged_notes (id BIGSERIAL PRIMARY KEY, tags TEXT[] NOT NULL)")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        let rows: Vec<HashMap<String, Value>> = (0..COPY_THRESHOLD)
+            .map(|i| {
+                HashMap::from([(
+                    "tags".to_string(),
+                    json!([format!("tag {}", i), "a, \"b\""]),
+                )])
+            })
+            .collect();
+        let query = QueryBuilderUtil::<TaggedNote, Postgres>::new()
+            .values_many(rows)
+            .unwrap();
+
+        let copied = query.copy_insert_returning(&mut tx).await;
+        assert!(matches!(copied, Err(ApiError::InvalidQuery(_))));
+
+        let notes = query.insert_many_returning(&mut tx).await.unwrap();
+        assert_eq!(notes.len(), COPY_THRESHOLD);
+        assert_eq!(notes[1].tags, ["tag 1", "a, \"b\""]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn copy_outside_a_transaction_is_refused() {
+        let url = pg_url();
+        let mut conn = PgConnection::connect(&url).await.unwrap();
+
+        let result = QueryBuilderUtil::<Note, Postgres>::new()
+            .values_many(note_rows(1))
+            .unwrap()
+            .copy_insert_returning(&mut conn)
+            .await;
+
+        assert!(matches!(result, Err(ApiError::InvalidQuery(_))));
+    }
+
+    async fn sleep_for(
+        seconds: u32,
+        marker: &str,
+        conn: impl ConnectionSource<'_, Postgres>,
+    ) -> Result<(), ApiError> {
+        SqlWriter::new(
+            DbType::Postgres,
+            format!("SELECT pg_sleep({}), '{}'", seconds, marker),
+        )
+        .into_statement()
+        .with_timeout(Some(Duration::from_millis(100)))
+        .execute::<Postgres, _>(conn)
+        .await
+        .map(|_| ())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn a_timed_out_statement_is_cancelled_on_the_server() {
+        let url = pg_url();
+        let pool = PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&url)
+            .await
+            .unwrap();
+        let marker = format!("sleep_{}", uuid::Uuid::new_v4().simple());
+
+        let result = sleep_for(5, &marker, &pool).await;
+        assert!(matches!(result, Err(ApiError::QueryTimeout(_))));
+
+        // Checked on another connection, as the pool's one would wait for the statement
+        let mut observer = PgConnection::connect(&url).await.unwrap();
+        let running: i64 = sqlx::query_scalar(
+            "SELECT count(*) FROM pg_stat_activity WHERE state = 'active' AND query LIKE $1 \
+             AND pid <> pg_backend_pid()",
+        )
+        .bind(format!("%{}%", marker))
+        .fetch_one(&mut observer)
+        .await
+        .unwrap();
+        assert_eq!(running, 0);
+        // The pool's connection is back, with its own statement_timeout
+        let timeout: String = sqlx::query_scalar("SHOW statement_timeout")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_ne!(timeout, "100ms");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn a_timed_out_statement_leaves_its_transaction_usable() {
+        let url = pg_url();
+        let mut conn = PgConnection::connect(&url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+        sqlx::query("SET LOCAL statement_timeout = '7s'")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+
+        sleep_for(0, "quick", &mut *tx).await.unwrap();
+        let result = sleep_for(5, "slow", &mut *tx).await;
+        assert!(matches!(result, Err(ApiError::QueryTimeout(_))));
+
+        let timeout: String = sqlx::query_scalar("SHOW statement_timeout")
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+        assert_eq!(timeout, "7s");
+    }
+}
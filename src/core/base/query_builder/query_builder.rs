@@ -1,35 +1,83 @@
+use futures_util::Stream;
 use serde_json::Value;
-use sqlx::{Pool, Postgres, QueryBuilder};
-use std::collections::HashMap;
+use sqlx::{
+    Acquire, Connection, Executor, FromRow, Pool, Postgres, QueryBuilder, TransactionManager,
+};
+use std::{borrow::Cow, collections::HashMap, marker::PhantomData, sync::Arc, time::Duration};
+use tracing::Instrument;
 
 use crate::core::{
     base::{
-        generic_repository::entry_trait::Entry,
-        query_builder::query_models::{
-            ComparisonOperator, JoinClause, JoinType, LogicalOperator, OrderBy, OrderDirection,
-            QueryResult, WhereClause, WhereCondition, WhereGroup,
+        generic_repository::{
+            entry_trait::{ColumnType, Entry},
+            tenant::TenantScope,
+        },
+        query_builder::{
+            backend::{Backend, SqlValue},
+            cursor::{Cursor, CursorPage},
+            generic_query_builder::DbType,
+            query_models::{
+                Agg, ArrayElement, ArrayType, ColumnComparison, ComparisonOperator, ConflictAction,
+                GroupBy, JoinClause, JoinType, Keyset, LogicalOperator, OnConflict, OrderBy,
+                OrderDirection, QueryResult, SqlExpr, SqlPart, StatementKind, Subquery,
+                SubqueryCondition, SubqueryOperator, TenantColumn, TextSearch, TimeBucket,
+                TrashedScope, WhereClause, WhereCondition, WhereGroup,
+            },
+            relation::{Relation, RelationKind},
+            search::{HEADLINE_ALIAS, RANK_ALIAS},
+            sql_writer::{ConnectionSource, SqlWriter, Statement, TimedConnection, with_timeout},
+            stream::{RowSender, row_stream, send_rows},
+            trace::QueryTrace,
         },
     },
     errors::errors::ApiError,
+    utils::csv::csv_line,
 };
 
+/// Number of rows from which `insert_many_returning` switches from INSERT statements to
+/// COPY (Postgres).
+pub const COPY_THRESHOLD: usize = 5_000;
+
+/// Builds and runs the statements of an entry's table.
+///
+/// `DB` is the database the SQL is written for, Postgres by default; MySQL and SQLite
+/// are available with the `mysql` and `sqlite` features. Postgres-only conditions
+/// (full-text search, trigram similarity, arrays, JSONB) fail with
+/// `ApiError::InvalidQuery` on the other databases.
 #[derive(Debug)]
-pub struct QueryBuilderUtil<T: Entry> {
+pub struct QueryBuilderUtil<T: Entry, DB: Backend = Postgres> {
     pub(crate) where_clauses: Vec<(WhereClause, Option<LogicalOperator>)>,
     pub(crate) order_by: Vec<OrderBy>,
     pub(crate) joins: Vec<JoinClause>,
     pub(crate) limit: Option<u32>,
     pub(crate) offset: Option<u32>,
-    pub(crate) group_by: Vec<String>,
+    pub(crate) keyset: Option<Keyset>,
+    pub(crate) group_by: Vec<GroupBy>,
     pub(crate) having: Vec<WhereCondition>,
+    pub(crate) aggregates: Vec<Agg>,
     pub(crate) distinct: bool,
     pub(crate) select_columns: Option<Vec<String>>,
+    pub(crate) select_exprs: Vec<(SqlExpr, String)>,
     pub(crate) update_data: HashMap<String, Value>,
+    pub(crate) update_exprs: Vec<(String, SqlExpr)>,
     pub(crate) insert_data: HashMap<String, Value>,
-    _phantom: std::marker::PhantomData<T>,
+    pub(crate) insert_rows: Vec<HashMap<String, Value>>,
+    pub(crate) on_conflict: Option<OnConflict>,
+    pub(crate) trashed: TrashedScope,
+    pub(crate) relations: Vec<Relation>,
+    pub(crate) timeout: Option<Duration>,
+    /// Captured from the task when the builder is created
+    pub(crate) tenant: Option<TenantScope>,
+    _phantom: PhantomData<(T, fn() -> DB)>,
+}
+
+impl<T: Entry + Send + Sync + Unpin + 'static, DB: Backend> Default for QueryBuilderUtil<T, DB> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl<T: Entry + Send + Sync + Unpin + 'static> QueryBuilderUtil<T> {
+impl<T: Entry + Send + Sync + Unpin + 'static, DB: Backend> QueryBuilderUtil<T, DB> {
     pub fn new() -> Self {
         Self {
             where_clauses: Vec::new(),
@@ -37,13 +85,23 @@ impl<T: Entry + Send + Sync + Unpin + 'static> QueryBuilderUtil<T> {
             joins: Vec::new(),
             limit: None,
             offset: None,
+            keyset: None,
             group_by: Vec::new(),
             having: Vec::new(),
+            aggregates: Vec::new(),
             distinct: false,
             select_columns: None,
+            select_exprs: Vec::new(),
             update_data: HashMap::new(),
+            update_exprs: Vec::new(),
             insert_data: HashMap::new(),
-            _phantom: std::marker::PhantomData,
+            insert_rows: Vec::new(),
+            on_conflict: None,
+            trashed: TrashedScope::Exclude,
+            relations: Vec::new(),
+            timeout: None,
+            tenant: TenantScope::current(),
+            _phantom: PhantomData,
         }
     }
 
@@ -240,6 +298,122 @@ impl<T: Entry + Send + Sync + Unpin + 'static> QueryBuilderUtil<T> {
         Ok(self)
     }
 
+    // ========== SUBQUERIES ==========
+
+    /// Example: where_in_subquery("id", QueryBuilderUtil::<Order>::new().select(["user_id"])?)
+    /// Result: WHERE id IN (SELECT user_id FROM orders)
+    pub fn where_in_subquery<U>(
+        self,
+        column: &str,
+        subquery: QueryBuilderUtil<U, DB>,
+    ) -> Result<Self, ApiError>
+    where
+        U: Entry + Send + Sync + Unpin + 'static,
+    {
+        self.push_in_subquery(column, SubqueryOperator::In, subquery)
+    }
+
+    pub fn where_not_in_subquery<U>(
+        self,
+        column: &str,
+        subquery: QueryBuilderUtil<U, DB>,
+    ) -> Result<Self, ApiError>
+    where
+        U: Entry + Send + Sync + Unpin + 'static,
+    {
+        self.push_in_subquery(column, SubqueryOperator::NotIn, subquery)
+    }
+
+    /// Example: where_exists(QueryBuilderUtil::<Order>::new().where_outer_eq::<User>("user_id", "id")?)
+    /// Result: WHERE EXISTS (SELECT ... FROM orders WHERE orders.user_id = users.id)
+    pub fn where_exists<U>(mut self, subquery: QueryBuilderUtil<U, DB>) -> Result<Self, ApiError>
+    where
+        U: Entry + Send + Sync + Unpin + 'static,
+    {
+        subquery.check_tenant_scope()?;
+        self.where_clauses.push((
+            WhereClause::Subquery(SubqueryCondition {
+                column: None,
+                operator: SubqueryOperator::Exists,
+                query: Arc::new(subquery),
+            }),
+            None,
+        ));
+        Ok(self)
+    }
+
+    pub fn where_not_exists<U>(
+        mut self,
+        subquery: QueryBuilderUtil<U, DB>,
+    ) -> Result<Self, ApiError>
+    where
+        U: Entry + Send + Sync + Unpin + 'static,
+    {
+        subquery.check_tenant_scope()?;
+        self.where_clauses.push((
+            WhereClause::Subquery(SubqueryCondition {
+                column: None,
+                operator: SubqueryOperator::NotExists,
+                query: Arc::new(subquery),
+            }),
+            None,
+        ));
+        Ok(self)
+    }
+
+    /// Correlates a subquery with its outer query, both columns being validated.
+    /// Example (inside a QueryBuilderUtil<Order>): where_outer_eq::<User>("user_id", "id")
+    /// Result: orders.user_id = users.id
+    pub fn where_outer_eq<O: Entry>(
+        mut self,
+        column: &str,
+        outer_column: &str,
+    ) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        if !O::columns().contains(&outer_column) {
+            return Err(ApiError::InvalidColumn(outer_column.to_string()));
+        }
+        let comparison = ColumnComparison {
+            left: format!("{}.{}", T::table_name(), column),
+            operator: ComparisonOperator::Equal,
+            right: format!("{}.{}", O::table_name(), outer_column),
+        };
+        self.where_clauses
+            .push((WhereClause::ColumnComparison(comparison), None));
+        Ok(self)
+    }
+
+    fn push_in_subquery<U>(
+        mut self,
+        column: &str,
+        operator: SubqueryOperator,
+        subquery: QueryBuilderUtil<U, DB>,
+    ) -> Result<Self, ApiError>
+    where
+        U: Entry + Send + Sync + Unpin + 'static,
+    {
+        self.validate_column(column)?;
+        if subquery
+            .select_columns
+            .as_ref()
+            .is_none_or(|columns| columns.len() != 1)
+        {
+            return Err(ApiError::InvalidQuery(
+                "An IN subquery must select exactly one column".to_string(),
+            ));
+        }
+        subquery.check_tenant_scope()?;
+        self.where_clauses.push((
+            WhereClause::Subquery(SubqueryCondition {
+                column: Some(column.to_string()),
+                operator,
+                query: Arc::new(subquery),
+            }),
+            None,
+        ));
+        Ok(self)
+    }
+
     // Logical operators
     pub fn and(mut self) -> Self {
         if let Some(last) = self.where_clauses.last_mut() {
@@ -262,9 +436,12 @@ impl<T: Entry + Send + Sync + Unpin + 'static> QueryBuilderUtil<T> {
     /// Result: WHERE (status = 'active' OR priority = 'high') AND ...
     pub fn where_group_and<F>(mut self, builder_fn: F) -> Result<Self, ApiError>
     where
-        F: FnOnce(GroupBuilder<T>) -> Result<GroupBuilder<T>, ApiError>,
+        F: FnOnce(GroupBuilder<T, DB>) -> Result<GroupBuilder<T, DB>, ApiError>,
     {
-        let group_builder = GroupBuilder::new();
+        let group_builder = GroupBuilder {
+            joins: self.joins.clone(),
+            ..GroupBuilder::new()
+        };
         let group_builder = builder_fn(group_builder)?;
 
         if !group_builder.clauses.is_empty() {
@@ -284,9 +461,12 @@ impl<T: Entry + Send + Sync + Unpin + 'static> QueryBuilderUtil<T> {
     /// Result: WHERE (status = 'draft' AND author_id = 123) OR ...
     pub fn where_group_or<F>(mut self, builder_fn: F) -> Result<Self, ApiError>
     where
-        F: FnOnce(GroupBuilder<T>) -> Result<GroupBuilder<T>, ApiError>,
+        F: FnOnce(GroupBuilder<T, DB>) -> Result<GroupBuilder<T, DB>, ApiError>,
     {
-        let group_builder = GroupBuilder::new();
+        let group_builder = GroupBuilder {
+            joins: self.joins.clone(),
+            ..GroupBuilder::new()
+        };
         let group_builder = builder_fn(group_builder)?;
 
         if !group_builder.clauses.is_empty() {
@@ -301,177 +481,1115 @@ impl<T: Entry + Send + Sync + Unpin + 'static> QueryBuilderUtil<T> {
         Ok(self)
     }
 
-    // Methods for ORDER BY
-    pub fn order_by(mut self, column: &str, direction: OrderDirection) -> Result<Self, ApiError> {
-        self.validate_column(column)?;
-        self.order_by.push(OrderBy {
-            column: column.to_string(),
-            direction,
-        });
+    /// Raw SQL condition; each `{}` is a placeholder for the next value, bound as a
+    /// `$n` parameter numbered with the rest of the query. The SQL text itself is not
+    /// validated and must never contain user input.
+    /// Example: where_raw("lower(email) = lower({})", ["John@Example.com"])
+    /// Result: WHERE (lower(email) = lower($1))
+    pub fn where_raw<I, V>(mut self, sql: &str, values: I) -> Result<Self, ApiError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        let expression = SqlExpr::raw(sql, values)?;
+        self.where_clauses
+            .push((WhereClause::Raw(expression), None));
         Ok(self)
     }
 
-    pub fn order_by_asc(self, column: &str) -> Result<Self, ApiError> {
-        self.order_by(column, OrderDirection::Asc)
+    // JSONB and array conditions
+
+    /// Example: where_json_contains("metadata", json!({"plan": "pro"}))
+    /// Result: WHERE metadata @> $1 (bound as jsonb)
+    pub fn where_json_contains(mut self, column: &str, document: Value) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition = WhereCondition::json(
+            DB::DB_TYPE,
+            column,
+            ComparisonOperator::JsonContains,
+            document,
+        )?;
+        self.where_clauses
+            .push((WhereClause::Condition(condition), None));
+        Ok(self)
     }
 
-    pub fn order_by_desc(self, column: &str) -> Result<Self, ApiError> {
-        self.order_by(column, OrderDirection::Desc)
+    pub fn where_json_contained_by(
+        mut self,
+        column: &str,
+        document: Value,
+    ) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition = WhereCondition::json(
+            DB::DB_TYPE,
+            column,
+            ComparisonOperator::JsonContainedBy,
+            document,
+        )?;
+        self.where_clauses
+            .push((WhereClause::Condition(condition), None));
+        Ok(self)
     }
 
-    // Methods for LIMIT and OFFSET
-    pub fn limit(mut self, limit: u32) -> Self {
-        self.limit = Some(limit);
-        self
+    /// Result: WHERE metadata ? $1
+    pub fn where_json_has_key(mut self, column: &str, key: &str) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition =
+            WhereCondition::json_keys(DB::DB_TYPE, column, ComparisonOperator::JsonHasKey, &[key])?;
+        self.where_clauses
+            .push((WhereClause::Condition(condition), None));
+        Ok(self)
     }
 
-    pub fn offset(mut self, offset: u32) -> Self {
-        self.offset = Some(offset);
-        self
+    /// Result: WHERE metadata ?| $1 (bound as text[])
+    pub fn where_json_has_any_key(mut self, column: &str, keys: &[&str]) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition = WhereCondition::json_keys(
+            DB::DB_TYPE,
+            column,
+            ComparisonOperator::JsonHasAnyKey,
+            keys,
+        )?;
+        self.where_clauses
+            .push((WhereClause::Condition(condition), None));
+        Ok(self)
     }
 
-    pub fn paginate(mut self, page: u32, page_size: u32) -> Self {
-        let offset = (page.saturating_sub(1)) * page_size;
-        self.limit = Some(page_size);
-        self.offset = Some(offset);
-        self
+    /// Result: WHERE metadata ?& $1 (bound as text[])
+    pub fn where_json_has_all_keys(
+        mut self,
+        column: &str,
+        keys: &[&str],
+    ) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition = WhereCondition::json_keys(
+            DB::DB_TYPE,
+            column,
+            ComparisonOperator::JsonHasAllKeys,
+            keys,
+        )?;
+        self.where_clauses
+            .push((WhereClause::Condition(condition), None));
+        Ok(self)
     }
 
-    // Methods for JOIN
-    pub fn inner_join(mut self, table: &str, on_condition: &str) -> Self {
-        self.joins.push(JoinClause {
-            join_type: JoinType::Inner,
-            table: table.to_string(),
-            on_condition: on_condition.to_string(),
-        });
-        self
+    /// Compares the text at `path` in a JSONB column.
+    /// Example: where_json_path("metadata", &["address", "city"], ComparisonOperator::Equal, Some("Paris"))
+    /// Result: WHERE (users.metadata#>>'{address,city}') = $1
+    pub fn where_json_path(
+        mut self,
+        column: &str,
+        path: &[&str],
+        operator: ComparisonOperator,
+        value: Option<&str>,
+    ) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition = WhereCondition::json_path(
+            DB::DB_TYPE,
+            &qualify::<T>(column),
+            path,
+            operator,
+            value.map(str::to_string),
+        )?;
+        self.where_clauses
+            .push((WhereClause::Condition(condition), None));
+        Ok(self)
     }
 
-    pub fn left_join(mut self, table: &str, on_condition: &str) -> Self {
-        self.joins.push(JoinClause {
-            join_type: JoinType::Left,
-            table: table.to_string(),
-            on_condition: on_condition.to_string(),
-        });
-        self
+    /// Example: where_json_path_eq("metadata", &["status"], "active")
+    /// Result: WHERE (users.metadata->>'status') = $1
+    pub fn where_json_path_eq(
+        self,
+        column: &str,
+        path: &[&str],
+        value: &str,
+    ) -> Result<Self, ApiError> {
+        self.where_json_path(column, path, ComparisonOperator::Equal, Some(value))
     }
 
-    pub fn right_join(mut self, table: &str, on_condition: &str) -> Self {
-        self.joins.push(JoinClause {
-            join_type: JoinType::Right,
-            table: table.to_string(),
-            on_condition: on_condition.to_string(),
-        });
-        self
+    /// Example: where_any("id", ids)
+    /// Result: WHERE id = ANY($1) (bound as one typed array, e.g. uuid[])
+    pub fn where_any<V: ArrayElement>(
+        mut self,
+        column: &str,
+        values: Vec<V>,
+    ) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition =
+            WhereCondition::array(DB::DB_TYPE, column, ComparisonOperator::AnyOf, values)?;
+        self.where_clauses
+            .push((WhereClause::Condition(condition), None));
+        Ok(self)
     }
 
-    pub fn full_outer_join(mut self, table: &str, on_condition: &str) -> Self {
-        self.joins.push(JoinClause {
-            join_type: JoinType::Full,
-            table: table.to_string(),
-            on_condition: on_condition.to_string(),
-        });
-        self
+    /// Result: WHERE tags && $1
+    pub fn where_array_overlaps<V: ArrayElement>(
+        mut self,
+        column: &str,
+        values: Vec<V>,
+    ) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition = WhereCondition::array(
+            DB::DB_TYPE,
+            column,
+            ComparisonOperator::ArrayOverlaps,
+            values,
+        )?;
+        self.where_clauses
+            .push((WhereClause::Condition(condition), None));
+        Ok(self)
     }
 
-    // Methods for GROUP BY and HAVING
-    pub fn group_by(mut self, column: &str) -> Result<Self, ApiError> {
+    /// Result: WHERE tags @> $1
+    pub fn where_array_contains<V: ArrayElement>(
+        mut self,
+        column: &str,
+        values: Vec<V>,
+    ) -> Result<Self, ApiError> {
         self.validate_column(column)?;
-        self.group_by.push(column.to_string());
+        let condition = WhereCondition::array(
+            DB::DB_TYPE,
+            column,
+            ComparisonOperator::ArrayContains,
+            values,
+        )?;
+        self.where_clauses
+            .push((WhereClause::Condition(condition), None));
         Ok(self)
     }
 
-    // Methods for DISTINCT and SELECT
-    pub fn distinct(mut self) -> Self {
-        self.distinct = true;
-        self
+    /// Raw ORDER BY expression with `{}` placeholders, see `where_raw`.
+    /// Example: order_by_raw("abs(score - {})", [50], OrderDirection::Asc)
+    pub fn order_by_raw<I, V>(
+        mut self,
+        sql: &str,
+        values: I,
+        direction: OrderDirection,
+    ) -> Result<Self, ApiError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        let expression = SqlExpr::raw(sql, values)?;
+        self.order_by.push(OrderBy {
+            column: sql.to_string(),
+            direction,
+            expression: Some(expression),
+        });
+        Ok(self)
     }
 
-    pub fn select(mut self, columns: Vec<&str>) -> Result<Self, ApiError> {
-        for column in &columns {
-            self.validate_column(column)?;
-        }
-        self.select_columns = Some(columns.iter().map(|&s| s.to_string()).collect());
+    /// Raw expression added to the selection as `alias`, with `{}` placeholders, see
+    /// `where_raw`. Read it with `fetch_all_as` into a type that has an `alias` field.
+    /// Example: select_raw("coalesce(nickname, {})", ["anonymous"], "display_name")
+    pub fn select_raw<I, V>(mut self, sql: &str, values: I, alias: &str) -> Result<Self, ApiError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        Self::validate_alias(alias)?;
+        let expression = SqlExpr::raw(sql, values)?;
+        self.select_exprs.push((expression, alias.to_string()));
         Ok(self)
     }
 
-    // Methods for UPDATE
-    pub fn set<V: Into<Value>>(mut self, column: &str, value: V) -> Result<Self, ApiError> {
+    // Methods for full-text and trigram search
+
+    /// Full-text search over the given columns.
+    /// Example: where_text_search(&TextSearch::new(["username", "email"], "john").config("english"))
+    /// Result: WHERE to_tsvector('english', coalesce(users.username, '') || ' ' || coalesce(users.email, '')) @@ websearch_to_tsquery('english', $1)
+    pub fn where_text_search(mut self, search: &TextSearch) -> Result<Self, ApiError> {
+        Self::require_postgres("where_text_search")?;
+        let (_, tsvector) = self.text_search_sql(search)?;
+        let condition = WhereCondition {
+            column: tsvector,
+            operator: ComparisonOperator::TextSearch(search.config.clone()),
+            value: Some(Value::String(search.query.clone())),
+            values: None,
+        };
+        self.where_clauses
+            .push((WhereClause::Condition(condition), None));
+        Ok(self)
+    }
+
+    /// Trigram similarity match (requires the pg_trgm extension).
+    /// Example: where_similar("username", "jhon")
+    /// Result: WHERE username % $1
+    pub fn where_similar(mut self, column: &str, value: &str) -> Result<Self, ApiError> {
+        Self::require_postgres("where_similar")?;
         self.validate_column(column)?;
-        self.update_data.insert(column.to_string(), value.into());
+        let condition = WhereCondition {
+            column: column.to_string(),
+            operator: ComparisonOperator::Similar,
+            value: Some(Value::String(value.to_string())),
+            values: None,
+        };
+        self.where_clauses
+            .push((WhereClause::Condition(condition), None));
         Ok(self)
     }
 
-    pub fn set_multiple(mut self, data: HashMap<String, Value>) -> Result<Self, ApiError> {
-        for column in data.keys() {
-            self.validate_column(column)?;
-        }
-        self.update_data.extend(data);
+    /// Orders by full-text relevance, best matches first.
+    /// Result: ORDER BY ts_rank(to_tsvector(...), websearch_to_tsquery(...)) DESC
+    pub fn order_by_rank(mut self, search: &TextSearch) -> Result<Self, ApiError> {
+        Self::require_postgres("order_by_rank")?;
+        let expression = self.ts_rank_expr(search)?;
+        self.order_by.push(OrderBy {
+            column: RANK_ALIAS.to_string(),
+            direction: OrderDirection::Desc,
+            expression: Some(expression),
+        });
         Ok(self)
     }
 
-    // Methods for INSERT
-    pub fn value<V: Into<Value>>(mut self, column: &str, value: V) -> Result<Self, ApiError> {
+    /// Orders by trigram similarity to `value`, closest first.
+    /// Result: ORDER BY similarity(username, $1) DESC
+    pub fn order_by_similarity(mut self, column: &str, value: &str) -> Result<Self, ApiError> {
+        Self::require_postgres("order_by_similarity")?;
         self.validate_column(column)?;
-        self.insert_data.insert(column.to_string(), value.into());
+        let expression = SqlExpr::default()
+            .sql(format!("similarity({}, ", qualify::<T>(column)))
+            .bind(value)
+            .sql(")");
+        self.order_by.push(OrderBy {
+            column: column.to_string(),
+            direction: OrderDirection::Desc,
+            expression: Some(expression),
+        });
         Ok(self)
     }
 
-    pub fn values(mut self, data: HashMap<String, Value>) -> Result<Self, ApiError> {
-        for column in data.keys() {
-            self.validate_column(column)?;
-        }
-        self.insert_data.extend(data);
+    /// Adds the `ts_rank` of the search to the selection, as `search_rank`.
+    pub fn select_rank(mut self, search: &TextSearch) -> Result<Self, ApiError> {
+        Self::require_postgres("select_rank")?;
+        let expression = self.ts_rank_expr(search)?;
+        self.select_exprs.push((expression, RANK_ALIAS.to_string()));
         Ok(self)
     }
 
-    // Column validation
-    fn validate_column(&self, column: &str) -> Result<(), ApiError> {
-        if !T::columns().contains(&column) {
-            return Err(ApiError::InvalidColumn(column.to_string()));
-        }
-        Ok(())
+    /// Adds a `ts_headline` snippet with the matches highlighted to the selection,
+    /// as `search_headline`.
+    pub fn select_headline(mut self, search: &TextSearch) -> Result<Self, ApiError> {
+        Self::require_postgres("select_headline")?;
+        let (document, _) = self.text_search_sql(search)?;
+        let expression = SqlExpr::default()
+            .sql(format!(
+                "coalesce(ts_headline('{}', {}, websearch_to_tsquery('{}', ",
+                search.config, document, search.config
+            ))
+            .bind(search.query.clone())
+            .sql(")), '')");
+        self.select_exprs
+            .push((expression, HEADLINE_ALIAS.to_string()));
+        Ok(self)
     }
 
-    // Build SELECT query
-    pub fn build_select_query(&self) -> QueryBuilder<'_, Postgres> {
-        let mut query_builder = QueryBuilder::new("SELECT ");
+    fn ts_rank_expr(&self, search: &TextSearch) -> Result<SqlExpr, ApiError> {
+        let (_, tsvector) = self.text_search_sql(search)?;
+        Ok(SqlExpr::default()
+            .sql(format!(
+                "ts_rank({}, websearch_to_tsquery('{}', ",
+                tsvector, search.config
+            ))
+            .bind(search.query.clone())
+            .sql("))"))
+    }
 
-        if self.distinct {
-            query_builder.push("DISTINCT ");
+    // Returns the searched document and its tsvector. A single column is used as-is so
+    // that an expression index on to_tsvector(config, column) applies.
+    fn text_search_sql(&self, search: &TextSearch) -> Result<(String, String), ApiError> {
+        Self::validate_alias(&search.config)?;
+        if search.columns.is_empty() {
+            return Err(ApiError::InvalidQuery(
+                "Text search requires at least one column".to_string(),
+            ));
+        }
+        for column in &search.columns {
+            self.validate_column(column)?;
         }
 
-        // Columns to select
-        let columns: Vec<String> = match &self.select_columns {
-            Some(cols) => cols.clone(),
-            None => T::columns().iter().map(|s| s.to_string()).collect(),
+        let document = match search.columns.as_slice() {
+            [column] => qualify::<T>(column),
+            columns => columns
+                .iter()
+                .map(|column| format!("coalesce({}, '')", qualify::<T>(column)))
+                .collect::<Vec<_>>()
+                .join(" || ' ' || "),
         };
-        query_builder.push(columns.join(", "));
-
-        query_builder.push(" FROM ");
-        query_builder.push(T::table_name());
+        let tsvector = format!("to_tsvector('{}', {})", search.config, document);
 
-        // Add JOINs
-        for join in &self.joins {
-            query_builder.push(" ");
-            query_builder.push(join.join_type.to_sql());
-            query_builder.push(" ");
-            query_builder.push(&join.table);
-            query_builder.push(" ON ");
-            query_builder.push(&join.on_condition);
-        }
+        Ok((document, tsvector))
+    }
 
-        // Add WHERE conditions
-        if !self.where_clauses.is_empty() {
-            query_builder.push(" WHERE ");
-            self.build_where_conditions(&mut query_builder);
+    // Methods for ORDER BY
+    pub fn order_by(mut self, column: &str, direction: OrderDirection) -> Result<Self, ApiError> {
+        if !self.is_alias(column) {
+            self.validate_column(column)?;
         }
+        self.order_by.push(OrderBy {
+            column: column.to_string(),
+            direction,
+            expression: None,
+        });
+        Ok(self)
+    }
 
-        // Add GROUP BY
-        if !self.group_by.is_empty() {
-            query_builder.push(" GROUP BY ");
-            query_builder.push(self.group_by.join(", "));
-        }
+    pub fn order_by_asc(self, column: &str) -> Result<Self, ApiError> {
+        self.order_by(column, OrderDirection::Asc)
+    }
+
+    pub fn order_by_desc(self, column: &str) -> Result<Self, ApiError> {
+        self.order_by(column, OrderDirection::Desc)
+    }
+
+    // Methods for LIMIT and OFFSET
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: u32) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn paginate(mut self, page: u32, page_size: u32) -> Self {
+        let offset = (page.saturating_sub(1)) * page_size;
+        self.limit = Some(page_size);
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Keyset (cursor) pagination on `columns`, with `id` appended as a tie-breaker.
+    /// Replaces ORDER BY, LIMIT and OFFSET; rows come after (or before, for backward
+    /// cursors) the cursor position using a row-value comparison such as
+    /// `(created_at, id) < ($1, $2)`. Sort columns must be NOT NULL and have a declared
+    /// type (`Entry::column_type`).
+    /// Use `fetch_cursor_page` to execute the query.
+    pub fn cursor_paginate(
+        self,
+        columns: &[&str],
+        direction: OrderDirection,
+        cursor: Option<Cursor>,
+        limit: u32,
+    ) -> Result<Self, ApiError> {
+        let sort: Vec<(&str, OrderDirection)> = columns
+            .iter()
+            .map(|column| (*column, direction.clone()))
+            .collect();
+        self.keyset_paginate(&sort, direction, cursor, limit)
+    }
+
+    /// Same as `cursor_paginate`, with a direction per column. `id` is appended with the
+    /// direction of the last column. When directions differ, the position is compared
+    /// column by column: `created_at < $1 OR (created_at = $2 AND username > $3) OR ...`
+    pub fn cursor_paginate_sorted(
+        self,
+        sort: &[(&str, OrderDirection)],
+        cursor: Option<Cursor>,
+        limit: u32,
+    ) -> Result<Self, ApiError> {
+        let id_direction = sort
+            .last()
+            .map_or(OrderDirection::Asc, |(_, direction)| direction.clone());
+        self.keyset_paginate(sort, id_direction, cursor, limit)
+    }
+
+    fn keyset_paginate(
+        mut self,
+        sort: &[(&str, OrderDirection)],
+        id_direction: OrderDirection,
+        cursor: Option<Cursor>,
+        limit: u32,
+    ) -> Result<Self, ApiError> {
+        let mut keyset_columns = Vec::with_capacity(sort.len() + 1);
+        let mut directions = Vec::with_capacity(sort.len() + 1);
+        for (column, direction) in sort {
+            self.validate_write_column(column)?;
+            if *column != T::primary_key_column() {
+                keyset_columns.push(column.to_string());
+                directions.push(direction.clone());
+            }
+        }
+        keyset_columns.push(T::primary_key_column().to_string());
+        directions.push(id_direction);
+        // Cursor values are bound with the declared column types
+        if let Some(column) = keyset_columns
+            .iter()
+            .find(|column| Self::column_type(column).is_none())
+        {
+            return Err(ApiError::InvalidColumn(column.clone()));
+        }
+
+        if let Some(cursor) = &cursor
+            && cursor.values.len() != keyset_columns.len()
+        {
+            return Err(ApiError::BadRequest("Invalid cursor".to_string()));
+        }
+
+        self.keyset = Some(Keyset {
+            columns: keyset_columns,
+            directions,
+            cursor,
+            limit,
+        });
+        Ok(self)
+    }
+
+    // Methods for JOIN
+    /// Joins the table of another entry on `column = foreign_column`.
+    /// `column` belongs to this query's table or to an already joined one, and may be
+    /// qualified (`users.id`); `foreign_column` belongs to `O`.
+    /// Example: inner_join::<Order>("id", "user_id")?
+    /// Result: INNER JOIN orders ON users.id = orders.user_id
+    pub fn inner_join<O: Entry>(
+        self,
+        column: &str,
+        foreign_column: &str,
+    ) -> Result<Self, ApiError> {
+        self.join::<O>(JoinType::Inner, column, foreign_column)
+    }
+
+    pub fn left_join<O: Entry>(self, column: &str, foreign_column: &str) -> Result<Self, ApiError> {
+        self.join::<O>(JoinType::Left, column, foreign_column)
+    }
+
+    pub fn right_join<O: Entry>(
+        self,
+        column: &str,
+        foreign_column: &str,
+    ) -> Result<Self, ApiError> {
+        self.join::<O>(JoinType::Right, column, foreign_column)
+    }
+
+    pub fn full_outer_join<O: Entry>(
+        self,
+        column: &str,
+        foreign_column: &str,
+    ) -> Result<Self, ApiError> {
+        if DB::DB_TYPE == DbType::MySQL {
+            return Err(ApiError::InvalidQuery(
+                "FULL OUTER JOIN is not supported on MySQL".to_string(),
+            ));
+        }
+        self.join::<O>(JoinType::Full, column, foreign_column)
+    }
+
+    fn join<O: Entry>(
+        mut self,
+        join_type: JoinType,
+        column: &str,
+        foreign_column: &str,
+    ) -> Result<Self, ApiError> {
+        let table = O::table_name();
+        if table == T::table_name() || self.joins.iter().any(|join| join.table == table) {
+            return Err(ApiError::InvalidQuery(format!(
+                "Table {} is already part of the query",
+                table
+            )));
+        }
+
+        self.validate_column(column)?;
+        let column = match column.split_once('.') {
+            Some(_) => column.to_string(),
+            None => format!("{}.{}", T::table_name(), column),
+        };
+
+        let foreign_name = match foreign_column.split_once('.') {
+            Some((foreign_table, name)) if foreign_table == table => name,
+            Some(_) => return Err(ApiError::InvalidColumn(foreign_column.to_string())),
+            None => foreign_column,
+        };
+        if !O::columns().contains(&foreign_name) {
+            return Err(ApiError::InvalidColumn(foreign_column.to_string()));
+        }
+
+        self.joins.push(JoinClause {
+            join_type,
+            table: table.to_string(),
+            on_condition: format!("{} = {}.{}", column, table, foreign_name),
+            columns: O::columns(),
+            tenant_column: TenantColumn::of::<O>(),
+        });
+        Ok(self)
+    }
+
+    /// Loads the rows of a relation declared by `Entry::relations()` along with the
+    /// entries, in one extra query per relation (see `fetch_all_with_relations`).
+    /// Postgres only.
+    /// Example: with("orders")?.fetch_all_with_relations(&pool)
+    pub fn with(mut self, name: &str) -> Result<Self, ApiError> {
+        Self::require_postgres("Eager loading")?;
+        let relation = T::relations()
+            .into_iter()
+            .find(|relation| relation.name == name)
+            .ok_or_else(|| {
+                ApiError::InvalidQuery(format!("Unknown relation {} on {}", name, T::table_name()))
+            })?;
+
+        let foreign_key_exists = match relation.kind {
+            RelationKind::BelongsTo => T::columns().contains(&relation.foreign_key),
+            RelationKind::HasMany => relation.columns.contains(&relation.foreign_key),
+            // Pivot table columns are not known
+            RelationKind::ManyToMany { .. } => true,
+        };
+        if !foreign_key_exists {
+            return Err(ApiError::InvalidColumn(relation.foreign_key.to_string()));
+        }
+        if Self::key_array_type(&relation).is_none() {
+            return Err(ApiError::InvalidQuery(format!(
+                "Relation {} needs a uuid, integer or text key column",
+                name
+            )));
+        }
+
+        if !self.relations.iter().any(|loaded| loaded.name == name) {
+            self.relations.push(relation);
+        }
+        Ok(self)
+    }
+
+    // Element type of the array of entry keys the related rows are looked up by
+    pub(super) fn key_array_type(relation: &Relation) -> Option<ArrayType> {
+        match Self::column_type(relation.local_column::<T>())? {
+            ColumnType::Uuid => Some(ArrayType::Uuid),
+            ColumnType::Integer => Some(ArrayType::BigInt),
+            ColumnType::Text => Some(ArrayType::Text),
+            _ => None,
+        }
+    }
+
+    // Methods for GROUP BY and HAVING
+    pub fn group_by(mut self, column: &str) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        self.group_by.push(GroupBy {
+            expression: column.to_string(),
+            alias: None,
+        });
+        Ok(self)
+    }
+
+    /// Groups by a truncated timestamp, selected as `alias`.
+    /// Example: group_by_date_trunc("created_at", TimeBucket::Day, "day")
+    /// Result: SELECT date_trunc('day', created_at) AS day ... GROUP BY date_trunc('day', created_at)
+    pub fn group_by_date_trunc(
+        mut self,
+        column: &str,
+        bucket: TimeBucket,
+        alias: &str,
+    ) -> Result<Self, ApiError> {
+        Self::require_postgres("group_by_date_trunc")?;
+        self.validate_column(column)?;
+        Self::validate_alias(alias)?;
+        self.group_by.push(GroupBy {
+            expression: format!("date_trunc('{}', {})", bucket.to_sql(), column),
+            alias: Some(alias.to_string()),
+        });
+        Ok(self)
+    }
+
+    /// Adds an aggregate to the selected columns.
+    /// Without `select`, the selection is the GROUP BY expressions followed by the aggregates;
+    /// fetch the results with `fetch_all_as` / `fetch_one_as`.
+    /// Example: group_by("email")?.aggregate(Agg::count("id").alias("n"))
+    pub fn aggregate(mut self, agg: Agg) -> Result<Self, ApiError> {
+        self.validate_agg(&agg)?;
+        if let Some(alias) = &agg.alias {
+            Self::validate_alias(alias)?;
+        }
+        self.aggregates.push(agg);
+        Ok(self)
+    }
+
+    // Methods for HAVING (conditions are joined with AND)
+    pub fn having_eq<V: Into<Value>>(self, agg: Agg, value: V) -> Result<Self, ApiError> {
+        self.having(agg, ComparisonOperator::Equal, value.into())
+    }
+
+    pub fn having_ne<V: Into<Value>>(self, agg: Agg, value: V) -> Result<Self, ApiError> {
+        self.having(agg, ComparisonOperator::NotEqual, value.into())
+    }
+
+    pub fn having_gt<V: Into<Value>>(self, agg: Agg, value: V) -> Result<Self, ApiError> {
+        self.having(agg, ComparisonOperator::GreaterThan, value.into())
+    }
+
+    pub fn having_gte<V: Into<Value>>(self, agg: Agg, value: V) -> Result<Self, ApiError> {
+        self.having(agg, ComparisonOperator::GreaterThanOrEqual, value.into())
+    }
+
+    pub fn having_lt<V: Into<Value>>(self, agg: Agg, value: V) -> Result<Self, ApiError> {
+        self.having(agg, ComparisonOperator::LessThan, value.into())
+    }
+
+    pub fn having_lte<V: Into<Value>>(self, agg: Agg, value: V) -> Result<Self, ApiError> {
+        self.having(agg, ComparisonOperator::LessThanOrEqual, value.into())
+    }
+
+    fn having(
+        mut self,
+        agg: Agg,
+        operator: ComparisonOperator,
+        value: Value,
+    ) -> Result<Self, ApiError> {
+        self.validate_agg(&agg)?;
+        self.having.push(WhereCondition {
+            column: agg.to_sql(),
+            operator,
+            value: Some(value),
+            values: None,
+        });
+        Ok(self)
+    }
+
+    /// Includes soft-deleted rows, which are left out by default.
+    pub fn with_trashed(mut self) -> Self {
+        self.trashed = TrashedScope::Include;
+        self
+    }
+
+    /// Keeps only soft-deleted rows.
+    pub fn only_trashed(mut self) -> Self {
+        self.trashed = TrashedScope::Only;
+        self
+    }
+
+    /// Reads and writes the rows of every tenant instead of those of the tenant in scope.
+    /// Reserved for admin jobs, see `tenant::without_tenant_scope`.
+    pub fn without_tenant_scope(mut self) -> Self {
+        self.tenant = Some(TenantScope::Unscoped);
+        self
+    }
+
+    /// Fails each statement of the query with `ApiError::QueryTimeout` when the database
+    /// has not answered within `timeout`. Postgres cancels the statement itself, other
+    /// databases only stop being waited for (see `TimedConnection`).
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    // Methods for DISTINCT and SELECT
+    pub fn distinct(mut self) -> Self {
+        self.distinct = true;
+        self
+    }
+
+    pub fn select<'a, I>(mut self, columns: I) -> Result<Self, ApiError>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut selected = Vec::new();
+        for column in columns {
+            self.validate_column(column)?;
+            selected.push(column.to_string());
+        }
+        self.select_columns = Some(selected);
+        Ok(self)
+    }
+
+    // Methods for UPDATE
+    pub fn set<V: Into<Value>>(mut self, column: &str, value: V) -> Result<Self, ApiError> {
+        self.validate_write_column(column)?;
+        self.update_exprs.retain(|(set, _)| set != column);
+        self.update_data.insert(column.to_string(), value.into());
+        Ok(self)
+    }
+
+    /// Raw SQL value for `column` with `{}` placeholders, see `where_raw`. It cannot
+    /// target the tenant column, which a scoped update keeps as it is.
+    /// Example: set_raw("version", "version + {}", [1])
+    /// Result: UPDATE users SET version = version + $1
+    pub fn set_raw<I, V>(mut self, column: &str, sql: &str, values: I) -> Result<Self, ApiError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        self.validate_write_column(column)?;
+        if T::tenant_column() == Some(column) {
+            return Err(ApiError::InvalidColumn(column.to_string()));
+        }
+        let expression = SqlExpr::raw(sql, values)?;
+        self.update_data.remove(column);
+        self.update_exprs.retain(|(set, _)| set != column);
+        self.update_exprs.push((column.to_string(), expression));
+        Ok(self)
+    }
+
+    pub fn set_multiple(mut self, data: HashMap<String, Value>) -> Result<Self, ApiError> {
+        for column in data.keys() {
+            self.validate_write_column(column)?;
+        }
+        self.update_exprs.retain(|(set, _)| !data.contains_key(set));
+        self.update_data.extend(data);
+        Ok(self)
+    }
+
+    // Methods for INSERT
+    pub fn value<V: Into<Value>>(mut self, column: &str, value: V) -> Result<Self, ApiError> {
+        self.validate_write_column(column)?;
+        self.insert_data.insert(column.to_string(), value.into());
+        Ok(self)
+    }
+
+    pub fn values(mut self, data: HashMap<String, Value>) -> Result<Self, ApiError> {
+        for column in data.keys() {
+            self.validate_write_column(column)?;
+        }
+        self.insert_data.extend(data);
+        Ok(self)
+    }
+
+    /// Rows of a multi-row INSERT. Every row must provide the same columns.
+    pub fn values_many(mut self, rows: Vec<HashMap<String, Value>>) -> Result<Self, ApiError> {
+        if let Some(first) = rows.first() {
+            for column in first.keys() {
+                self.validate_write_column(column)?;
+            }
+            let same_columns = rows
+                .iter()
+                .all(|row| row.len() == first.len() && row.keys().all(|k| first.contains_key(k)));
+            if !same_columns {
+                return Err(ApiError::InvalidQuery(
+                    "All inserted rows must have the same columns".to_string(),
+                ));
+            }
+        }
+        self.insert_rows.extend(rows);
+        Ok(self)
+    }
+
+    // Methods for upsert (INSERT ... ON CONFLICT)
+
+    /// Sets the conflict target of the INSERT; the action defaults to DO NOTHING.
+    /// Example: on_conflict(["email"])?.do_update(["username", "updated_at"])?
+    /// Result: ON CONFLICT (email) DO UPDATE SET username = EXCLUDED.username, updated_at = EXCLUDED.updated_at
+    pub fn on_conflict<'a, I>(mut self, columns: I) -> Result<Self, ApiError>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut target = Vec::new();
+        for column in columns {
+            self.validate_write_column(column)?;
+            target.push(column.to_string());
+        }
+        if target.is_empty() {
+            return Err(ApiError::InvalidQuery(
+                "on_conflict requires at least one column".to_string(),
+            ));
+        }
+        self.on_conflict = Some(OnConflict {
+            columns: target,
+            action: ConflictAction::DoNothing,
+            where_clauses: Vec::new(),
+        });
+        Ok(self)
+    }
+
+    pub fn do_nothing(mut self) -> Self {
+        if let Some(on_conflict) = self.on_conflict.as_mut() {
+            on_conflict.action = ConflictAction::DoNothing;
+        }
+        self
+    }
+
+    pub fn do_update<'a, I>(mut self, columns: I) -> Result<Self, ApiError>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut update_columns = Vec::new();
+        for column in columns {
+            self.validate_write_column(column)?;
+            update_columns.push(column.to_string());
+        }
+        if update_columns.is_empty() {
+            return Err(ApiError::InvalidQuery(
+                "do_update requires at least one column".to_string(),
+            ));
+        }
+        self.conflict_mut()?.action = ConflictAction::DoUpdate(update_columns);
+        Ok(self)
+    }
+
+    /// Restricts DO UPDATE to existing rows matching the conditions.
+    /// Columns refer to the existing row and are qualified with the table name.
+    /// Example: do_update_where(|group| group.where_ne("status", "locked"))
+    /// Result: ... DO UPDATE SET ... WHERE users.status != $n
+    pub fn do_update_where<F>(mut self, builder_fn: F) -> Result<Self, ApiError>
+    where
+        F: FnOnce(GroupBuilder<T, DB>) -> Result<GroupBuilder<T, DB>, ApiError>,
+    {
+        if DB::DB_TYPE == DbType::MySQL {
+            return Err(ApiError::InvalidQuery(
+                "do_update_where is not supported on MySQL".to_string(),
+            ));
+        }
+        let group_builder = builder_fn(GroupBuilder::new())?;
+        let clauses = Self::qualify_clauses(group_builder.clauses);
+        self.conflict_mut()?.where_clauses.extend(clauses);
+        Ok(self)
+    }
+
+    fn conflict_mut(&mut self) -> Result<&mut OnConflict, ApiError> {
+        self.on_conflict
+            .as_mut()
+            .ok_or_else(|| ApiError::InvalidQuery("on_conflict must be called first".to_string()))
+    }
+
+    // Prefixes condition columns with the table name
+    fn qualify_clauses(
+        clauses: Vec<(WhereClause, Option<LogicalOperator>)>,
+    ) -> Vec<(WhereClause, Option<LogicalOperator>)> {
+        clauses
+            .into_iter()
+            .map(|(clause, op)| {
+                let clause = match clause {
+                    WhereClause::Condition(mut condition) if !condition.column.contains('.') => {
+                        condition.column = format!("{}.{}", T::table_name(), condition.column);
+                        WhereClause::Condition(condition)
+                    }
+                    WhereClause::Group(mut group) => {
+                        group.clauses = Self::qualify_clauses(group.clauses);
+                        WhereClause::Group(group)
+                    }
+                    other => other,
+                };
+                (clause, op)
+            })
+            .collect()
+    }
+
+    // Conditions that only exist in Postgres are rejected when building for another database
+    fn require_postgres(feature: &str) -> Result<(), ApiError> {
+        DB::DB_TYPE.require_postgres(feature)
+    }
+
+    // Column validation
+    fn validate_column(&self, column: &str) -> Result<(), ApiError> {
+        if !is_known_column::<T>(column, &self.joins) {
+            return Err(ApiError::InvalidColumn(column.to_string()));
+        }
+        Ok(())
+    }
+
+    // INSERT/UPDATE columns always belong to this table and cannot be qualified
+    fn validate_write_column(&self, column: &str) -> Result<(), ApiError> {
+        if !T::columns().contains(&column) {
+            return Err(ApiError::InvalidColumn(column.to_string()));
+        }
+        Ok(())
+    }
+
+    // In a query with joins, unqualified columns refer to this query's table
+    fn column_sql<'a>(&self, column: &'a str) -> Cow<'a, str> {
+        if self.joins.is_empty() || !T::columns().contains(&column) {
+            Cow::Borrowed(column)
+        } else {
+            Cow::Owned(format!("{}.{}", T::table_name(), column))
+        }
+    }
+
+    fn validate_agg(&self, agg: &Agg) -> Result<(), ApiError> {
+        match &agg.column {
+            Some(column) => self.validate_column(column),
+            None => Ok(()),
+        }
+    }
+
+    // Aliases are pushed as-is into the SQL, so only plain identifiers are accepted
+    fn validate_alias(alias: &str) -> Result<(), ApiError> {
+        let mut chars = alias.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(ApiError::InvalidColumn(alias.to_string()));
+        }
+        Ok(())
+    }
+
+    // Whether `name` is an alias defined by group_by_date_trunc or aggregate
+    fn is_alias(&self, name: &str) -> bool {
+        self.group_by
+            .iter()
+            .filter_map(|group| group.alias.as_deref())
+            .chain(
+                self.aggregates
+                    .iter()
+                    .filter_map(|agg| agg.alias.as_deref()),
+            )
+            .any(|alias| alias == name)
+    }
+
+    // Tenant the rows are restricted to, None when the query is not tenant-scoped
+    fn tenant(&self) -> Option<&Value> {
+        match &self.tenant {
+            Some(TenantScope::Tenant(tenant)) => Some(&tenant.id),
+            _ => None,
+        }
+    }
+
+    /// A query reaching a tenant table (this entry's, a joined or a related one) needs a
+    /// tenant in scope, or `without_tenant_scope`: it fails rather than reach every tenant.
+    pub(super) fn check_tenant_scope(&self) -> QueryResult<()> {
+        if self.tenant.is_some() {
+            return Ok(());
+        }
+        let table = T::tenant_column()
+            .map(|_| T::table_name())
+            .or_else(|| {
+                self.joins
+                    .iter()
+                    .find(|join| join.tenant_column.is_some())
+                    .map(|join| join.table.as_str())
+            })
+            .or_else(|| {
+                self.relations
+                    .iter()
+                    .find(|relation| relation.tenant_column.is_some())
+                    .map(|relation| relation.table)
+            });
+        match table {
+            Some(table) => Err(ApiError::Authorization(format!(
+                "No tenant in scope to query {}",
+                table
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    // ` AND table.column = $n`, restricting a joined or related table to the tenant
+    pub(super) fn push_tenant_condition(
+        &self,
+        table: &str,
+        tenant_column: Option<TenantColumn>,
+        query_builder: &mut SqlWriter,
+    ) {
+        let (Some(column), Some(tenant)) = (tenant_column, self.tenant()) else {
+            return;
+        };
+        query_builder.push(format_args!(" AND {}.{} = ", table, column.name));
+        query_builder.push_bind(match column.column_type {
+            Some(column_type) => SqlValue::typed(tenant.clone(), column_type),
+            None => SqlValue::untyped(tenant.clone()),
+        });
+    }
+
+    // Build SELECT query
+    pub fn build_select_query(&self) -> QueryResult<QueryBuilder<'static, DB>> {
+        self.select_writer()?.into_statement().into_query_builder()
+    }
+
+    pub(super) fn select_writer(&self) -> QueryResult<SqlWriter> {
+        self.check_tenant_scope()?;
+        let mut query_builder = SqlWriter::new(DB::DB_TYPE, "");
+        self.push_select_query(&mut query_builder);
+        Ok(query_builder)
+    }
+
+    /// Writes the SELECT statement into an existing builder, so that it can be nested
+    /// as a subquery with its bind parameters numbered after the outer ones
+    pub(super) fn push_select_query(&self, query_builder: &mut SqlWriter) {
+        self.push_select_query_with(&self.select_list(), query_builder);
+    }
+
+    fn select_list(&self) -> String {
+        let mut columns: Vec<String> = match &self.select_columns {
+            Some(cols) => cols
+                .iter()
+                .map(|col| self.column_sql(col).into_owned())
+                .collect(),
+            None if !self.aggregates.is_empty() => self
+                .group_by
+                .iter()
+                .map(|group| {
+                    let expression = self.column_sql(&group.expression);
+                    match &group.alias {
+                        Some(alias) => format!("{} AS {}", expression, alias),
+                        None => expression.into_owned(),
+                    }
+                })
+                .collect(),
+            None => T::columns()
+                .iter()
+                .map(|col| self.column_sql(col).into_owned())
+                .collect(),
+        };
+        columns.extend(self.aggregates.iter().map(|agg| match &agg.alias {
+            Some(alias) => format!("{} AS {}", agg.to_sql(), alias),
+            None => agg.to_sql(),
+        }));
+        columns.join(", ")
+    }
+
+    pub(super) fn push_select_query_with(&self, select_list: &str, query_builder: &mut SqlWriter) {
+        query_builder.push("SELECT ");
+
+        if self.distinct {
+            query_builder.push("DISTINCT ");
+        }
+
+        query_builder.push(select_list);
+        for (expression, alias) in &self.select_exprs {
+            query_builder.push(", ");
+            self.push_sql_expr(expression, query_builder);
+            query_builder.push(" AS ");
+            query_builder.push(alias);
+        }
+
+        query_builder.push(" FROM ");
+        query_builder.push(T::table_name());
+
+        // Add JOINs
+        self.push_joins(query_builder);
+
+        // Add WHERE conditions
+        let keyset_cursor = self
+            .keyset
+            .as_ref()
+            .and_then(|keyset| keyset.cursor.as_ref().map(|cursor| (keyset, cursor)));
+        self.push_where(self.trashed, keyset_cursor, query_builder);
+
+        // Add GROUP BY
+        if !self.group_by.is_empty() {
+            let expressions: Vec<Cow<'_, str>> = self
+                .group_by
+                .iter()
+                .map(|group| self.column_sql(&group.expression))
+                .collect();
+            query_builder.push(" GROUP BY ");
+            query_builder.push(expressions.join(", "));
+        }
+
+        // Add HAVING
+        if !self.having.is_empty() {
+            query_builder.push(" HAVING ");
+            for (i, condition) in self.having.iter().enumerate() {
+                if i > 0 {
+                    query_builder.push(" AND ");
+                }
+                self.build_single_condition(condition, query_builder);
+            }
+        }
+
+        // Keyset pagination defines its own ordering and limit
+        if let Some(keyset) = &self.keyset {
+            query_builder.push(" ORDER BY ");
+            for (i, column) in keyset.columns.iter().enumerate() {
+                if i > 0 {
+                    query_builder.push(", ");
+                }
+                query_builder.push(self.column_sql(column));
+                query_builder.push(" ");
+                query_builder.push(keyset.effective_direction(i).to_sql());
+            }
+
+            // One extra row tells whether another page exists
+            query_builder.push(" LIMIT ");
+            query_builder.push((keyset.limit as u64 + 1).to_string());
+            return;
+        }
 
         // Add ORDER BY
         if !self.order_by.is_empty() {
@@ -480,7 +1598,12 @@ impl<T: Entry + Send + Sync + Unpin + 'static> QueryBuilderUtil<T> {
                 if i > 0 {
                     query_builder.push(", ");
                 }
-                query_builder.push(&order.column);
+                match &order.expression {
+                    Some(expression) => self.push_sql_expr(expression, query_builder),
+                    None => {
+                        query_builder.push(self.column_sql(&order.column));
+                    }
+                }
                 query_builder.push(" ");
                 query_builder.push(order.direction.to_sql());
             }
@@ -494,105 +1617,405 @@ impl<T: Entry + Send + Sync + Unpin + 'static> QueryBuilderUtil<T> {
 
         // Add OFFSET
         if let Some(offset) = self.offset {
+            if self.limit.is_none()
+                && let Some(unbounded) = query_builder.db_type().unbounded_limit()
+            {
+                query_builder.push(" LIMIT ");
+                query_builder.push(unbounded);
+            }
             query_builder.push(" OFFSET ");
             query_builder.push(offset.to_string());
         }
-        query_builder
+    }
+
+    fn push_joins(&self, query_builder: &mut SqlWriter) {
+        for join in &self.joins {
+            query_builder.push(" ");
+            query_builder.push(join.join_type.to_sql());
+            query_builder.push(" ");
+            query_builder.push(&join.table);
+            query_builder.push(" ON ");
+            query_builder.push(&join.on_condition);
+            self.push_tenant_condition(&join.table, join.tenant_column, query_builder);
+        }
     }
 
     // Build UPDATE query
-    pub fn build_update_query(&self) -> Result<QueryBuilder<'_, Postgres>, ApiError> {
-        if self.update_data.is_empty() {
+    pub fn build_update_query(&self) -> Result<QueryBuilder<'static, DB>, ApiError> {
+        self.update_writer()?.into_statement().into_query_builder()
+    }
+
+    fn update_writer(&self) -> Result<SqlWriter, ApiError> {
+        if self.update_data.is_empty() && self.update_exprs.is_empty() {
             return Err(ApiError::InvalidQuery(
                 "No data provided for update".to_string(),
             ));
         }
+        self.check_tenant_scope()?;
 
-        let mut query_builder = QueryBuilder::new("UPDATE ");
+        let mut query_builder = SqlWriter::new(DB::DB_TYPE, "UPDATE ");
         query_builder.push(T::table_name());
         query_builder.push(" SET ");
 
+        // Sorted, so that the same columns always give the same statement
+        let mut assignments: Vec<_> = self.update_data.iter().collect();
+        assignments.sort_unstable_by_key(|(column, _)| column.as_str());
+
         let mut first = true;
-        for (column, value) in &self.update_data {
+        for (column, value) in assignments {
             if !first {
                 query_builder.push(", ");
             }
+            // A scoped update cannot move rows to another tenant
+            let value = match T::tenant_column().zip(self.tenant()) {
+                Some((tenant_column, tenant)) if tenant_column == column => tenant,
+                _ => value,
+            };
             query_builder.push(column);
             query_builder.push(" = ");
-            self.bind_value(&mut query_builder, value.clone());
+            self.bind_value(&mut query_builder, Some(column), value.clone());
+            first = false;
+        }
+        for (column, expression) in &self.update_exprs {
+            if !first {
+                query_builder.push(", ");
+            }
+            query_builder.push(column);
+            query_builder.push(" = ");
+            self.push_sql_expr(expression, &mut query_builder);
             first = false;
         }
 
         // Add WHERE conditions
-        if !self.where_clauses.is_empty() {
-            query_builder.push(" WHERE ");
-            self.build_where_conditions(&mut query_builder);
-        }
+        self.push_where(self.trashed, None, &mut query_builder);
 
         Ok(query_builder)
     }
 
     // Build INSERT query
-    pub fn build_insert_query(&self) -> Result<QueryBuilder<'_, Postgres>, ApiError> {
-        if self.insert_data.is_empty() {
-            return Err(ApiError::InvalidQuery(
-                "No data provided for insert".to_string(),
-            ));
+    pub fn build_insert_query(&self) -> Result<QueryBuilder<'static, DB>, ApiError> {
+        self.insert_writer(&self.rows_to_insert()?)?
+            .into_statement()
+            .into_query_builder()
+    }
+
+    pub(super) fn insert_writer(
+        &self,
+        rows: &[HashMap<String, Value>],
+    ) -> Result<SqlWriter, ApiError> {
+        let columns = Self::insert_columns(rows)?;
+
+        let action = self
+            .on_conflict
+            .as_ref()
+            .map(|on_conflict| &on_conflict.action);
+        // MySQL cannot restrict ON DUPLICATE KEY UPDATE to the rows of the tenant
+        if DB::DB_TYPE == DbType::MySQL
+            && matches!(action, Some(ConflictAction::DoUpdate(_)))
+            && T::tenant_column().zip(self.tenant()).is_some()
+        {
+            return Err(ApiError::InvalidQuery(format!(
+                "Upserts into the tenant table {} are not supported on MySQL",
+                T::table_name()
+            )));
         }
 
-        let mut query_builder = QueryBuilder::new("INSERT INTO ");
+        // MySQL has no DO NOTHING: INSERT IGNORE skips the rows that conflict
+        let ignore =
+            DB::DB_TYPE == DbType::MySQL && matches!(action, Some(ConflictAction::DoNothing));
+        let mut query_builder = SqlWriter::new(
+            DB::DB_TYPE,
+            if ignore {
+                "INSERT IGNORE INTO "
+            } else {
+                "INSERT INTO "
+            },
+        );
         query_builder.push(T::table_name());
         query_builder.push(" (");
+        query_builder.push(columns.join(", "));
+        query_builder.push(") VALUES ");
 
-        let columns: Vec<&String> = self.insert_data.keys().collect();
-        query_builder.push(
-            columns
-                .iter()
-                .map(|s| s.as_str())
-                .collect::<Vec<_>>()
-                .join(", "),
-        );
-        query_builder.push(") VALUES (");
-
-        for (i, (_, value)) in self.insert_data.iter().enumerate() {
+        for (i, row) in rows.iter().enumerate() {
             if i > 0 {
                 query_builder.push(", ");
             }
-            self.bind_value(&mut query_builder, value.clone());
+            query_builder.push("(");
+            for (j, column) in columns.iter().enumerate() {
+                if j > 0 {
+                    query_builder.push(", ");
+                }
+                let value = row.get(*column).cloned().unwrap_or(Value::Null);
+                self.bind_value(&mut query_builder, Some(column), value);
+            }
+            query_builder.push(")");
+        }
+
+        if let Some(on_conflict) = &self.on_conflict {
+            self.build_on_conflict(on_conflict, &mut query_builder);
+        }
+
+        Ok(query_builder)
+    }
+
+    fn insert_columns(rows: &[HashMap<String, Value>]) -> Result<Vec<&str>, ApiError> {
+        match rows.first() {
+            Some(row) if !row.is_empty() => {
+                // Sorted, so that the same columns always give the same statement
+                let mut columns: Vec<&str> = row.keys().map(|k| k.as_str()).collect();
+                columns.sort_unstable();
+                Ok(columns)
+            }
+            _ => Err(ApiError::InvalidQuery(
+                "No data provided for insert".to_string(),
+            )),
+        }
+    }
+
+    fn build_on_conflict(&self, on_conflict: &OnConflict, query_builder: &mut SqlWriter) {
+        // MySQL detects conflicts on any unique key, so there is no conflict target
+        if query_builder.db_type() == DbType::MySQL {
+            if let ConflictAction::DoUpdate(columns) = &on_conflict.action {
+                let assignments: Vec<String> = columns
+                    .iter()
+                    .map(|column| format!("{} = VALUES({})", column, column))
+                    .collect();
+                query_builder.push(" ON DUPLICATE KEY UPDATE ");
+                query_builder.push(assignments.join(", "));
+            }
+            return;
         }
+
+        query_builder.push(" ON CONFLICT (");
+        query_builder.push(on_conflict.columns.join(", "));
         query_builder.push(")");
 
+        match &on_conflict.action {
+            ConflictAction::DoNothing => {
+                query_builder.push(" DO NOTHING");
+            }
+            ConflictAction::DoUpdate(columns) => {
+                let assignments: Vec<String> = columns
+                    .iter()
+                    .map(|column| format!("{} = EXCLUDED.{}", column, column))
+                    .collect();
+                query_builder.push(" DO UPDATE SET ");
+                query_builder.push(assignments.join(", "));
+
+                // The conflicting row may belong to another tenant, which is left as is
+                let tenant = T::tenant_column().zip(self.tenant());
+                if on_conflict.where_clauses.is_empty() && tenant.is_none() {
+                    return;
+                }
+                query_builder.push(" WHERE ");
+                if !on_conflict.where_clauses.is_empty() {
+                    query_builder.push("(");
+                    self.build_where_clauses(&on_conflict.where_clauses, query_builder);
+                    query_builder.push(")");
+                }
+                if let Some((column, tenant)) = tenant {
+                    if !on_conflict.where_clauses.is_empty() {
+                        query_builder.push(" AND ");
+                    }
+                    query_builder.push(format_args!("{}.{} = ", T::table_name(), column));
+                    self.bind_value(query_builder, Some(column), tenant.clone());
+                }
+            }
+        }
+    }
+
+    // Build DELETE query; for entries with a soft delete column, an UPDATE setting it
+    pub fn build_delete_query(&self) -> QueryResult<QueryBuilder<'static, DB>> {
+        self.delete_writer(false)?
+            .into_statement()
+            .into_query_builder()
+    }
+
+    fn delete_writer(&self, force: bool) -> QueryResult<SqlWriter> {
+        self.check_tenant_scope()?;
+        let soft_delete_column = T::soft_delete_column().filter(|_| !force);
+        let mut query_builder = match soft_delete_column {
+            Some(column) => {
+                let now = chrono::Utc::now();
+                let mut query_builder = SqlWriter::new(DB::DB_TYPE, "UPDATE ");
+                query_builder.push(T::table_name());
+                query_builder.push(" SET ");
+                query_builder.push(column);
+                query_builder.push(" = ");
+                query_builder.push_bind(SqlValue::Timestamp(now));
+                query_builder
+            }
+            None => {
+                let mut query_builder = SqlWriter::new(DB::DB_TYPE, "DELETE FROM ");
+                query_builder.push(T::table_name());
+                query_builder
+            }
+        };
+
+        // Add WHERE conditions
+        self.push_where(self.trashed, None, &mut query_builder);
+
         Ok(query_builder)
     }
 
-    // Build DELETE query
-    pub fn build_delete_query(&self) -> QueryBuilder<'_, Postgres> {
-        let mut query_builder = QueryBuilder::new("DELETE FROM ");
+    // Build the UPDATE clearing the soft delete column of the matching deleted rows
+    fn restore_writer(&self) -> Result<SqlWriter, ApiError> {
+        let column = T::soft_delete_column().ok_or_else(|| {
+            ApiError::InvalidQuery(format!("{} has no soft delete column", T::table_name()))
+        })?;
+        self.check_tenant_scope()?;
+
+        let mut query_builder = SqlWriter::new(DB::DB_TYPE, "UPDATE ");
         query_builder.push(T::table_name());
+        query_builder.push(" SET ");
+        query_builder.push(column);
+        query_builder.push(" = NULL");
+        self.push_where(TrashedScope::Only, None, &mut query_builder);
 
-        // Add WHERE conditions
+        Ok(query_builder)
+    }
+
+    /// Pushes the WHERE clause: the conditions, the soft delete scope, the tenant and the
+    /// keyset condition, joined with AND. Nothing is pushed if there is no condition at all.
+    fn push_where(
+        &self,
+        trashed: TrashedScope,
+        keyset_cursor: Option<(&Keyset, &Cursor)>,
+        query_builder: &mut SqlWriter,
+    ) {
+        let trashed_condition = T::soft_delete_column().and_then(|column| match trashed {
+            TrashedScope::Exclude => Some(format!("{} IS NULL", self.column_sql(column))),
+            TrashedScope::Only => Some(format!("{} IS NOT NULL", self.column_sql(column))),
+            TrashedScope::Include => None,
+        });
+        let tenant_condition = T::tenant_column().zip(self.tenant());
+
+        let parts = usize::from(!self.where_clauses.is_empty())
+            + usize::from(trashed_condition.is_some())
+            + usize::from(tenant_condition.is_some())
+            + usize::from(keyset_cursor.is_some());
+        if parts == 0 {
+            return;
+        }
+
+        query_builder.push(" WHERE ");
+        let mut written = false;
+        let mut and = |query_builder: &mut SqlWriter| {
+            if written {
+                query_builder.push(" AND ");
+            }
+            written = true;
+        };
         if !self.where_clauses.is_empty() {
-            query_builder.push(" WHERE ");
-            self.build_where_conditions(&mut query_builder);
+            and(query_builder);
+            if parts > 1 {
+                query_builder.push("(");
+                self.build_where_conditions(query_builder);
+                query_builder.push(")");
+            } else {
+                self.build_where_conditions(query_builder);
+            }
         }
+        if let Some(condition) = trashed_condition {
+            and(query_builder);
+            query_builder.push(condition);
+        }
+        if let Some((column, tenant)) = tenant_condition {
+            and(query_builder);
+            query_builder.push(self.column_sql(column));
+            query_builder.push(" = ");
+            self.bind_value(query_builder, Some(column), tenant.clone());
+        }
+        if let Some((keyset, cursor)) = keyset_cursor {
+            and(query_builder);
+            self.build_keyset_condition(keyset, cursor, query_builder);
+        }
+    }
+
+    /// Renders the statement of the given kind without executing it: the SQL with its
+    /// placeholders (`$n` or `?` depending on the database) and the bound parameters in order.
+    pub fn to_sql(&self, kind: StatementKind) -> QueryResult<(String, Vec<Value>)> {
+        let writer = match kind {
+            StatementKind::Select => self.select_writer()?,
+            StatementKind::Insert => self.insert_writer(&self.rows_to_insert()?)?,
+            StatementKind::Update => self.update_writer()?,
+            StatementKind::Delete => self.delete_writer(false)?,
+        };
 
-        query_builder
+        Ok(writer.into_parts())
     }
 
-    pub fn build_where_conditions(&self, query_builder: &mut QueryBuilder<'_, Postgres>) {
+    fn build_keyset_condition(
+        &self,
+        keyset: &Keyset,
+        cursor: &Cursor,
+        query_builder: &mut SqlWriter,
+    ) {
+        let columns: Vec<Cow<'_, str>> = keyset
+            .columns
+            .iter()
+            .map(|column| self.column_sql(column))
+            .collect();
+        let operator = |index: usize| match keyset.effective_direction(index) {
+            OrderDirection::Asc => ">",
+            OrderDirection::Desc => "<",
+        };
+
+        if keyset.is_uniform() {
+            query_builder.push("(");
+            query_builder.push(columns.join(", "));
+            query_builder.push(") ");
+            query_builder.push(operator(0));
+            query_builder.push(" (");
+            for (i, value) in cursor.values.iter().enumerate() {
+                if i > 0 {
+                    query_builder.push(", ");
+                }
+                self.bind_cursor_value(query_builder, &columns[i], value.clone());
+            }
+            query_builder.push(")");
+            return;
+        }
+
+        // A row-value comparison needs a single direction: expand it into one term per
+        // column, each requiring equality on the columns before it
+        query_builder.push("(");
+        for i in 0..columns.len() {
+            if i > 0 {
+                query_builder.push(" OR ");
+            }
+            query_builder.push("(");
+            for (j, column) in columns.iter().enumerate().take(i) {
+                query_builder.push(column);
+                query_builder.push(" = ");
+                self.bind_cursor_value(query_builder, column, cursor.values[j].clone());
+                query_builder.push(" AND ");
+            }
+            query_builder.push(&columns[i]);
+            query_builder.push(" ");
+            query_builder.push(operator(i));
+            query_builder.push(" ");
+            self.bind_cursor_value(query_builder, &columns[i], cursor.values[i].clone());
+            query_builder.push(")");
+        }
+        query_builder.push(")");
+    }
+
+    pub fn build_where_conditions(&self, query_builder: &mut SqlWriter) {
         self.build_where_clauses(&self.where_clauses, query_builder);
     }
 
     fn build_where_clauses(
         &self,
         clauses: &[(WhereClause, Option<LogicalOperator>)],
-        query_builder: &mut QueryBuilder<'_, Postgres>,
+        query_builder: &mut SqlWriter,
     ) {
-        for (i, (clause, logical_op)) in clauses.iter().enumerate() {
-            // Add the logical operator if it's not the first condition
+        for (i, (clause, _)) in clauses.iter().enumerate() {
+            // Add the logical operator if it's not the first condition: `and()` and `or()`
+            // set it on the clause before
             if i > 0 {
                 query_builder.push(" ");
-                if let Some(op) = logical_op {
+                if let Some(op) = &clauses[i - 1].1 {
                     query_builder.push(op.to_sql());
                 } else {
                     query_builder.push("AND"); // By default
@@ -609,16 +2032,48 @@ impl<T: Entry + Send + Sync + Unpin + 'static> QueryBuilderUtil<T> {
                     self.build_where_clauses(&group.clauses, query_builder);
                     query_builder.push(")");
                 }
+                WhereClause::Subquery(subquery) => {
+                    if let Some(column) = &subquery.column {
+                        query_builder.push(self.column_sql(column));
+                        query_builder.push(" ");
+                    }
+                    query_builder.push(subquery.operator.to_sql());
+                    query_builder.push(" (");
+                    subquery.query.push_sql(query_builder);
+                    query_builder.push(")");
+                }
+                WhereClause::ColumnComparison(comparison) => {
+                    query_builder.push(&comparison.left);
+                    query_builder.push(" ");
+                    query_builder.push(comparison.operator.to_sql());
+                    query_builder.push(" ");
+                    query_builder.push(&comparison.right);
+                }
+                WhereClause::Raw(expression) => {
+                    query_builder.push("(");
+                    self.push_sql_expr(expression, query_builder);
+                    query_builder.push(")");
+                }
             }
         }
     }
 
-    fn build_single_condition(
-        &self,
-        condition: &WhereCondition,
-        query_builder: &mut QueryBuilder<'_, Postgres>,
-    ) {
-        query_builder.push(&condition.column);
+    fn build_single_condition(&self, condition: &WhereCondition, query_builder: &mut SqlWriter) {
+        // Only Postgres has ILIKE; elsewhere both sides are lowercased
+        if matches!(condition.operator, ComparisonOperator::ILike)
+            && query_builder.db_type() != DbType::Postgres
+        {
+            if let Some(value) = &condition.value {
+                query_builder.push("LOWER(");
+                query_builder.push(self.column_sql(&condition.column));
+                query_builder.push(") LIKE LOWER(");
+                self.bind_value(query_builder, None, value.clone());
+                query_builder.push(")");
+            }
+            return;
+        }
+
+        query_builder.push(self.column_sql(&condition.column));
         query_builder.push(" ");
         query_builder.push(condition.operator.to_sql());
 
@@ -633,206 +2088,764 @@ impl<T: Entry + Send + Sync + Unpin + 'static> QueryBuilderUtil<T> {
                         if j > 0 {
                             query_builder.push(", ");
                         }
-                        self.bind_value(query_builder, value.clone());
+                        self.bind_value(query_builder, Some(&condition.column), value.clone());
                     }
                     query_builder.push(")");
                 }
             }
-            ComparisonOperator::Between => {
-                if let Some(values) = &condition.values {
-                    if values.len() == 2 {
-                        query_builder.push(" ");
-                        self.bind_value(query_builder, values[0].clone());
-                        query_builder.push(" AND ");
-                        self.bind_value(query_builder, values[1].clone());
-                    }
+            ComparisonOperator::AnyOf(array_type) => {
+                query_builder.push("(");
+                self.bind_array(query_builder, *array_type, condition.values.as_deref());
+                query_builder.push(")");
+            }
+            ComparisonOperator::ArrayOverlaps(array_type)
+            | ComparisonOperator::ArrayContains(array_type) => {
+                query_builder.push(" ");
+                self.bind_array(query_builder, *array_type, condition.values.as_deref());
+            }
+            ComparisonOperator::JsonContains | ComparisonOperator::JsonContainedBy => {
+                if let Some(value) = &condition.value {
+                    query_builder.push(" ");
+                    query_builder.push_bind(SqlValue::Json(value.clone()));
+                }
+            }
+            ComparisonOperator::JsonHasKey => {
+                if let Some(Value::String(key)) = &condition.value {
+                    query_builder.push(" ");
+                    query_builder.push_bind(SqlValue::Text(key.clone()));
+                }
+            }
+            ComparisonOperator::JsonHasAnyKey | ComparisonOperator::JsonHasAllKeys => {
+                query_builder.push(" ");
+                self.bind_array(query_builder, ArrayType::Text, condition.values.as_deref());
+            }
+            ComparisonOperator::JsonPath(_) => {
+                if let Some(Value::String(text)) = &condition.value {
+                    query_builder.push(" ");
+                    query_builder.push_bind(SqlValue::Text(text.clone()));
+                }
+            }
+            ComparisonOperator::TextSearch(config) => {
+                if let Some(value) = &condition.value {
+                    query_builder.push(" websearch_to_tsquery('");
+                    query_builder.push(config);
+                    query_builder.push("', ");
+                    self.bind_value(query_builder, None, value.clone());
+                    query_builder.push(")");
+                }
+            }
+            ComparisonOperator::Between => {
+                if let Some(values) = &condition.values
+                    && values.len() == 2
+                {
+                    query_builder.push(" ");
+                    self.bind_value(query_builder, Some(&condition.column), values[0].clone());
+                    query_builder.push(" AND ");
+                    self.bind_value(query_builder, Some(&condition.column), values[1].clone());
                 }
             }
             _ => {
                 if let Some(value) = &condition.value {
                     query_builder.push(" ");
-                    self.bind_value(query_builder, value.clone());
+                    self.bind_value(query_builder, Some(&condition.column), value.clone());
                 }
             }
         }
     }
+}
 
+impl<T, DB> QueryBuilderUtil<T, DB>
+where
+    T: Entry + Send + Sync + Unpin + 'static + for<'r> FromRow<'r, DB::Row>,
+    DB: Backend,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+{
     // Execution methods for SELECT
-    pub async fn fetch_all(&self, pool: &Pool<Postgres>) -> QueryResult<Vec<T>> {
-        let items = self
-            .build_select_query()
-            .build_query_as::<T>()
-            .fetch_all(pool)
+    pub async fn fetch_all<'c, E>(&self, executor: E) -> QueryResult<Vec<T>>
+    where
+        E: ConnectionSource<'c, DB>,
+    {
+        self.select_writer()?
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .fetch_all(executor)
             .await
-            .map_err(ApiError::Database)?;
+    }
 
-        Ok(items)
+    /// Streams the rows of the SELECT instead of collecting them, for result sets too
+    /// large to hold in memory. Rows are read as the stream is polled.
+    pub fn fetch_stream<'e, 'c: 'e, E>(
+        &self,
+        executor: E,
+    ) -> impl Stream<Item = QueryResult<T>> + Send + use<'e, 'c, T, DB, E>
+    where
+        E: ConnectionSource<'c, DB> + 'e,
+    {
+        let statement = self.select_writer().map(|writer| {
+            writer
+                .into_statement()
+                .with_table(T::table_name())
+                .with_timeout(self.timeout)
+        });
+        row_stream(move |sender| Self::send_statement_rows(statement, executor, sender))
+    }
+
+    /// Same as `fetch_stream`, but the stream holds a handle to the pool instead of
+    /// borrowing an executor, so that it can outlive the caller (e.g. as an HTTP body).
+    pub fn fetch_stream_owned(
+        &self,
+        pool: Pool<DB>,
+    ) -> impl Stream<Item = QueryResult<T>> + Send + use<T, DB> {
+        let statement = self.select_writer().map(|writer| {
+            writer
+                .into_statement()
+                .with_table(T::table_name())
+                .with_timeout(self.timeout)
+        });
+        row_stream(move |sender| async move {
+            Self::send_statement_rows(statement, &pool, sender).await;
+        })
     }
 
-    pub async fn fetch_one(&self, pool: &Pool<Postgres>) -> QueryResult<T> {
-        let item = self
-            .build_select_query()
-            .build_query_as::<T>()
-            .fetch_one(pool)
+    async fn send_statement_rows<'c, E>(
+        statement: QueryResult<Statement>,
+        executor: E,
+        sender: RowSender<T>,
+    ) where
+        E: ConnectionSource<'c, DB>,
+    {
+        let statement = match statement {
+            Ok(statement) => statement,
+            Err(e) => {
+                let _ = sender.send(Err(e)).await;
+                return;
+            }
+        };
+        let trace = QueryTrace::start(statement.table, &statement.sql);
+        let (timeout, params) = (statement.timeout, statement.params.len());
+        let (sql, arguments) = match statement.into_arguments::<DB>() {
+            Ok(statement) => statement,
+            Err(e) => {
+                let _ = sender.send(Err(e)).await;
+                return;
+            }
+        };
+        let sent = async {
+            let mut conn = executor.connection().await?;
+            let mut timed = TimedConnection::begin(&mut conn, timeout).await?;
+            let client_timeout = timed.client_timeout();
+            let rows = sqlx::query_as_with::<DB, T, _>(&sql, arguments).fetch(timed.conn());
+            // The timeout bounds the whole stream, not each row
+            let send = send_rows(rows, sender.clone()).instrument(trace.span());
+            let sent = with_timeout(client_timeout, send)
+                .await
+                .and_then(|sent| sent);
+            timed.end(sent).await
+        }
+        .await;
+        trace.finish(&sql, params, sent.as_ref().ok().copied());
+        if let Err(e) = sent {
+            let _ = sender.send(Err(e)).await;
+        }
+    }
+
+    pub async fn fetch_one<'c, E>(&self, executor: E) -> QueryResult<T>
+    where
+        E: ConnectionSource<'c, DB>,
+    {
+        self.select_writer()?
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .fetch_one(executor)
             .await
-            .map_err(|e| match e {
-                sqlx::Error::RowNotFound => ApiError::NotFound("No record found".to_string()),
-                _ => ApiError::Database(e),
-            })?;
+    }
+
+    pub async fn fetch_optional<'c, E>(&self, executor: E) -> QueryResult<Option<T>>
+    where
+        E: ConnectionSource<'c, DB>,
+    {
+        self.select_writer()?
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .fetch_optional(executor)
+            .await
+    }
 
-        Ok(item)
+    /// Fetches rows into any `FromRow` type, e.g. a tuple or a struct for aggregate results.
+    pub async fn fetch_all_as<'c, R, E>(&self, executor: E) -> QueryResult<Vec<R>>
+    where
+        E: ConnectionSource<'c, DB>,
+        R: for<'r> FromRow<'r, DB::Row> + Send + Unpin,
+    {
+        self.select_writer()?
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .fetch_all(executor)
+            .await
     }
 
-    pub async fn fetch_optional(&self, pool: &Pool<Postgres>) -> QueryResult<Option<T>> {
-        let item = self
-            .build_select_query()
-            .build_query_as::<T>()
-            .fetch_optional(pool)
+    pub async fn fetch_one_as<'c, R, E>(&self, executor: E) -> QueryResult<R>
+    where
+        E: ConnectionSource<'c, DB>,
+        R: for<'r> FromRow<'r, DB::Row> + Send + Unpin,
+    {
+        self.select_writer()?
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .fetch_one(executor)
             .await
-            .map_err(ApiError::Database)?;
+    }
+
+    /// Executes a query configured with `cursor_paginate` and returns one page,
+    /// with signed cursors pointing to the next and previous pages.
+    pub async fn fetch_cursor_page<'c, E>(
+        &self,
+        executor: E,
+        secret: &[u8],
+    ) -> QueryResult<CursorPage<T>>
+    where
+        E: ConnectionSource<'c, DB>,
+    {
+        let keyset = self.keyset.as_ref().ok_or_else(|| {
+            ApiError::InvalidQuery(
+                "cursor_paginate must be called before fetching a page".to_string(),
+            )
+        })?;
+
+        let mut items = self.fetch_all(executor).await?;
+        let has_more = items.len() > keyset.limit as usize;
+        items.truncate(keyset.limit as usize);
+
+        let backward = keyset.cursor.as_ref().is_some_and(|c| c.backward);
+        if backward {
+            items.reverse();
+        }
+
+        // Going forward there is a next page if we over-fetched, and a previous page if
+        // we started from a cursor; the opposite holds when going backward.
+        let (has_next, has_prev) = if backward {
+            (true, has_more)
+        } else {
+            (has_more, keyset.cursor.is_some())
+        };
+
+        let next_cursor = match items.last() {
+            Some(last) if has_next => {
+                Some(Cursor::from_entry(last, &keyset.columns, false)?.encode(secret)?)
+            }
+            _ => None,
+        };
+        let prev_cursor = match items.first() {
+            Some(first) if has_prev => {
+                Some(Cursor::from_entry(first, &keyset.columns, true)?.encode(secret)?)
+            }
+            _ => None,
+        };
 
-        Ok(item)
+        Ok(CursorPage {
+            items,
+            next_cursor,
+            prev_cursor,
+        })
     }
 
-    pub async fn count(&self, pool: &Pool<Postgres>) -> QueryResult<i64> {
-        let mut query_builder = QueryBuilder::new("SELECT COUNT(*) FROM ");
+    pub async fn count<'c, E>(&self, executor: E) -> QueryResult<i64>
+    where
+        E: ConnectionSource<'c, DB>,
+    {
+        self.check_tenant_scope()?;
+        let mut query_builder = SqlWriter::new(DB::DB_TYPE, "SELECT COUNT(*) FROM ");
         query_builder.push(T::table_name());
 
         // Add JOINs
-        for join in &self.joins {
-            query_builder.push(" ");
-            query_builder.push(join.join_type.to_sql());
-            query_builder.push(" ");
-            query_builder.push(&join.table);
-            query_builder.push(" ON ");
-            query_builder.push(&join.on_condition);
-        }
+        self.push_joins(&mut query_builder);
 
         // Add WHERE conditions
-        if !self.where_clauses.is_empty() {
-            query_builder.push(" WHERE ");
-            self.build_where_conditions(&mut query_builder);
+        self.push_where(self.trashed, None, &mut query_builder);
+
+        let row = query_builder
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .fetch_row(executor)
+            .await?;
+        DB::get_i64(&row, 0).map_err(ApiError::Database)
+    }
+
+    // Execution methods for UPDATE
+    pub async fn update<'c, E>(&self, executor: E) -> QueryResult<u64>
+    where
+        E: ConnectionSource<'c, DB>,
+    {
+        let result = self
+            .update_writer()?
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .execute(executor)
+            .await?;
+
+        Ok(DB::rows_affected(&result))
+    }
+
+    /// UPDATE ... RETURNING *. Without RETURNING (MySQL), the matching rows are locked
+    /// and read back after the update, in a transaction (a savepoint if `conn` is
+    /// already in one).
+    pub async fn update_returning<'c, A>(&self, conn: A) -> QueryResult<Vec<T>>
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        let mut conn = conn.acquire().await?;
+        self.update_returning_on(&mut conn).await
+    }
+
+    // `update_returning` on a connection: unlike an `Acquire` of a transaction, taking
+    // it keeps the futures awaiting it provably `Send`
+    pub(crate) async fn update_returning_on(
+        &self,
+        conn: &mut DB::Connection,
+    ) -> QueryResult<Vec<T>> {
+        let statement = self
+            .update_writer()?
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout);
+
+        if DB::DB_TYPE.supports_returning() {
+            return statement.push(" RETURNING *").fetch_all(&mut *conn).await;
         }
+        self.execute_locked(conn, statement, false).await
+    }
 
-        let count: (i64,) = query_builder
-            .build_query_as()
-            .fetch_one(pool)
-            .await
-            .map_err(ApiError::Database)?;
+    // Execution methods for INSERT
+    pub async fn insert<'c, E>(&self, executor: E) -> QueryResult<u64>
+    where
+        E: ConnectionSource<'c, DB>,
+    {
+        let result = self
+            .insert_writer(&self.rows_to_insert()?)?
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .execute(executor)
+            .await?;
 
-        Ok(count.0)
+        Ok(DB::rows_affected(&result))
     }
 
-    // Execution methods for UPDATE
-    pub async fn update(&self, pool: &Pool<Postgres>) -> QueryResult<u64> {
-        let mut query = self.build_update_query()?;
-        let result = query
-            .build()
-            .execute(pool)
-            .await
-            .map_err(ApiError::Database)?;
+    /// INSERT ... RETURNING *. `conn` is a pool, a connection or a transaction; without
+    /// RETURNING (MySQL) the row is read back on the same connection, see
+    /// `insert_returning_optional`.
+    pub async fn insert_returning<'c, A>(&self, conn: A) -> QueryResult<T>
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        let mut conn = conn.acquire().await?;
+        self.insert_returning_on(&mut conn).await
+    }
+
+    // `insert_returning` on a connection, see `update_returning_on`
+    pub(crate) async fn insert_returning_on(&self, conn: &mut DB::Connection) -> QueryResult<T> {
+        self.insert_returning_optional_on(conn)
+            .await?
+            .ok_or_else(|| ApiError::NotFound("No record inserted".to_string()))
+    }
+
+    /// INSERT ... RETURNING that yields None when ON CONFLICT DO NOTHING skipped the row
+    /// (or DO UPDATE ... WHERE did not match).
+    ///
+    /// Without RETURNING (MySQL), the row is read back by the conflict columns, else by
+    /// the `id` of the inserted data, else by the AUTO_INCREMENT id.
+    pub async fn insert_returning_optional<'c, A>(&self, conn: A) -> QueryResult<Option<T>>
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        let mut conn = conn.acquire().await?;
+        self.insert_returning_optional_on(&mut conn).await
+    }
+
+    // `insert_returning_optional` on a connection, see `update_returning_on`
+    pub(crate) async fn insert_returning_optional_on(
+        &self,
+        conn: &mut DB::Connection,
+    ) -> QueryResult<Option<T>> {
+        if DB::DB_TYPE.supports_returning() {
+            return self
+                .insert_writer(&self.rows_to_insert()?)?
+                .into_statement()
+                .with_table(T::table_name())
+                .with_timeout(self.timeout)
+                .push(" RETURNING *")
+                .fetch_optional(&mut *conn)
+                .await;
+        }
+        match &*self.rows_to_insert()? {
+            [row] => self.insert_read_back(conn, row).await,
+            _ => Err(ApiError::InvalidQuery(
+                "Use insert_many_returning to insert several rows".to_string(),
+            )),
+        }
+    }
+
+    /// Multi-row INSERT ... RETURNING *, split into several statements so that
+    /// none exceeds the bind-parameter limit. Run it inside a transaction for atomicity.
+    /// Without RETURNING (MySQL), rows are inserted and read back one by one.
+    ///
+    /// On Postgres, batches of `COPY_THRESHOLD` rows or more go through
+    /// `copy_insert_returning`, in a transaction (or a savepoint) of their own, unless
+    /// they write array columns.
+    pub async fn insert_many_returning(&self, conn: &mut DB::Connection) -> QueryResult<Vec<T>> {
+        let rows = self.rows_to_insert()?;
+        let columns = Self::insert_columns(&rows)?;
+
+        if DB::DB_TYPE == DbType::Postgres
+            && rows.len() >= COPY_THRESHOLD
+            && Self::copy_unsupported_column(&rows, &columns).is_none()
+        {
+            let mut tx = conn.begin().await?;
+            let items = self.copy_insert_returning(&mut tx).await?;
+            tx.commit().await?;
+            return Ok(items);
+        }
+
+        if !DB::DB_TYPE.supports_returning() {
+            let mut items = Vec::with_capacity(rows.len());
+            for row in rows.iter() {
+                items.extend(self.insert_read_back(conn, row).await?);
+            }
+            return Ok(items);
+        }
+
+        let chunk_size = (DB::DB_TYPE.max_bind_params() / columns.len()).max(1);
+        let mut items = Vec::with_capacity(rows.len());
+        for chunk in rows.chunks(chunk_size) {
+            let chunk_items = self
+                .insert_writer(chunk)?
+                .into_statement()
+                .with_table(T::table_name())
+                .with_timeout(self.timeout)
+                .push(" RETURNING *")
+                .fetch_all(&mut *conn)
+                .await?;
+            items.extend(chunk_items);
+        }
 
-        Ok(result.rows_affected())
+        Ok(items)
     }
 
-    pub async fn update_returning(&self, pool: &Pool<Postgres>) -> QueryResult<Vec<T>> {
-        let mut query = self.build_update_query()?;
+    /// Bulk insert through `COPY FROM STDIN`, for batches too large for INSERT statements.
+    /// Postgres only.
+    ///
+    /// Rows are copied into a temporary staging table, then moved with
+    /// `INSERT ... SELECT ... RETURNING *` so the ON CONFLICT clause and the returned
+    /// rows behave as with `insert_many_returning`. The staging table is dropped once
+    /// the rows are moved, or with the transaction on failure, so `conn` must be inside
+    /// a transaction.
+    ///
+    /// Array columns are refused: their values would be written as JSON, which COPY
+    /// does not read as array literals.
+    pub async fn copy_insert_returning(&self, conn: &mut DB::Connection) -> QueryResult<Vec<T>> {
+        Self::require_postgres("COPY")?;
+        if DB::TransactionManager::get_transaction_depth(conn) == 0 {
+            return Err(ApiError::InvalidQuery(
+                "COPY must run inside a transaction, its staging table is dropped on commit"
+                    .to_string(),
+            ));
+        }
+
+        let rows = self.rows_to_insert()?;
+        let column_names = Self::insert_columns(&rows)?;
+        if let Some(column) = Self::copy_unsupported_column(&rows, &column_names) {
+            return Err(ApiError::InvalidQuery(format!(
+                "COPY cannot write the array column {}, use insert_many_returning",
+                column
+            )));
+        }
+        let columns = column_names.join(", ");
+        let staging_table = format!("{}_copy_{}", T::table_name(), uuid::Uuid::new_v4().simple());
+
+        let mut create = SqlWriter::new(DB::DB_TYPE, "CREATE TEMP TABLE ");
+        create.push(format!(
+            "{} ON COMMIT DROP AS SELECT {} FROM {} WITH NO DATA",
+            staging_table,
+            columns,
+            T::table_name()
+        ));
+        create
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .execute(&mut *conn)
+            .await?;
+
+        let mut buffer = String::new();
+        for row in rows.iter() {
+            buffer.push_str(&csv_line(
+                column_names
+                    .iter()
+                    .map(|column| row.get(*column).unwrap_or(&Value::Null)),
+            ));
+        }
+        DB::copy_in(
+            conn,
+            &format!(
+                "COPY {} ({}) FROM STDIN WITH (FORMAT csv)",
+                staging_table, columns
+            ),
+            buffer.into_bytes(),
+        )
+        .await?;
+
+        let mut query = SqlWriter::new(DB::DB_TYPE, "INSERT INTO ");
+        query.push(T::table_name());
+        query.push(format!(
+            " ({}) SELECT {} FROM {}",
+            columns, columns, staging_table
+        ));
+        if let Some(on_conflict) = &self.on_conflict {
+            self.build_on_conflict(on_conflict, &mut query);
+        }
         query.push(" RETURNING *");
 
         let items = query
-            .build_query_as::<T>()
-            .fetch_all(pool)
-            .await
-            .map_err(ApiError::Database)?;
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .fetch_all(&mut *conn)
+            .await?;
+
+        SqlWriter::new(DB::DB_TYPE, format!("DROP TABLE {}", staging_table))
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .execute(&mut *conn)
+            .await?;
 
         Ok(items)
     }
 
-    // Execution methods for INSERT
-    pub async fn insert(&self, pool: &Pool<Postgres>) -> QueryResult<u64> {
-        let mut query = self.build_insert_query()?;
-        let result = query
-            .build()
-            .execute(pool)
-            .await
-            .map_err(ApiError::Database)?;
+    // First column COPY cannot write: a declared array column, or one without a declared
+    // type given array values, which may as well be an array as a json column
+    fn copy_unsupported_column<'a>(
+        rows: &[HashMap<String, Value>],
+        columns: &[&'a str],
+    ) -> Option<&'a str> {
+        columns
+            .iter()
+            .copied()
+            .find(|column| match Self::column_type(column) {
+                Some(ColumnType::Array(_)) => true,
+                Some(_) => false,
+                None => rows
+                    .iter()
+                    .any(|row| matches!(row.get(*column), Some(Value::Array(_)))),
+            })
+    }
+
+    // Execution methods for DELETE
+    pub async fn delete<'c, E>(&self, executor: E) -> QueryResult<u64>
+    where
+        E: ConnectionSource<'c, DB>,
+    {
+        let result = self
+            .delete_writer(false)?
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .execute(executor)
+            .await?;
 
-        Ok(result.rows_affected())
+        Ok(DB::rows_affected(&result))
     }
 
-    pub async fn insert_returning(&self, pool: &Pool<Postgres>) -> QueryResult<T> {
-        let mut query = self.build_insert_query()?;
-        query.push(" RETURNING *");
+    /// Deletes the matching rows for good, even for entries with a soft delete column.
+    /// Soft-deleted rows are only matched with `with_trashed` or `only_trashed`.
+    pub async fn force_delete<'c, E>(&self, executor: E) -> QueryResult<u64>
+    where
+        E: ConnectionSource<'c, DB>,
+    {
+        let result = self
+            .delete_writer(true)?
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .execute(executor)
+            .await?;
 
-        let item = query
-            .build_query_as::<T>()
-            .fetch_one(pool)
-            .await
-            .map_err(|e| match e {
-                sqlx::Error::RowNotFound => ApiError::NotFound("No record inserted".to_string()),
-                _ => ApiError::Database(e),
-            })?;
+        Ok(DB::rows_affected(&result))
+    }
 
-        Ok(item)
+    /// Restores the matching soft-deleted rows. Fails if the entry has no soft delete column.
+    pub async fn restore<'c, E>(&self, executor: E) -> QueryResult<u64>
+    where
+        E: ConnectionSource<'c, DB>,
+    {
+        let result = self
+            .restore_writer()?
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .execute(executor)
+            .await?;
+
+        Ok(DB::rows_affected(&result))
     }
 
-    // Execution methods for DELETE
-    pub async fn delete(&self, pool: &Pool<Postgres>) -> QueryResult<u64> {
-        let mut query = self.build_delete_query();
-        let result = query
-            .build()
-            .execute(pool)
+    /// DELETE ... RETURNING *, see `update_returning` for databases without RETURNING.
+    pub async fn delete_returning<'c, A>(&self, conn: A) -> QueryResult<Vec<T>>
+    where
+        A: Acquire<'c, Database = DB> + Send,
+    {
+        let mut conn = conn.acquire().await?;
+        let statement = self
+            .delete_writer(false)?
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout);
+
+        if DB::DB_TYPE.supports_returning() {
+            return statement.push(" RETURNING *").fetch_all(&mut *conn).await;
+        }
+        let hard_delete = T::soft_delete_column().is_none();
+        self.execute_locked(&mut conn, statement, hard_delete).await
+    }
+
+    /// SELECT ... FOR UPDATE of the matching rows (WHERE only), which stay locked until
+    /// the end of the transaction `executor` runs in. SQLite has no row locks: the rows
+    /// are only read.
+    pub async fn fetch_for_update<'c, E>(&self, executor: E) -> QueryResult<Vec<T>>
+    where
+        E: ConnectionSource<'c, DB>,
+    {
+        self.check_tenant_scope()?;
+        let mut select = SqlWriter::new(DB::DB_TYPE, "SELECT ");
+        select.push(T::columns().join(", "));
+        select.push(" FROM ");
+        select.push(T::table_name());
+        self.push_where(self.trashed, None, &mut select);
+        if DB::DB_TYPE != DbType::SQLite {
+            select.push(" FOR UPDATE");
+        }
+        select
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .fetch_all(executor)
             .await
-            .map_err(ApiError::Database)?;
+    }
+}
 
-        Ok(result.rows_affected())
+impl<T: Entry + Send + Sync + Unpin + 'static, DB: Backend> QueryBuilderUtil<T, DB> {
+    /// # Method that must be used to bind values to the query
+    /// Values are bound with the SQL type `Entry::column_type` declares for `column`, and
+    /// from their JSON form when the column is unknown (e.g. raw fragments); strings are
+    /// then bound as text, never reinterpreted.
+    fn bind_value(&self, query_builder: &mut SqlWriter, column: Option<&str>, value: Value) {
+        let value = match column.and_then(Self::column_type) {
+            Some(column_type) => SqlValue::typed(value, column_type),
+            None => SqlValue::untyped(value),
+        };
+        query_builder.push_bind(value);
     }
 
-    pub async fn delete_returning(&self, pool: &Pool<Postgres>) -> QueryResult<Vec<T>> {
-        let mut query = self.build_delete_query();
-        query.push(" RETURNING *");
+    /// Declared type of a column of the entry, possibly prefixed by the table name.
+    fn column_type(column: &str) -> Option<ColumnType> {
+        let column = column
+            .strip_prefix(T::table_name())
+            .and_then(|column| column.strip_prefix('.'))
+            .unwrap_or(column);
+        T::column_type(column)
+    }
 
-        let items = query
-            .build_query_as::<T>()
-            .fetch_all(pool)
-            .await
-            .map_err(ApiError::Database)?;
+    /// Binds values as one array of the given element type, so that the parameter is
+    /// typed (e.g. uuid[]) whatever the column.
+    pub(super) fn bind_array(
+        &self,
+        query_builder: &mut SqlWriter,
+        array_type: ArrayType,
+        values: Option<&[Value]>,
+    ) {
+        query_builder.push_bind(SqlValue::array(array_type, values.unwrap_or_default()));
+    }
 
-        Ok(items)
+    /// Rows to insert, stamped with the tenant in scope for entries with a tenant column.
+    fn rows_to_insert(&self) -> QueryResult<Cow<'_, [HashMap<String, Value>]>> {
+        self.check_tenant_scope()?;
+        let rows = if self.insert_rows.is_empty() {
+            std::slice::from_ref(&self.insert_data)
+        } else {
+            &self.insert_rows
+        };
+        let Some((column, tenant)) = T::tenant_column().zip(self.tenant()) else {
+            return Ok(Cow::Borrowed(rows));
+        };
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let mut row = row.clone();
+                if !row.is_empty() {
+                    row.insert(column.to_string(), tenant.clone());
+                }
+                row
+            })
+            .collect())
     }
 
-    /// # Method that must be used to bind values to the query
-    /// It handles the conversion of `Value::String` to `String` for proper binding
-    fn bind_value(&self, query_builder: &mut QueryBuilder<'_, Postgres>, value: Value) {
-        match value {
-            // Handle UUIDs represented as strings
-            Value::String(ref s) => {
-                // Try to parse as UUID, otherwise bind as string
-                if let Ok(uuid) = uuid::Uuid::parse_str(s) {
-                    query_builder.push_bind(uuid);
-                } else {
-                    query_builder.push_bind(s.clone());
+    fn push_sql_expr(&self, expression: &SqlExpr, query_builder: &mut SqlWriter) {
+        for part in &expression.parts {
+            match part {
+                SqlPart::Sql(sql) => {
+                    query_builder.push(sql);
                 }
+                SqlPart::Bind(value) => self.bind_value(query_builder, None, value.clone()),
             }
-            _ => {
-                query_builder.push_bind(value);
-            }
-        };
+        }
+    }
+
+    /// Cursor values come from serialized entries (timestamps as RFC 3339 strings...), so
+    /// they are bound with the declared type of their column, which `keyset_paginate`
+    /// requires.
+    fn bind_cursor_value(&self, query_builder: &mut SqlWriter, column: &str, value: Value) {
+        self.bind_value(query_builder, Some(column), value);
+    }
+}
+
+impl<T: Entry + Send + Sync + Unpin + 'static, DB: Backend> Subquery for QueryBuilderUtil<T, DB> {
+    fn push_sql(&self, query_builder: &mut SqlWriter) {
+        self.push_select_query(query_builder);
+    }
+
+    fn check_tenant_scope(&self) -> QueryResult<()> {
+        QueryBuilderUtil::check_tenant_scope(self)
     }
 }
 
 // ========== BUILDER POUR GROUPES ==========
 
-/// Specialized builder to construct groups of conditions
-pub struct GroupBuilder<T: Entry> {
+/// Specialized builder to construct groups of conditions, for the database `DB` of the
+/// query they belong to.
+pub struct GroupBuilder<T: Entry, DB: Backend = Postgres> {
     pub(crate) clauses: Vec<(WhereClause, Option<LogicalOperator>)>,
-    _phantom: std::marker::PhantomData<T>,
+    pub(crate) joins: Vec<JoinClause>,
+    _phantom: std::marker::PhantomData<(T, DB)>,
+}
+
+impl<T: Entry, DB: Backend> Default for GroupBuilder<T, DB> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl<T: Entry> GroupBuilder<T> {
+impl<T: Entry, DB: Backend> GroupBuilder<T, DB> {
     pub fn new() -> Self {
         Self {
             clauses: Vec::new(),
+            joins: Vec::new(),
             _phantom: std::marker::PhantomData,
         }
     }
@@ -954,6 +2967,173 @@ impl<T: Entry> GroupBuilder<T> {
         Ok(self)
     }
 
+    /// Raw SQL condition; each `{}` is a placeholder for the next value, bound as a
+    /// `$n` parameter numbered with the rest of the query. The SQL text itself is not
+    /// validated and must never contain user input.
+    /// Example: where_raw("lower(email) = lower({})", ["John@Example.com"])
+    /// Result: WHERE (lower(email) = lower($1))
+    pub fn where_raw<I, V>(mut self, sql: &str, values: I) -> Result<Self, ApiError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        let expression = SqlExpr::raw(sql, values)?;
+        self.clauses.push((WhereClause::Raw(expression), None));
+        Ok(self)
+    }
+
+    // JSONB and array conditions
+
+    /// Example: where_json_contains("metadata", json!({"plan": "pro"}))
+    /// Result: WHERE metadata @> $1 (bound as jsonb)
+    pub fn where_json_contains(mut self, column: &str, document: Value) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition = WhereCondition::json(
+            DB::DB_TYPE,
+            column,
+            ComparisonOperator::JsonContains,
+            document,
+        )?;
+        self.clauses.push((WhereClause::Condition(condition), None));
+        Ok(self)
+    }
+
+    pub fn where_json_contained_by(
+        mut self,
+        column: &str,
+        document: Value,
+    ) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition = WhereCondition::json(
+            DB::DB_TYPE,
+            column,
+            ComparisonOperator::JsonContainedBy,
+            document,
+        )?;
+        self.clauses.push((WhereClause::Condition(condition), None));
+        Ok(self)
+    }
+
+    /// Result: WHERE metadata ? $1
+    pub fn where_json_has_key(mut self, column: &str, key: &str) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition =
+            WhereCondition::json_keys(DB::DB_TYPE, column, ComparisonOperator::JsonHasKey, &[key])?;
+        self.clauses.push((WhereClause::Condition(condition), None));
+        Ok(self)
+    }
+
+    /// Result: WHERE metadata ?| $1 (bound as text[])
+    pub fn where_json_has_any_key(mut self, column: &str, keys: &[&str]) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition = WhereCondition::json_keys(
+            DB::DB_TYPE,
+            column,
+            ComparisonOperator::JsonHasAnyKey,
+            keys,
+        )?;
+        self.clauses.push((WhereClause::Condition(condition), None));
+        Ok(self)
+    }
+
+    /// Result: WHERE metadata ?& $1 (bound as text[])
+    pub fn where_json_has_all_keys(
+        mut self,
+        column: &str,
+        keys: &[&str],
+    ) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition = WhereCondition::json_keys(
+            DB::DB_TYPE,
+            column,
+            ComparisonOperator::JsonHasAllKeys,
+            keys,
+        )?;
+        self.clauses.push((WhereClause::Condition(condition), None));
+        Ok(self)
+    }
+
+    /// Compares the text at `path` in a JSONB column.
+    /// Example: where_json_path("metadata", &["address", "city"], ComparisonOperator::Equal, Some("Paris"))
+    /// Result: WHERE (users.metadata#>>'{address,city}') = $1
+    pub fn where_json_path(
+        mut self,
+        column: &str,
+        path: &[&str],
+        operator: ComparisonOperator,
+        value: Option<&str>,
+    ) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition = WhereCondition::json_path(
+            DB::DB_TYPE,
+            &qualify::<T>(column),
+            path,
+            operator,
+            value.map(str::to_string),
+        )?;
+        self.clauses.push((WhereClause::Condition(condition), None));
+        Ok(self)
+    }
+
+    /// Example: where_json_path_eq("metadata", &["status"], "active")
+    /// Result: WHERE (users.metadata->>'status') = $1
+    pub fn where_json_path_eq(
+        self,
+        column: &str,
+        path: &[&str],
+        value: &str,
+    ) -> Result<Self, ApiError> {
+        self.where_json_path(column, path, ComparisonOperator::Equal, Some(value))
+    }
+
+    /// Example: where_any("id", ids)
+    /// Result: WHERE id = ANY($1) (bound as one typed array, e.g. uuid[])
+    pub fn where_any<V: ArrayElement>(
+        mut self,
+        column: &str,
+        values: Vec<V>,
+    ) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition =
+            WhereCondition::array(DB::DB_TYPE, column, ComparisonOperator::AnyOf, values)?;
+        self.clauses.push((WhereClause::Condition(condition), None));
+        Ok(self)
+    }
+
+    /// Result: WHERE tags && $1
+    pub fn where_array_overlaps<V: ArrayElement>(
+        mut self,
+        column: &str,
+        values: Vec<V>,
+    ) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition = WhereCondition::array(
+            DB::DB_TYPE,
+            column,
+            ComparisonOperator::ArrayOverlaps,
+            values,
+        )?;
+        self.clauses.push((WhereClause::Condition(condition), None));
+        Ok(self)
+    }
+
+    /// Result: WHERE tags @> $1
+    pub fn where_array_contains<V: ArrayElement>(
+        mut self,
+        column: &str,
+        values: Vec<V>,
+    ) -> Result<Self, ApiError> {
+        self.validate_column(column)?;
+        let condition = WhereCondition::array(
+            DB::DB_TYPE,
+            column,
+            ComparisonOperator::ArrayContains,
+            values,
+        )?;
+        self.clauses.push((WhereClause::Condition(condition), None));
+        Ok(self)
+    }
+
     pub fn and(mut self) -> Self {
         if let Some(last) = self.clauses.last_mut() {
             last.1 = Some(LogicalOperator::And);
@@ -969,9 +3149,30 @@ impl<T: Entry> GroupBuilder<T> {
     }
 
     fn validate_column(&self, column: &str) -> Result<(), ApiError> {
-        if !T::columns().contains(&column) {
+        if !is_known_column::<T>(column, &self.joins) {
             return Err(ApiError::InvalidColumn(column.to_string()));
         }
         Ok(())
     }
 }
+
+// Columns inside computed expressions are always qualified: the expression is not
+// a plain column that column_sql could qualify when rendering
+fn qualify<T: Entry>(column: &str) -> String {
+    if column.contains('.') {
+        column.to_string()
+    } else {
+        format!("{}.{}", T::table_name(), column)
+    }
+}
+
+// A column of `T` (`email` or `users.email`) or of a joined table (`orders.total`)
+fn is_known_column<T: Entry>(column: &str, joins: &[JoinClause]) -> bool {
+    match column.split_once('.') {
+        Some((table, name)) if table == T::table_name() => T::columns().contains(&name),
+        Some((table, name)) => joins
+            .iter()
+            .any(|join| join.table == table && join.columns.contains(&name)),
+        None => T::columns().contains(&column),
+    }
+}
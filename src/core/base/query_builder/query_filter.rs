@@ -0,0 +1,443 @@
+use std::future::{Ready, ready};
+
+use actix_web::{FromRequest, HttpRequest, dev::Payload, web};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json::Value;
+use sqlx::Postgres;
+use utoipa::{
+    IntoParams,
+    openapi::{
+        Required,
+        path::{Parameter, ParameterBuilder, ParameterIn},
+        schema::{ObjectBuilder, Type},
+    },
+};
+
+use crate::core::{
+    base::{
+        generic_repository::entry_trait::Entry,
+        query_builder::{
+            backend::Backend,
+            cursor::Cursor,
+            generic_query_builder::DbType,
+            query_builder::QueryBuilderUtil,
+            query_models::{OrderDirection, QueryResult},
+        },
+    },
+    errors::errors::ApiError,
+};
+
+/// Operators a client can use in `filter[column][operator]=value`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOperator {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Like,
+    Ilike,
+    /// Comma-separated list of values
+    In,
+    /// `true` for IS NULL, `false` for IS NOT NULL
+    Null,
+}
+
+impl FilterOperator {
+    const ALL: [FilterOperator; 10] = [
+        FilterOperator::Eq,
+        FilterOperator::Ne,
+        FilterOperator::Gt,
+        FilterOperator::Gte,
+        FilterOperator::Lt,
+        FilterOperator::Lte,
+        FilterOperator::Like,
+        FilterOperator::Ilike,
+        FilterOperator::In,
+        FilterOperator::Null,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterOperator::Eq => "eq",
+            FilterOperator::Ne => "ne",
+            FilterOperator::Gt => "gt",
+            FilterOperator::Gte => "gte",
+            FilterOperator::Lt => "lt",
+            FilterOperator::Lte => "lte",
+            FilterOperator::Like => "like",
+            FilterOperator::Ilike => "ilike",
+            FilterOperator::In => "in",
+            FilterOperator::Null => "null",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|operator| operator.name() == name)
+    }
+}
+
+/// How the values of a filterable column are parsed from the query string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    Text,
+    Integer,
+    Uuid,
+    /// RFC 3339 date-time or `YYYY-MM-DD` (midnight UTC)
+    Timestamp,
+}
+
+/// A column clients can filter on, with the operators allowed for it.
+#[derive(Debug, Clone, Copy)]
+pub struct FilterField {
+    pub column: &'static str,
+    pub filter_type: FilterType,
+    pub operators: &'static [FilterOperator],
+}
+
+impl FilterField {
+    pub const fn new(
+        column: &'static str,
+        filter_type: FilterType,
+        operators: &'static [FilterOperator],
+    ) -> Self {
+        Self {
+            column,
+            filter_type,
+            operators,
+        }
+    }
+}
+
+/// Whitelist of what the clients of a resource can filter and sort on.
+pub trait FilterSpec {
+    type Entry: Entry + Send + Sync + Unpin + 'static;
+
+    const FILTERS: &'static [FilterField];
+
+    const SORTS: &'static [&'static str];
+
+    /// Order used when the request has no `sort`
+    const DEFAULT_SORT: &'static [(&'static str, OrderDirection)] = &[];
+
+    const DEFAULT_PAGE_SIZE: u32 = 20;
+
+    const MAX_PAGE_SIZE: u32 = 100;
+}
+
+/// Actix extractor for the filter, sort and page size parameters of a list endpoint,
+/// paginated with a cursor:
+///
+/// `?filter[email][ilike]=%foo%&filter[created_at][gte]=2024-01-01&sort=-created_at,username&page[size]=20`
+///
+/// Conditions are validated against the `FilterSpec` whitelist while extracting; a
+/// column or operator outside of it is rejected with `ApiError::InvalidColumn` (400).
+/// `limit` is accepted as an alias of `page[size]`. Other query parameters are ignored,
+/// so that the endpoint can read them separately.
+pub struct QueryFilter<S: FilterSpec, DB: Backend = Postgres> {
+    query: QueryBuilderUtil<S::Entry, DB>,
+    sort: Vec<(&'static str, OrderDirection)>,
+    page_size: Option<u32>,
+}
+
+impl<S: FilterSpec, DB: Backend> QueryFilter<S, DB> {
+    pub fn from_query_string(query_string: &str) -> QueryResult<Self> {
+        let pairs = web::Query::<Vec<(String, String)>>::from_query(query_string)
+            .map_err(|e| ApiError::BadRequest(e.to_string()))?
+            .into_inner();
+
+        let mut filter = Self {
+            query: QueryBuilderUtil::new(),
+            sort: Vec::new(),
+            page_size: None,
+        };
+
+        for (key, value) in pairs {
+            if let Some(condition) = key.strip_prefix("filter[") {
+                filter.query = apply_filter::<S, DB>(filter.query, condition, &value)?;
+            } else if key == "sort" {
+                filter.sort = parse_sort::<S>(&value)?;
+            } else if key == "page[size]" || key == "limit" {
+                filter.page_size = Some(parse_page_size(&key, &value, S::MAX_PAGE_SIZE)?);
+            }
+        }
+
+        Ok(filter)
+    }
+
+    /// Requested order, or `FilterSpec::DEFAULT_SORT`.
+    pub fn sort(&self) -> Vec<(&'static str, OrderDirection)> {
+        if self.sort.is_empty() {
+            S::DEFAULT_SORT.to_vec()
+        } else {
+            self.sort.clone()
+        }
+    }
+
+    pub fn page_size(&self) -> u32 {
+        self.page_size.unwrap_or(S::DEFAULT_PAGE_SIZE)
+    }
+
+    /// The query with the filters, paginated with a cursor on the requested order
+    /// (see `QueryBuilderUtil::cursor_paginate_sorted`).
+    pub fn into_cursor_query(
+        self,
+        cursor: Option<Cursor>,
+    ) -> QueryResult<QueryBuilderUtil<S::Entry, DB>> {
+        let sort = self.sort();
+        let page_size = self.page_size();
+
+        self.query.cursor_paginate_sorted(&sort, cursor, page_size)
+    }
+}
+
+impl<S: FilterSpec, DB: Backend> FromRequest for QueryFilter<S, DB> {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Self::from_query_string(req.query_string()))
+    }
+}
+
+impl<S: FilterSpec, DB: Backend> IntoParams for QueryFilter<S, DB> {
+    fn into_params(_: impl Fn() -> Option<ParameterIn>) -> Vec<Parameter> {
+        let query_param = |name: String, schema_type: Type, description: String| {
+            ParameterBuilder::new()
+                .name(name)
+                .parameter_in(ParameterIn::Query)
+                .required(Required::False)
+                .description(Some(description))
+                .schema(Some(ObjectBuilder::new().schema_type(schema_type)))
+                .build()
+        };
+
+        let mut params: Vec<Parameter> = S::FILTERS
+            .iter()
+            .flat_map(|field| {
+                field.operators.iter().map(move |operator| {
+                    query_param(
+                        format!("filter[{}][{}]", field.column, operator.name()),
+                        Type::String,
+                        filter_description(field, *operator),
+                    )
+                })
+            })
+            .collect();
+
+        params.push(query_param(
+            "sort".to_string(),
+            Type::String,
+            format!(
+                "Colonnes de tri séparées par des virgules, préfixées par `-` pour un tri décroissant. Colonnes : {}",
+                S::SORTS.join(", ")
+            ),
+        ));
+        params.push(query_param(
+            "page[size]".to_string(),
+            Type::Integer,
+            format!(
+                "Nombre d'éléments par page (défaut {}, maximum {})",
+                S::DEFAULT_PAGE_SIZE,
+                S::MAX_PAGE_SIZE
+            ),
+        ));
+        params.push(query_param(
+            "limit".to_string(),
+            Type::Integer,
+            "Alias de `page[size]`".to_string(),
+        ));
+        params
+    }
+}
+
+fn filter_description(field: &FilterField, operator: FilterOperator) -> String {
+    let value = match operator {
+        FilterOperator::In => "liste de valeurs séparées par des virgules",
+        FilterOperator::Null => "`true` (IS NULL) ou `false` (IS NOT NULL)",
+        FilterOperator::Like | FilterOperator::Ilike => "motif, `%` pour n'importe quelle suite",
+        _ => match field.filter_type {
+            FilterType::Text => "texte",
+            FilterType::Integer => "entier",
+            FilterType::Uuid => "UUID",
+            FilterType::Timestamp => "date RFC 3339 ou `AAAA-MM-JJ`",
+        },
+    };
+    format!(
+        "Filtre `{}` sur {} : {}",
+        operator.name(),
+        field.column,
+        value
+    )
+}
+
+// Applies `filter[column][operator]=value`, `condition` being the key after `filter[`;
+// `filter[column]=value` stands for the `eq` operator
+fn apply_filter<S: FilterSpec, DB: Backend>(
+    query: QueryBuilderUtil<S::Entry, DB>,
+    condition: &str,
+    value: &str,
+) -> QueryResult<QueryBuilderUtil<S::Entry, DB>> {
+    let malformed = || ApiError::BadRequest(format!("Malformed filter: filter[{}", condition));
+
+    let (column, rest) = condition.split_once(']').ok_or_else(malformed)?;
+    let operator = match rest {
+        "" => "eq",
+        _ => rest
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+            .ok_or_else(malformed)?,
+    };
+
+    let field = S::FILTERS
+        .iter()
+        .find(|field| field.column == column)
+        .ok_or_else(|| ApiError::InvalidColumn(column.to_string()))?;
+    let operator = FilterOperator::from_name(operator)
+        .filter(|operator| field.operators.contains(operator))
+        .ok_or_else(|| {
+            ApiError::InvalidColumn(format!("{} (operator `{}` not allowed)", column, operator))
+        })?;
+
+    if field.filter_type == FilterType::Timestamp {
+        return apply_timestamp_filter(query, field, operator, value);
+    }
+
+    match operator {
+        FilterOperator::Eq => query.where_eq(column, parse_value(field, value)?),
+        FilterOperator::Ne => query.where_ne(column, parse_value(field, value)?),
+        FilterOperator::Gt => query.where_gt(column, parse_value(field, value)?),
+        FilterOperator::Gte => query.where_gte(column, parse_value(field, value)?),
+        FilterOperator::Lt => query.where_lt(column, parse_value(field, value)?),
+        FilterOperator::Lte => query.where_lte(column, parse_value(field, value)?),
+        FilterOperator::Like => query.where_like(column, value),
+        FilterOperator::Ilike => query.where_ilike(column, value),
+        FilterOperator::In => {
+            let values = value
+                .split(',')
+                .map(|item| parse_value(field, item))
+                .collect::<QueryResult<Vec<Value>>>()?;
+            query.where_in(column, values)
+        }
+        FilterOperator::Null => match parse_bool(field, value)? {
+            true => query.where_null(column),
+            false => query.where_not_null(column),
+        },
+    }
+}
+
+// Timestamps are bound as text, so Postgres compares them through an explicit cast;
+// MySQL and SQLite convert the text themselves
+fn apply_timestamp_filter<T: Entry + Send + Sync + Unpin + 'static, DB: Backend>(
+    query: QueryBuilderUtil<T, DB>,
+    field: &FilterField,
+    operator: FilterOperator,
+    value: &str,
+) -> QueryResult<QueryBuilderUtil<T, DB>> {
+    let column = field.column;
+    let placeholder = match DB::DB_TYPE {
+        DbType::Postgres => "CAST({} AS timestamptz)",
+        DbType::MySQL | DbType::SQLite => "{}",
+    };
+    let comparison = match operator {
+        FilterOperator::Eq => "=",
+        FilterOperator::Ne => "<>",
+        FilterOperator::Gt => ">",
+        FilterOperator::Gte => ">=",
+        FilterOperator::Lt => "<",
+        FilterOperator::Lte => "<=",
+        FilterOperator::In => {
+            let values = value
+                .split(',')
+                .map(|item| parse_value(field, item))
+                .collect::<QueryResult<Vec<Value>>>()?;
+            let placeholders = vec![placeholder; values.len()].join(", ");
+            return query.where_raw(&format!("{} IN ({})", column, placeholders), values);
+        }
+        FilterOperator::Null => {
+            return match parse_bool(field, value)? {
+                true => query.where_null(column),
+                false => query.where_not_null(column),
+            };
+        }
+        FilterOperator::Like | FilterOperator::Ilike => {
+            return Err(ApiError::InvalidColumn(format!(
+                "{} (operator `{}` not supported on timestamps)",
+                column,
+                operator.name()
+            )));
+        }
+    };
+
+    query.where_raw(
+        &format!("{} {} {}", column, comparison, placeholder),
+        [parse_value(field, value)?],
+    )
+}
+
+fn parse_value(field: &FilterField, value: &str) -> QueryResult<Value> {
+    let invalid = || {
+        ApiError::BadRequest(format!(
+            "Invalid value for filter on {}: {}",
+            field.column, value
+        ))
+    };
+
+    Ok(match field.filter_type {
+        FilterType::Text => Value::String(value.to_string()),
+        FilterType::Integer => value.parse::<i64>().map_err(|_| invalid())?.into(),
+        FilterType::Uuid => {
+            let uuid = uuid::Uuid::parse_str(value).map_err(|_| invalid())?;
+            Value::String(uuid.to_string())
+        }
+        FilterType::Timestamp => {
+            let timestamp = DateTime::parse_from_rfc3339(value)
+                .map(|timestamp| timestamp.with_timezone(&Utc))
+                .or_else(|_| {
+                    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                        .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+                })
+                .map_err(|_| invalid())?;
+            Value::String(timestamp.to_rfc3339())
+        }
+    })
+}
+
+fn parse_bool(field: &FilterField, value: &str) -> QueryResult<bool> {
+    value.parse().map_err(|_| {
+        ApiError::BadRequest(format!(
+            "Invalid value for filter on {}: {} (expected true or false)",
+            field.column, value
+        ))
+    })
+}
+
+fn parse_sort<S: FilterSpec>(value: &str) -> QueryResult<Vec<(&'static str, OrderDirection)>> {
+    value
+        .split(',')
+        .map(|item| {
+            let (column, direction) = match item.strip_prefix('-') {
+                Some(column) => (column, OrderDirection::Desc),
+                None => (item, OrderDirection::Asc),
+            };
+            S::SORTS
+                .iter()
+                .find(|sortable| **sortable == column)
+                .map(|sortable| (*sortable, direction))
+                .ok_or_else(|| ApiError::InvalidColumn(format!("{} (not sortable)", column)))
+        })
+        .collect()
+}
+
+fn parse_page_size(key: &str, value: &str, max: u32) -> QueryResult<u32> {
+    value
+        .parse::<u32>()
+        .ok()
+        .filter(|number| (1..=max).contains(number))
+        .ok_or_else(|| {
+            ApiError::BadRequest(format!("{} must be an integer between 1 and {}", key, max))
+        })
+}
@@ -1,5 +1,12 @@
-use crate::core::errors::errors::ApiError;
+use crate::core::{
+    base::{
+        generic_repository::entry_trait::{ColumnType, Entry},
+        query_builder::{cursor::Cursor, generic_query_builder::DbType, sql_writer::SqlWriter},
+    },
+    errors::errors::ApiError,
+};
 use serde_json::Value;
+use std::sync::Arc;
 
 pub type QueryResult<T> = Result<T, ApiError>;
 
@@ -18,6 +25,30 @@ pub enum ComparisonOperator {
     IsNull,
     IsNotNull,
     Between,
+    /// `to_tsvector(config, column) @@ websearch_to_tsquery(config, value)`, holding the
+    /// text search configuration
+    TextSearch(String),
+    /// pg_trgm similarity: `column % value`
+    Similar,
+    /// `column = ANY($1)`, with the values bound as one typed array
+    AnyOf(ArrayType),
+    /// Array overlap: `column && $1`
+    ArrayOverlaps(ArrayType),
+    /// Array containment: `column @> $1`
+    ArrayContains(ArrayType),
+    /// JSONB containment: `column @> $1::jsonb`
+    JsonContains,
+    /// JSONB containment: `column <@ $1::jsonb`
+    JsonContainedBy,
+    /// JSONB key existence: `column ? $1`
+    JsonHasKey,
+    /// JSONB key existence: `column ?| $1::text[]`
+    JsonHasAnyKey,
+    /// JSONB key existence: `column ?& $1::text[]`
+    JsonHasAllKeys,
+    /// Comparison of a text value extracted from JSONB (`data->>'status' = $1`);
+    /// the value is always bound as text
+    JsonPath(Box<ComparisonOperator>),
 }
 
 impl ComparisonOperator {
@@ -36,8 +67,111 @@ impl ComparisonOperator {
             ComparisonOperator::IsNull => "IS NULL",
             ComparisonOperator::IsNotNull => "IS NOT NULL",
             ComparisonOperator::Between => "BETWEEN",
+            ComparisonOperator::TextSearch(_) => "@@",
+            ComparisonOperator::Similar => "%",
+            ComparisonOperator::AnyOf(_) => "= ANY",
+            ComparisonOperator::ArrayOverlaps(_) => "&&",
+            ComparisonOperator::ArrayContains(_) | ComparisonOperator::JsonContains => "@>",
+            ComparisonOperator::JsonContainedBy => "<@",
+            ComparisonOperator::JsonHasKey => "?",
+            ComparisonOperator::JsonHasAnyKey => "?|",
+            ComparisonOperator::JsonHasAllKeys => "?&",
+            ComparisonOperator::JsonPath(operator) => operator.to_sql(),
         }
     }
+
+    /// Whether the operator only exists in Postgres (full-text search, pg_trgm, arrays
+    /// and JSONB operators).
+    pub fn is_postgres_only(&self) -> bool {
+        matches!(
+            self,
+            ComparisonOperator::TextSearch(_)
+                | ComparisonOperator::Similar
+                | ComparisonOperator::AnyOf(_)
+                | ComparisonOperator::ArrayOverlaps(_)
+                | ComparisonOperator::ArrayContains(_)
+                | ComparisonOperator::JsonContains
+                | ComparisonOperator::JsonContainedBy
+                | ComparisonOperator::JsonHasKey
+                | ComparisonOperator::JsonHasAnyKey
+                | ComparisonOperator::JsonHasAllKeys
+                | ComparisonOperator::JsonPath(_)
+        )
+    }
+}
+
+/// Element type of an array bound with `AnyOf`, `ArrayOverlaps` or `ArrayContains`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrayType {
+    Text,
+    Int,
+    BigInt,
+    Float,
+    Bool,
+    Uuid,
+}
+
+/// Rust types that can be bound as elements of a Postgres array.
+pub trait ArrayElement {
+    const ARRAY_TYPE: ArrayType;
+
+    fn into_value(self) -> Value;
+}
+
+impl ArrayElement for String {
+    const ARRAY_TYPE: ArrayType = ArrayType::Text;
+
+    fn into_value(self) -> Value {
+        Value::String(self)
+    }
+}
+
+impl ArrayElement for &str {
+    const ARRAY_TYPE: ArrayType = ArrayType::Text;
+
+    fn into_value(self) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
+impl ArrayElement for i32 {
+    const ARRAY_TYPE: ArrayType = ArrayType::Int;
+
+    fn into_value(self) -> Value {
+        Value::from(self)
+    }
+}
+
+impl ArrayElement for i64 {
+    const ARRAY_TYPE: ArrayType = ArrayType::BigInt;
+
+    fn into_value(self) -> Value {
+        Value::from(self)
+    }
+}
+
+impl ArrayElement for f64 {
+    const ARRAY_TYPE: ArrayType = ArrayType::Float;
+
+    fn into_value(self) -> Value {
+        Value::from(self)
+    }
+}
+
+impl ArrayElement for bool {
+    const ARRAY_TYPE: ArrayType = ArrayType::Bool;
+
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl ArrayElement for uuid::Uuid {
+    const ARRAY_TYPE: ArrayType = ArrayType::Uuid;
+
+    fn into_value(self) -> Value {
+        Value::String(self.to_string())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -55,7 +189,7 @@ impl LogicalOperator {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum OrderDirection {
     Asc,
     Desc,
@@ -68,6 +202,13 @@ impl OrderDirection {
             OrderDirection::Desc => "DESC",
         }
     }
+
+    pub fn reverse(&self) -> Self {
+        match self {
+            OrderDirection::Asc => OrderDirection::Desc,
+            OrderDirection::Desc => OrderDirection::Asc,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -78,10 +219,189 @@ pub struct WhereCondition {
     pub values: Option<Vec<Value>>, // For IN, NOT IN, BETWEEN
 }
 
+impl WhereCondition {
+    // Constructors shared by QueryBuilderUtil and GroupBuilder, which validate the column
+    // beforehand. Postgres-only operators fail when building for another database.
+    fn require_dialect(db_type: DbType, operator: &ComparisonOperator) -> Result<(), ApiError> {
+        if operator.is_postgres_only() {
+            db_type.require_postgres(&format!("{:?}", operator))?;
+        }
+        Ok(())
+    }
+
+    /// Array condition (`= ANY`, `&&`, `@>`) on `column`.
+    pub fn array<V: ArrayElement>(
+        db_type: DbType,
+        column: &str,
+        operator: fn(ArrayType) -> ComparisonOperator,
+        values: Vec<V>,
+    ) -> Result<Self, ApiError> {
+        let operator = operator(V::ARRAY_TYPE);
+        Self::require_dialect(db_type, &operator)?;
+        Ok(Self {
+            column: column.to_string(),
+            operator,
+            value: None,
+            values: Some(values.into_iter().map(ArrayElement::into_value).collect()),
+        })
+    }
+
+    /// JSONB condition whose operand is a JSON document (`@>`, `<@`).
+    pub fn json(
+        db_type: DbType,
+        column: &str,
+        operator: ComparisonOperator,
+        document: Value,
+    ) -> Result<Self, ApiError> {
+        Self::require_dialect(db_type, &operator)?;
+        Ok(Self {
+            column: column.to_string(),
+            operator,
+            value: Some(document),
+            values: None,
+        })
+    }
+
+    /// JSONB key existence condition (`?`, `?|`, `?&`).
+    pub fn json_keys(
+        db_type: DbType,
+        column: &str,
+        operator: ComparisonOperator,
+        keys: &[&str],
+    ) -> Result<Self, ApiError> {
+        Self::require_dialect(db_type, &operator)?;
+        let keys: Vec<Value> = keys.iter().map(|key| Value::from(*key)).collect();
+        Ok(match operator {
+            ComparisonOperator::JsonHasKey => Self {
+                column: column.to_string(),
+                operator,
+                value: keys.into_iter().next(),
+                values: None,
+            },
+            _ => Self {
+                column: column.to_string(),
+                operator,
+                value: None,
+                values: Some(keys),
+            },
+        })
+    }
+
+    /// Comparison on the text at `path` inside a JSONB column: `data->>'status'` for one
+    /// key, `data#>>'{address,city}'` for a nested path. `column` must already be
+    /// validated and qualified.
+    pub fn json_path(
+        db_type: DbType,
+        column: &str,
+        path: &[&str],
+        operator: ComparisonOperator,
+        value: Option<String>,
+    ) -> Result<Self, ApiError> {
+        db_type.require_postgres("JsonPath")?;
+        // Keys are written into the SQL text, so only plain keys are accepted
+        let valid_key = |key: &&str| {
+            !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        };
+        if path.is_empty() || !path.iter().all(valid_key) {
+            return Err(ApiError::InvalidQuery(format!(
+                "Invalid JSON path: {}",
+                path.join(".")
+            )));
+        }
+
+        let needs_value = !matches!(
+            operator,
+            ComparisonOperator::IsNull | ComparisonOperator::IsNotNull
+        );
+        let supported = matches!(
+            operator,
+            ComparisonOperator::Equal
+                | ComparisonOperator::NotEqual
+                | ComparisonOperator::GreaterThan
+                | ComparisonOperator::GreaterThanOrEqual
+                | ComparisonOperator::LessThan
+                | ComparisonOperator::LessThanOrEqual
+                | ComparisonOperator::Like
+                | ComparisonOperator::ILike
+                | ComparisonOperator::IsNull
+                | ComparisonOperator::IsNotNull
+        );
+        if !supported || needs_value != value.is_some() {
+            return Err(ApiError::InvalidQuery(format!(
+                "Unsupported JSON path comparison: {:?}",
+                operator
+            )));
+        }
+
+        let expression = match path {
+            [key] => format!("({}->>'{}')", column, key),
+            keys => format!("({}#>>'{{{}}}')", column, keys.join(",")),
+        };
+
+        Ok(Self {
+            column: expression,
+            operator: ComparisonOperator::JsonPath(Box::new(operator)),
+            value: value.map(Value::String),
+            values: None,
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum WhereClause {
     Condition(WhereCondition),
     Group(Box<WhereGroup>),
+    Subquery(SubqueryCondition),
+    ColumnComparison(ColumnComparison),
+    /// Raw SQL fragment from `where_raw`, wrapped in parentheses
+    Raw(SqlExpr),
+}
+
+/// A SELECT statement that can be nested in another query's WHERE clause,
+/// implemented by `QueryBuilderUtil<U>` for any entry type `U`.
+pub trait Subquery: std::fmt::Debug + Send + Sync {
+    /// Writes the statement into the outer builder, binding its parameters there.
+    fn push_sql(&self, query_builder: &mut SqlWriter);
+
+    /// Fails if the statement reads a tenant table with no tenant in scope.
+    fn check_tenant_scope(&self) -> QueryResult<()>;
+}
+
+#[derive(Debug, Clone)]
+pub enum SubqueryOperator {
+    In,
+    NotIn,
+    Exists,
+    NotExists,
+}
+
+impl SubqueryOperator {
+    pub fn to_sql(&self) -> &'static str {
+        match self {
+            SubqueryOperator::In => "IN",
+            SubqueryOperator::NotIn => "NOT IN",
+            SubqueryOperator::Exists => "EXISTS",
+            SubqueryOperator::NotExists => "NOT EXISTS",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SubqueryCondition {
+    pub column: Option<String>, // None for EXISTS / NOT EXISTS
+    pub operator: SubqueryOperator,
+    pub query: Arc<dyn Subquery>,
+}
+
+/// Comparison between two validated, table-qualified columns (no bound value).
+#[derive(Debug, Clone)]
+pub struct ColumnComparison {
+    pub left: String,
+    pub operator: ComparisonOperator,
+    pub right: String,
 }
 
 #[derive(Debug, Clone)]
@@ -94,6 +414,258 @@ pub struct WhereGroup {
 pub struct OrderBy {
     pub column: String,
     pub direction: OrderDirection,
+    /// Computed sort key with bound values (ts_rank, similarity), rendered instead of `column`
+    pub expression: Option<SqlExpr>,
+}
+
+/// SQL expression made of trusted SQL text and bound values, rendered in order.
+#[derive(Debug, Clone, Default)]
+pub struct SqlExpr {
+    pub parts: Vec<SqlPart>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SqlPart {
+    Sql(String),
+    Bind(Value),
+}
+
+impl SqlExpr {
+    pub fn sql(mut self, sql: impl Into<String>) -> Self {
+        self.parts.push(SqlPart::Sql(sql.into()));
+        self
+    }
+
+    pub fn bind(mut self, value: impl Into<Value>) -> Self {
+        self.parts.push(SqlPart::Bind(value.into()));
+        self
+    }
+
+    /// Parses a raw SQL fragment where each `{}` is a placeholder for the next value.
+    /// Example: `SqlExpr::raw("lower(email) = {}", ["a@b.c"])`
+    pub fn raw<I, V>(sql: &str, values: I) -> Result<Self, ApiError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        let mut values = values.into_iter();
+        let mut expression = SqlExpr::default();
+
+        let mut pieces = sql.split("{}");
+        if let Some(first) = pieces.next() {
+            expression = expression.sql(first);
+        }
+        for piece in pieces {
+            let value = values.next().ok_or_else(|| {
+                ApiError::InvalidQuery(format!("Missing value for placeholder in: {}", sql))
+            })?;
+            expression = expression.bind(value).sql(piece);
+        }
+
+        if values.next().is_some() {
+            return Err(ApiError::InvalidQuery(format!(
+                "More values than placeholders in: {}",
+                sql
+            )));
+        }
+
+        Ok(expression)
+    }
+}
+
+/// Full-text search of `query` over one or more text columns, parsed with
+/// `websearch_to_tsquery` (quoted phrases, `or`, `-excluded`).
+/// Example: `TextSearch::new(["title", "body"], "rust -java").config("english")`
+#[derive(Debug, Clone)]
+pub struct TextSearch {
+    pub columns: Vec<String>,
+    pub query: String,
+    pub config: String,
+}
+
+impl TextSearch {
+    pub fn new<'a, I>(columns: I, query: &str) -> Self
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        Self {
+            columns: columns.into_iter().map(str::to_string).collect(),
+            query: query.to_string(),
+            config: "simple".to_string(),
+        }
+    }
+
+    /// Text search configuration (language), "simple" by default.
+    pub fn config(mut self, config: &str) -> Self {
+        self.config = config.to_string();
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AggFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggFunction {
+    pub fn to_sql(&self) -> &'static str {
+        match self {
+            AggFunction::Count => "COUNT",
+            AggFunction::Sum => "SUM",
+            AggFunction::Avg => "AVG",
+            AggFunction::Min => "MIN",
+            AggFunction::Max => "MAX",
+        }
+    }
+}
+
+/// Aggregate expression for `QueryBuilderUtil::aggregate` and the `having_*` methods.
+/// Example: `Agg::count("id").alias("n")` renders `COUNT(id) AS n`.
+#[derive(Debug, Clone)]
+pub struct Agg {
+    pub function: AggFunction,
+    pub column: Option<String>, // None for COUNT(*)
+    pub distinct: bool,
+    pub alias: Option<String>,
+}
+
+impl Agg {
+    fn new(function: AggFunction, column: &str) -> Self {
+        Self {
+            function,
+            column: Some(column.to_string()),
+            distinct: false,
+            alias: None,
+        }
+    }
+
+    pub fn count(column: &str) -> Self {
+        Self::new(AggFunction::Count, column)
+    }
+
+    pub fn count_all() -> Self {
+        Self {
+            function: AggFunction::Count,
+            column: None,
+            distinct: false,
+            alias: None,
+        }
+    }
+
+    pub fn sum(column: &str) -> Self {
+        Self::new(AggFunction::Sum, column)
+    }
+
+    pub fn avg(column: &str) -> Self {
+        Self::new(AggFunction::Avg, column)
+    }
+
+    pub fn min(column: &str) -> Self {
+        Self::new(AggFunction::Min, column)
+    }
+
+    pub fn max(column: &str) -> Self {
+        Self::new(AggFunction::Max, column)
+    }
+
+    pub fn distinct(mut self) -> Self {
+        self.distinct = true;
+        self
+    }
+
+    pub fn alias(mut self, alias: &str) -> Self {
+        self.alias = Some(alias.to_string());
+        self
+    }
+
+    /// The aggregate expression without its alias, e.g. `COUNT(DISTINCT id)`.
+    pub fn to_sql(&self) -> String {
+        let argument = match &self.column {
+            Some(column) if self.distinct => format!("DISTINCT {}", column),
+            Some(column) => column.clone(),
+            None => "*".to_string(),
+        };
+        format!("{}({})", self.function.to_sql(), argument)
+    }
+}
+
+/// Truncation unit for `QueryBuilderUtil::group_by_date_trunc`.
+#[derive(Debug, Clone)]
+pub enum TimeBucket {
+    Minute,
+    Hour,
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl TimeBucket {
+    pub fn to_sql(&self) -> &'static str {
+        match self {
+            TimeBucket::Minute => "minute",
+            TimeBucket::Hour => "hour",
+            TimeBucket::Day => "day",
+            TimeBucket::Week => "week",
+            TimeBucket::Month => "month",
+            TimeBucket::Quarter => "quarter",
+            TimeBucket::Year => "year",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupBy {
+    pub expression: String,
+    pub alias: Option<String>,
+}
+
+/// `ON CONFLICT` clause of an INSERT (upsert).
+#[derive(Debug, Clone)]
+pub struct OnConflict {
+    pub columns: Vec<String>,
+    pub action: ConflictAction,
+    /// Condition of `DO UPDATE ... WHERE`, with table-qualified columns
+    pub where_clauses: Vec<(WhereClause, Option<LogicalOperator>)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ConflictAction {
+    DoNothing,
+    /// Columns set from the proposed row: `col = EXCLUDED.col`
+    DoUpdate(Vec<String>),
+}
+
+/// Keyset (cursor) pagination settings.
+/// `columns` always ends with `id` so that the ordering is total; `directions` holds
+/// the direction of each column.
+#[derive(Debug, Clone)]
+pub struct Keyset {
+    pub columns: Vec<String>,
+    pub directions: Vec<OrderDirection>,
+    pub cursor: Option<Cursor>,
+    pub limit: u32,
+}
+
+impl Keyset {
+    /// Direction in which the column at `index` is actually read (reversed for
+    /// backward cursors).
+    pub fn effective_direction(&self, index: usize) -> OrderDirection {
+        match &self.cursor {
+            Some(cursor) if cursor.backward => self.directions[index].reverse(),
+            _ => self.directions[index].clone(),
+        }
+    }
+
+    /// Whether all columns are sorted in the same direction.
+    pub fn is_uniform(&self) -> bool {
+        self.directions.windows(2).all(|pair| pair[0] == pair[1])
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -101,6 +673,25 @@ pub struct JoinClause {
     pub join_type: JoinType,
     pub table: String,
     pub on_condition: String,
+    /// Columns of the joined table, for validating `table.column` references
+    pub columns: Vec<&'static str>,
+    pub tenant_column: Option<TenantColumn>,
+}
+
+/// Tenant column of a joined or related table, with its type for binding the tenant.
+#[derive(Debug, Clone, Copy)]
+pub struct TenantColumn {
+    pub name: &'static str,
+    pub column_type: Option<ColumnType>,
+}
+
+impl TenantColumn {
+    pub fn of<O: Entry>() -> Option<Self> {
+        O::tenant_column().map(|name| Self {
+            name,
+            column_type: O::column_type(name),
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -121,3 +712,24 @@ impl JoinType {
         }
     }
 }
+
+/// Kind of statement rendered by `QueryBuilderUtil::to_sql`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatementKind {
+    Select,
+    Insert,
+    Update,
+    Delete,
+}
+
+/// Which soft-deleted rows a query sees, for entries with a `soft_delete_column`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrashedScope {
+    /// Only rows that are not deleted
+    #[default]
+    Exclude,
+    /// All rows
+    Include,
+    /// Only deleted rows
+    Only,
+}
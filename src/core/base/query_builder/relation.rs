@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::core::{
+    base::{generic_repository::entry_trait::Entry, query_builder::query_models::TenantColumn},
+    errors::errors::ApiError,
+};
+
+/// How the rows of a relation are matched with an entry. Primary keys are `id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelationKind {
+    /// `target.id = entry.<foreign_key>`
+    BelongsTo,
+    /// `target.<foreign_key> = entry.id`
+    HasMany,
+    /// `pivot.<local_key> = entry.id AND pivot.<foreign_key> = target.id`
+    ManyToMany {
+        pivot_table: &'static str,
+        local_key: &'static str,
+    },
+}
+
+/// A relation from an entry to the rows of another `Entry` type, declared by
+/// `Entry::relations()` and loaded with `QueryBuilderUtil::with`.
+#[derive(Debug, Clone)]
+pub struct Relation {
+    pub name: &'static str,
+    pub kind: RelationKind,
+    /// Table of the related entry
+    pub table: &'static str,
+    pub foreign_key: &'static str,
+    /// Primary key column of the related entry
+    pub primary_key: &'static str,
+    pub(crate) columns: Vec<&'static str>,
+    pub(crate) soft_delete_column: Option<&'static str>,
+    pub(crate) tenant_column: Option<TenantColumn>,
+}
+
+impl Relation {
+    /// The entry references one `O` row through its `foreign_key` column.
+    pub fn belongs_to<O: Entry>(name: &'static str, foreign_key: &'static str) -> Self {
+        Self::new::<O>(name, RelationKind::BelongsTo, foreign_key)
+    }
+
+    /// The `O` rows reference the entry through their `foreign_key` column.
+    pub fn has_many<O: Entry>(name: &'static str, foreign_key: &'static str) -> Self {
+        Self::new::<O>(name, RelationKind::HasMany, foreign_key)
+    }
+
+    /// The entry and the `O` rows are linked by the rows of `pivot_table`, referencing
+    /// the entry through `local_key` and `O` through `foreign_key`.
+    pub fn many_to_many<O: Entry>(
+        name: &'static str,
+        pivot_table: &'static str,
+        local_key: &'static str,
+        foreign_key: &'static str,
+    ) -> Self {
+        let kind = RelationKind::ManyToMany {
+            pivot_table,
+            local_key,
+        };
+        Self::new::<O>(name, kind, foreign_key)
+    }
+
+    fn new<O: Entry>(name: &'static str, kind: RelationKind, foreign_key: &'static str) -> Self {
+        Self {
+            name,
+            kind,
+            table: O::table_name(),
+            foreign_key,
+            primary_key: O::primary_key_column(),
+            columns: O::columns(),
+            soft_delete_column: O::soft_delete_column(),
+            tenant_column: TenantColumn::of::<O>(),
+        }
+    }
+
+    /// Column of the entry `T` whose values the related rows are looked up by.
+    pub fn local_column<T: Entry>(&self) -> &'static str {
+        match self.kind {
+            RelationKind::BelongsTo => self.foreign_key,
+            RelationKind::HasMany | RelationKind::ManyToMany { .. } => T::primary_key_column(),
+        }
+    }
+
+    /// Qualified column of the related query compared with the entry's values.
+    pub(crate) fn key_column(&self) -> String {
+        match self.kind {
+            RelationKind::BelongsTo => format!("{}.{}", self.table, self.primary_key),
+            RelationKind::HasMany => format!("{}.{}", self.table, self.foreign_key),
+            RelationKind::ManyToMany {
+                pivot_table,
+                local_key,
+            } => format!("{}.{}", pivot_table, local_key),
+        }
+    }
+}
+
+/// An entry with the relations loaded by `QueryBuilderUtil::with`. It serializes as the
+/// entry's fields followed by one field per relation: an array of rows, or a row (or
+/// null) for `belongs_to`.
+#[derive(Debug, Serialize)]
+pub struct WithRelations<T> {
+    #[serde(flatten)]
+    pub entry: T,
+    #[serde(flatten)]
+    pub relations: HashMap<String, Value>,
+}
+
+impl<T> WithRelations<T> {
+    /// Rows of a loaded `has_many` or `many_to_many` relation.
+    pub fn many<O: Entry>(&self, name: &str) -> Result<Vec<O>, ApiError> {
+        serde_json::from_value(self.relation(name)?.clone()).map_err(ApiError::Serialization)
+    }
+
+    /// Row of a loaded `belongs_to` relation, None when the foreign key is NULL or
+    /// references no row.
+    pub fn one<O: Entry>(&self, name: &str) -> Result<Option<O>, ApiError> {
+        serde_json::from_value(self.relation(name)?.clone()).map_err(ApiError::Serialization)
+    }
+
+    fn relation(&self, name: &str) -> Result<&Value, ApiError> {
+        self.relations
+            .get(name)
+            .ok_or_else(|| ApiError::InvalidQuery(format!("Relation {} is not loaded", name)))
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+use sqlx::{Connection, Executor, FromRow};
+
+use crate::core::{
+    base::{
+        generic_repository::entry_trait::Entry,
+        query_builder::{
+            backend::Backend,
+            query_builder::QueryBuilderUtil,
+            query_models::{ConflictAction, QueryResult},
+            sql_writer::Statement,
+        },
+    },
+    errors::errors::ApiError,
+};
+
+// Emulation of RETURNING for databases without it (MySQL), used by the insert, update
+// and delete methods returning rows
+impl<T, DB> QueryBuilderUtil<T, DB>
+where
+    T: Entry + Send + Sync + Unpin + 'static + for<'r> FromRow<'r, DB::Row>,
+    DB: Backend,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+{
+    // Without RETURNING (MySQL): inserts one row, then reads it back on the same
+    // connection by its conflict columns, its id, or the id generated for it
+    pub(super) async fn insert_read_back(
+        &self,
+        conn: &mut DB::Connection,
+        row: &HashMap<String, Value>,
+    ) -> QueryResult<Option<T>> {
+        let result = self
+            .insert_writer(std::slice::from_ref(row))?
+            .into_statement()
+            .with_table(T::table_name())
+            .with_timeout(self.timeout)
+            .execute(&mut *conn)
+            .await?;
+
+        let lookup: Vec<(&str, Value)> = match &self.on_conflict {
+            Some(on_conflict) => {
+                let skipped = DB::rows_affected(&result) == 0;
+                if skipped && matches!(on_conflict.action, ConflictAction::DoNothing) {
+                    return Ok(None);
+                }
+                on_conflict
+                    .columns
+                    .iter()
+                    .map(|column| {
+                        let value = row.get(column).cloned().unwrap_or(Value::Null);
+                        (column.as_str(), value)
+                    })
+                    .collect()
+            }
+            None => match row.get(T::primary_key_column()).filter(|id| !id.is_null()) {
+                Some(id) => vec![(T::primary_key_column(), id.clone())],
+                None => {
+                    let id = DB::last_insert_id(&result).ok_or_else(|| {
+                        ApiError::InvalidQuery(format!(
+                            "Cannot read back the row inserted into {}: no id",
+                            T::table_name()
+                        ))
+                    })?;
+                    vec![(T::primary_key_column(), Value::from(id))]
+                }
+            },
+        };
+
+        let mut query = Self::new().with_trashed();
+        query.timeout = self.timeout;
+        query.tenant = self.tenant.clone();
+        for (column, value) in lookup {
+            query = query.where_eq(column, value)?;
+        }
+        query.fetch_optional(&mut *conn).await
+    }
+
+    // Emulates RETURNING for an UPDATE or DELETE: locks the matching rows with
+    // SELECT ... FOR UPDATE, runs the statement, then returns the rows as they are
+    // after it, or as they were before for a hard delete
+    pub(super) async fn execute_locked(
+        &self,
+        conn: &mut DB::Connection,
+        statement: Statement,
+        hard_delete: bool,
+    ) -> QueryResult<Vec<T>> {
+        let mut tx = conn.begin().await?;
+
+        let locked = self.fetch_for_update(&mut *tx).await?;
+
+        if locked.is_empty() {
+            tx.commit().await?;
+            return Ok(locked);
+        }
+        statement.execute(&mut *tx).await?;
+
+        let items = if hard_delete {
+            locked
+        } else {
+            let ids = locked
+                .iter()
+                .map(|item| {
+                    serde_json::to_value(item).map(|json| {
+                        json.get(T::primary_key_column())
+                            .cloned()
+                            .unwrap_or(Value::Null)
+                    })
+                })
+                .collect::<Result<Vec<Value>, _>>()
+                .map_err(ApiError::Serialization)?;
+            let mut query = Self::new()
+                .with_trashed()
+                .where_in(T::primary_key_column(), ids)?;
+            query.timeout = self.timeout;
+            query.tenant = self.tenant.clone();
+            query.fetch_all(&mut *tx).await?
+        };
+        tx.commit().await?;
+
+        Ok(items)
+    }
+}
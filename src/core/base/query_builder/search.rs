@@ -0,0 +1,32 @@
+use serde::Serialize;
+use sqlx::{ColumnIndex, Decode, FromRow, Row, Type};
+
+/// Alias of the `ts_rank` column selected by `QueryBuilderUtil::select_rank`.
+pub const RANK_ALIAS: &str = "search_rank";
+/// Alias of the `ts_headline` column selected by `QueryBuilderUtil::select_headline`.
+pub const HEADLINE_ALIAS: &str = "search_headline";
+
+/// A row returned by a full-text search, with its relevance and a highlighted snippet.
+#[derive(Debug, Serialize)]
+pub struct SearchResult<T> {
+    pub item: T,
+    pub rank: f32,
+    pub headline: String,
+}
+
+impl<'r, R, T> FromRow<'r, R> for SearchResult<T>
+where
+    R: Row,
+    T: FromRow<'r, R>,
+    f32: Decode<'r, R::Database> + Type<R::Database>,
+    String: Decode<'r, R::Database> + Type<R::Database>,
+    &'r str: ColumnIndex<R>,
+{
+    fn from_row(row: &'r R) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            item: T::from_row(row)?,
+            rank: row.try_get(RANK_ALIAS)?,
+            headline: row.try_get(HEADLINE_ALIAS)?,
+        })
+    }
+}
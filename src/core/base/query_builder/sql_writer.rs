@@ -0,0 +1,387 @@
+use std::{
+    fmt::{Display, Write},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use futures_util::future::{self, BoxFuture};
+use serde_json::Value;
+use sqlx::{
+    Connection, Database, Executor, FromRow, Pool, QueryBuilder, Transaction, TransactionManager,
+    pool::MaybePoolConnection,
+};
+use tracing::Instrument;
+
+use crate::core::{
+    base::query_builder::{
+        backend::{Backend, SqlValue, arguments},
+        generic_query_builder::DbType,
+        trace::QueryTrace,
+    },
+    errors::errors::ApiError,
+};
+
+static LOG_STATEMENTS: AtomicBool = AtomicBool::new(false);
+
+/// Enables the debug log of every statement generated by `QueryBuilderUtil`, with its
+/// parameters. Meant for development: parameters may contain personal data.
+pub fn set_statement_logging(enabled: bool) {
+    LOG_STATEMENTS.store(enabled, Ordering::Relaxed);
+}
+
+/// Writes the SQL text of a statement in the dialect of a database, with its
+/// placeholders (`$n` or `?`), and keeps the bound values so that the statement can be
+/// executed on any backend or previewed with its parameters (`QueryBuilderUtil::to_sql`).
+pub struct SqlWriter {
+    db_type: DbType,
+    sql: String,
+    params: Vec<SqlValue>,
+}
+
+impl SqlWriter {
+    pub fn new(db_type: DbType, init: impl Into<String>) -> Self {
+        Self {
+            db_type,
+            sql: init.into(),
+            params: Vec::new(),
+        }
+    }
+
+    pub fn db_type(&self) -> DbType {
+        self.db_type
+    }
+
+    pub fn push(&mut self, sql: impl Display) -> &mut Self {
+        // Writing into a String cannot fail
+        let _ = write!(self.sql, "{}", sql);
+        self
+    }
+
+    /// Binds `value` as the next parameter. On Postgres, values bound as text for another
+    /// type (numeric, enums) get a cast to that type.
+    pub fn push_bind(&mut self, value: SqlValue) -> &mut Self {
+        let cast = value
+            .postgres_cast()
+            .filter(|_| self.db_type == DbType::Postgres);
+        self.params.push(value);
+        let placeholder = self.db_type.placeholder(self.params.len());
+        match cast {
+            Some(cast) => self.push(format_args!("CAST({} AS {})", placeholder, cast)),
+            None => self.push(placeholder),
+        }
+    }
+
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    /// The statement ready to execute.
+    pub fn into_statement(self) -> Statement {
+        if LOG_STATEMENTS.load(Ordering::Relaxed) {
+            tracing::debug!(sql = self.sql(), params = ?self.params, "Generated statement");
+        }
+        Statement {
+            sql: self.sql,
+            params: self.params,
+            table: None,
+            timeout: None,
+        }
+    }
+
+    /// The SQL text and the parameters in placeholder order.
+    pub fn into_parts(self) -> (String, Vec<Value>) {
+        (self.sql, self.params.into_iter().map(Value::from).collect())
+    }
+}
+
+/// A rendered statement and its parameters.
+///
+/// Executing it takes two steps, `let (sql, arguments) = statement.into_arguments::<DB>()?`
+/// then `sqlx::query_with(&sql, arguments)`: the query borrows the SQL text, which the
+/// caller owns for as long as the query runs.
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub sql: String,
+    pub params: Vec<SqlValue>,
+    /// Table the statement is traced under, see `QueryTrace`
+    pub table: Option<&'static str>,
+    /// Time the execution methods wait for the database before failing with
+    /// `ApiError::QueryTimeout`
+    pub timeout: Option<Duration>,
+}
+
+impl Statement {
+    pub fn with_table(mut self, table: &'static str) -> Self {
+        self.table = Some(table);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Appends SQL text after the bound parameters, e.g. ` RETURNING *`.
+    pub fn push(mut self, sql: &str) -> Self {
+        self.sql.push_str(sql);
+        self
+    }
+
+    pub fn into_arguments<'q, DB: Backend>(self) -> Result<(String, DB::Arguments<'q>), ApiError> {
+        Ok((self.sql, arguments::<DB>(self.params)?))
+    }
+
+    /// The statement as a sqlx `QueryBuilder`, to be completed or executed by the caller.
+    pub fn into_query_builder<DB: Backend>(self) -> Result<QueryBuilder<'static, DB>, ApiError> {
+        let (sql, arguments) = self.into_arguments::<DB>()?;
+        Ok(QueryBuilder::with_arguments(sql, arguments))
+    }
+
+    pub async fn fetch_all<'c, DB, R, E>(self, executor: E) -> Result<Vec<R>, ApiError>
+    where
+        DB: Backend,
+        for<'t> &'t mut DB::Connection: Executor<'t, Database = DB>,
+        R: for<'r> FromRow<'r, DB::Row> + Send + Unpin,
+        E: ConnectionSource<'c, DB>,
+    {
+        let trace = QueryTrace::start(self.table, &self.sql);
+        let (timeout, params) = (self.timeout, self.params.len());
+        let (sql, arguments) = self.into_arguments::<DB>()?;
+        let mut conn = executor.connection().await?;
+        let mut timed = TimedConnection::begin(&mut conn, timeout).await?;
+        let client_timeout = timed.client_timeout();
+        let query = sqlx::query_as_with::<DB, R, _>(&sql, arguments).fetch_all(timed.conn());
+        let result = with_timeout(client_timeout, query.instrument(trace.span()))
+            .await
+            .and_then(|result| result.map_err(ApiError::database));
+        let result = timed.end(result).await;
+        trace.finish(
+            &sql,
+            params,
+            result.as_ref().ok().map(|rows| rows.len() as u64),
+        );
+        result
+    }
+
+    pub async fn fetch_optional<'c, DB, R, E>(self, executor: E) -> Result<Option<R>, ApiError>
+    where
+        DB: Backend,
+        for<'t> &'t mut DB::Connection: Executor<'t, Database = DB>,
+        R: for<'r> FromRow<'r, DB::Row> + Send + Unpin,
+        E: ConnectionSource<'c, DB>,
+    {
+        let trace = QueryTrace::start(self.table, &self.sql);
+        let (timeout, params) = (self.timeout, self.params.len());
+        let (sql, arguments) = self.into_arguments::<DB>()?;
+        let mut conn = executor.connection().await?;
+        let mut timed = TimedConnection::begin(&mut conn, timeout).await?;
+        let client_timeout = timed.client_timeout();
+        let query = sqlx::query_as_with::<DB, R, _>(&sql, arguments).fetch_optional(timed.conn());
+        let result = with_timeout(client_timeout, query.instrument(trace.span()))
+            .await
+            .and_then(|result| result.map_err(ApiError::database));
+        let result = timed.end(result).await;
+        trace.finish(
+            &sql,
+            params,
+            result.as_ref().ok().map(|row| u64::from(row.is_some())),
+        );
+        result
+    }
+
+    /// Fetches exactly one row, `ApiError::NotFound` if there is none.
+    pub async fn fetch_one<'c, DB, R, E>(self, executor: E) -> Result<R, ApiError>
+    where
+        DB: Backend,
+        for<'t> &'t mut DB::Connection: Executor<'t, Database = DB>,
+        R: for<'r> FromRow<'r, DB::Row> + Send + Unpin,
+        E: ConnectionSource<'c, DB>,
+    {
+        self.fetch_optional(executor)
+            .await?
+            .ok_or_else(|| ApiError::NotFound("No record found".to_string()))
+    }
+
+    /// Fetches the first row as is, for values read with `Backend` helpers.
+    pub async fn fetch_row<'c, DB, E>(self, executor: E) -> Result<DB::Row, ApiError>
+    where
+        DB: Backend,
+        for<'t> &'t mut DB::Connection: Executor<'t, Database = DB>,
+        E: ConnectionSource<'c, DB>,
+    {
+        let trace = QueryTrace::start(self.table, &self.sql);
+        let (timeout, params) = (self.timeout, self.params.len());
+        let (sql, arguments) = self.into_arguments::<DB>()?;
+        let mut conn = executor.connection().await?;
+        let mut timed = TimedConnection::begin(&mut conn, timeout).await?;
+        let client_timeout = timed.client_timeout();
+        let query = sqlx::query_with::<DB, _>(&sql, arguments).fetch_one(timed.conn());
+        let result = with_timeout(client_timeout, query.instrument(trace.span()))
+            .await
+            .and_then(|result| result.map_err(ApiError::database));
+        let result = timed.end(result).await;
+        trace.finish(&sql, params, result.as_ref().ok().map(|_| 1));
+        result
+    }
+
+    pub async fn execute<'c, DB, E>(self, executor: E) -> Result<DB::QueryResult, ApiError>
+    where
+        DB: Backend,
+        for<'t> &'t mut DB::Connection: Executor<'t, Database = DB>,
+        E: ConnectionSource<'c, DB>,
+    {
+        let trace = QueryTrace::start(self.table, &self.sql);
+        let (timeout, params) = (self.timeout, self.params.len());
+        let (sql, arguments) = self.into_arguments::<DB>()?;
+        let mut conn = executor.connection().await?;
+        let mut timed = TimedConnection::begin(&mut conn, timeout).await?;
+        let client_timeout = timed.client_timeout();
+        let query = sqlx::query_with::<DB, _>(&sql, arguments).execute(timed.conn());
+        let result = with_timeout(client_timeout, query.instrument(trace.span()))
+            .await
+            .and_then(|result| result.map_err(ApiError::database));
+        let result = timed.end(result).await;
+        trace.finish(
+            &sql,
+            params,
+            result.as_ref().ok().map(|result| DB::rows_affected(result)),
+        );
+        result
+    }
+}
+
+/// A pool, or a connection, that statements run on. Unlike an `Executor`, it hands over
+/// the connection itself, on which a statement's timeout is set (see `TimedConnection`).
+pub trait ConnectionSource<'c, DB: Database>: Send {
+    fn connection(self) -> BoxFuture<'c, Result<MaybePoolConnection<'c, DB>, sqlx::Error>>;
+}
+
+impl<'c, DB: Database> ConnectionSource<'c, DB> for &'c Pool<DB> {
+    fn connection(self) -> BoxFuture<'c, Result<MaybePoolConnection<'c, DB>, sqlx::Error>> {
+        Box::pin(async move { self.acquire().await.map(MaybePoolConnection::from) })
+    }
+}
+
+impl<'c, DB: Database> ConnectionSource<'c, DB> for &'c mut DB::Connection {
+    fn connection(self) -> BoxFuture<'c, Result<MaybePoolConnection<'c, DB>, sqlx::Error>> {
+        Box::pin(future::ok(MaybePoolConnection::from(self)))
+    }
+}
+
+/// Connection of a statement with a timeout.
+///
+/// On Postgres the server cancels the statement past the timeout, which frees its
+/// connection: the statement runs in a transaction, or in a savepoint of the current
+/// one, whose `statement_timeout` is the timeout. Elsewhere only the client stops
+/// waiting, and the statement keeps its connection busy until the database finishes it.
+pub struct TimedConnection<'a, DB: Database> {
+    conn: TimedConn<'a, DB>,
+    client_timeout: Option<Duration>,
+    /// `statement_timeout` of the enclosing transaction, in milliseconds, which a
+    /// released savepoint would otherwise keep
+    previous: Option<i64>,
+}
+
+enum TimedConn<'a, DB: Database> {
+    Plain(&'a mut DB::Connection),
+    Transaction(Transaction<'a, DB>),
+}
+
+// `statement_timeout` as a number of milliseconds
+const CURRENT_STATEMENT_TIMEOUT: &str =
+    "SELECT (extract(epoch FROM current_setting('statement_timeout')::interval) * 1000)::bigint";
+
+impl<'a, DB> TimedConnection<'a, DB>
+where
+    DB: Backend,
+    for<'t> &'t mut DB::Connection: Executor<'t, Database = DB>,
+{
+    pub async fn begin(
+        conn: &'a mut DB::Connection,
+        timeout: Option<Duration>,
+    ) -> Result<Self, ApiError> {
+        let Some(timeout) = timeout.filter(|_| DB::DB_TYPE == DbType::Postgres) else {
+            return Ok(Self {
+                conn: TimedConn::Plain(conn),
+                client_timeout: timeout,
+                previous: None,
+            });
+        };
+
+        let nested = DB::TransactionManager::get_transaction_depth(conn) > 0;
+        let mut tx = conn.begin().await?;
+        let previous = if nested {
+            let row = sqlx::query::<DB>(CURRENT_STATEMENT_TIMEOUT)
+                .fetch_one(&mut *tx)
+                .await?;
+            Some(DB::get_i64(&row, 0)?)
+        } else {
+            None
+        };
+        // 0 would disable the timeout
+        let millis = i64::try_from(timeout.as_millis())
+            .unwrap_or(i64::MAX)
+            .max(1);
+        set_statement_timeout(&mut tx, millis).await?;
+        Ok(Self {
+            conn: TimedConn::Transaction(tx),
+            client_timeout: None,
+            previous,
+        })
+    }
+
+    /// The connection to run the statement on.
+    pub fn conn(&mut self) -> &mut DB::Connection {
+        match &mut self.conn {
+            TimedConn::Plain(conn) => conn,
+            TimedConn::Transaction(tx) => tx,
+        }
+    }
+
+    /// Time to wait for the statement on the client side, when the database does not
+    /// enforce the timeout itself.
+    pub fn client_timeout(&self) -> Option<Duration> {
+        self.client_timeout
+    }
+
+    /// Ends the transaction of the statement, committed if it succeeded.
+    pub async fn end<R>(self, result: Result<R, ApiError>) -> Result<R, ApiError> {
+        let TimedConn::Transaction(mut tx) = self.conn else {
+            return result;
+        };
+        if result.is_err() {
+            // The statement's error matters more than a failed rollback
+            let _ = tx.rollback().await;
+            return result;
+        }
+        if let Some(previous) = self.previous {
+            set_statement_timeout(&mut tx, previous).await?;
+        }
+        tx.commit().await?;
+        result
+    }
+}
+
+async fn set_statement_timeout<DB>(conn: &mut DB::Connection, millis: i64) -> Result<(), ApiError>
+where
+    DB: Backend,
+    for<'t> &'t mut DB::Connection: Executor<'t, Database = DB>,
+{
+    let sql = format!("SET LOCAL statement_timeout = {}", millis);
+    conn.execute(sql.as_str()).await?;
+    Ok(())
+}
+
+/// Runs `future`, failing with `ApiError::QueryTimeout` past `timeout`. Only the client
+/// stops waiting, see `TimedConnection`.
+pub async fn with_timeout<F: Future>(
+    timeout: Option<Duration>,
+    future: F,
+) -> Result<F::Output, ApiError> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_| ApiError::QueryTimeout(format!("no result after {:?}", timeout))),
+        None => Ok(future.await),
+    }
+}
@@ -0,0 +1,50 @@
+use std::future::Future;
+
+use futures_util::{FutureExt, Stream, StreamExt, future, stream};
+use tokio::sync::mpsc;
+
+use crate::core::errors::errors::ApiError;
+
+/// Number of rows fetched ahead of the consumer of a stream.
+const STREAM_BUFFER: usize = 32;
+
+/// Sending half given to the producer of a `row_stream`.
+pub type RowSender<T> = mpsc::Sender<Result<T, ApiError>>;
+
+/// Turns `produce`, a future sending rows to the given sender, into a stream of those rows.
+///
+/// sqlx's row streams borrow the statement they run; the producer can own it instead.
+/// It is polled together with the stream, so no task is spawned and dropping the stream
+/// cancels the query.
+pub fn row_stream<'e, T, F, Fut>(produce: F) -> impl Stream<Item = Result<T, ApiError>> + Send + 'e
+where
+    T: Send + 'e,
+    F: FnOnce(RowSender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'e,
+{
+    let (sender, mut receiver) = mpsc::channel(STREAM_BUFFER);
+    let producer = produce(sender)
+        .into_stream()
+        .filter_map(|()| future::ready(None));
+    let rows = stream::poll_fn(move |cx| receiver.poll_recv(cx));
+
+    stream::select(rows, producer)
+}
+
+/// Forwards the rows of a sqlx stream until it ends or the receiver is dropped, and
+/// returns the number of rows sent, or the error that ended the stream.
+pub async fn send_rows<T, S>(rows: S, sender: RowSender<T>) -> Result<u64, ApiError>
+where
+    S: Stream<Item = Result<T, sqlx::Error>>,
+{
+    let mut rows = std::pin::pin!(rows);
+    let mut sent = 0;
+    while let Some(row) = rows.next().await {
+        let row = row.map_err(ApiError::database)?;
+        if sender.send(Ok(row)).await.is_err() {
+            break;
+        }
+        sent += 1;
+    }
+    Ok(sent)
+}
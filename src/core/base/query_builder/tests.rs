@@ -0,0 +1,609 @@
+//! SQL rendered by `QueryBuilderUtil::to_sql` on each database, with its parameters in
+//! bind order.
+
+use std::collections::HashMap;
+
+use serde_json::{Value, json};
+use sqlx::{MySql, Postgres, Sqlite};
+
+use crate::core::{
+    base::{
+        generic_repository::{
+            entry_trait::Entry,
+            tenant::{TenantContext, with_tenant},
+            test_support::{Comment, Note},
+        },
+        query_builder::{
+            backend::Backend,
+            cursor::Cursor,
+            generic_query_builder::DbType,
+            query_builder::QueryBuilderUtil,
+            query_models::{OrderDirection, QueryResult, StatementKind},
+        },
+    },
+    errors::errors::ApiError,
+};
+
+const NOTE_COLUMNS: &str = "id, title, body, created_at, updated_at, version";
+const COMMENT_COLUMNS: &str = "id, note_id, tenant_id, body, deleted_at";
+
+fn render<T, DB>(
+    query: QueryResult<QueryBuilderUtil<T, DB>>,
+    kind: StatementKind,
+) -> (String, Vec<Value>)
+where
+    T: crate::core::base::generic_repository::entry_trait::Entry + Send + Sync + Unpin + 'static,
+    DB: Backend,
+{
+    query.and_then(|query| query.to_sql(kind)).unwrap()
+}
+
+// The Postgres statement with its `$n` placeholders replaced by `?`, as MySQL and
+// SQLite write them
+fn question_marks(sql: &str) -> String {
+    let mut rendered = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '$' && chars.peek().is_some_and(char::is_ascii_digit) {
+            while chars.peek().is_some_and(char::is_ascii_digit) {
+                chars.next();
+            }
+            rendered.push('?');
+        } else {
+            rendered.push(c);
+        }
+    }
+    rendered
+}
+
+// Asserts the statement built by `build` on every database, given its Postgres form
+macro_rules! assert_sql_on_every_db {
+    ($build:ident, $kind:expr, $sql:expr, $params:expr) => {{
+        let params: Vec<Value> = $params;
+        assert_eq!(
+            render($build::<Postgres>(), $kind),
+            ($sql.to_string(), params.clone()),
+            "Postgres"
+        );
+        assert_eq!(
+            render($build::<MySql>(), $kind),
+            (question_marks($sql), params.clone()),
+            "MySQL"
+        );
+        assert_eq!(
+            render($build::<Sqlite>(), $kind),
+            (question_marks($sql), params),
+            "SQLite"
+        );
+    }};
+}
+
+fn note_row(title: &str, body: &str) -> HashMap<String, Value> {
+    HashMap::from([
+        ("title".to_string(), json!(title)),
+        ("body".to_string(), json!(body)),
+        ("version".to_string(), json!(1)),
+    ])
+}
+
+#[test]
+fn placeholders_depend_on_the_database() {
+    assert_eq!(DbType::Postgres.placeholder(3), "$3");
+    assert_eq!(DbType::MySQL.placeholder(3), "?");
+    assert_eq!(DbType::SQLite.placeholder(3), "?");
+    assert_eq!(
+        question_marks("a = $1 AND b IN ($2, $10)"),
+        "a = ? AND b IN (?, ?)"
+    );
+}
+
+#[test]
+fn select_with_order_limit_and_offset() {
+    fn build<DB: Backend>() -> QueryResult<QueryBuilderUtil<Note, DB>> {
+        Ok(QueryBuilderUtil::new()
+            .where_eq("title", "a")?
+            .where_gte("version", 2)?
+            .order_by("id", OrderDirection::Desc)?
+            .limit(10)
+            .offset(20))
+    }
+
+    assert_sql_on_every_db!(
+        build,
+        StatementKind::Select,
+        &format!(
+            "SELECT {} FROM notes WHERE title = $1 AND version >= $2 ORDER BY id DESC LIMIT 10 OFFSET 20",
+            NOTE_COLUMNS
+        ),
+        vec![json!("a"), json!(2)]
+    );
+}
+
+#[test]
+fn select_without_conditions() {
+    fn build<DB: Backend>() -> QueryResult<QueryBuilderUtil<Note, DB>> {
+        QueryBuilderUtil::new().select(["id", "title"])
+    }
+
+    assert_sql_on_every_db!(
+        build,
+        StatementKind::Select,
+        "SELECT id, title FROM notes",
+        vec![]
+    );
+}
+
+#[test]
+fn or_joins_the_clauses_around_it() {
+    fn build<DB: Backend>() -> QueryResult<QueryBuilderUtil<Note, DB>> {
+        QueryBuilderUtil::new()
+            .where_eq("title", "a")?
+            .or()
+            .where_eq("title", "b")?
+            .and()
+            .where_in("version", vec![1, 2])
+    }
+
+    assert_sql_on_every_db!(
+        build,
+        StatementKind::Select,
+        &format!(
+            "SELECT {} FROM notes WHERE title = $1 OR title = $2 AND version IN ($3, $4)",
+            NOTE_COLUMNS
+        ),
+        vec![json!("a"), json!("b"), json!(1), json!(2)]
+    );
+}
+
+#[test]
+fn where_groups_are_parenthesized() {
+    fn build<DB: Backend>() -> QueryResult<QueryBuilderUtil<Note, DB>> {
+        QueryBuilderUtil::new()
+            .where_group_and(|group| group.where_eq("title", "a")?.or().where_eq("body", "b"))?
+            .and()
+            .where_group_or(|group| group.where_gt("version", 1)?.or().where_null("body"))
+    }
+
+    assert_sql_on_every_db!(
+        build,
+        StatementKind::Select,
+        &format!(
+            "SELECT {} FROM notes WHERE (title = $1 OR body = $2) AND (version > $3 OR body IS NULL)",
+            NOTE_COLUMNS
+        ),
+        vec![json!("a"), json!("b"), json!(1)]
+    );
+}
+
+#[test]
+fn json_and_array_conditions_need_postgres_in_groups_too() {
+    let in_group = QueryBuilderUtil::<Note, Sqlite>::new().where_group_or(|group| {
+        group
+            .where_eq("title", "a")?
+            .or()
+            .where_any("id", vec![1i64])
+    });
+    assert!(matches!(in_group, Err(ApiError::InvalidQuery(_))));
+    let in_group = QueryBuilderUtil::<Note, MySql>::new()
+        .where_group_and(|group| group.where_json_has_key("body", "plan"));
+    assert!(matches!(in_group, Err(ApiError::InvalidQuery(_))));
+    let top_level = QueryBuilderUtil::<Note, Sqlite>::new().where_json_has_key("body", "plan");
+    assert!(matches!(top_level, Err(ApiError::InvalidQuery(_))));
+
+    let (sql, params) = render(
+        QueryBuilderUtil::<Note, Postgres>::new().where_group_or(|group| {
+            group
+                .where_eq("title", "a")?
+                .or()
+                .where_any("id", vec![1i64])
+        }),
+        StatementKind::Select,
+    );
+    assert_eq!(
+        sql,
+        format!(
+            "SELECT {} FROM notes WHERE (title = $1 OR id = ANY($2))",
+            NOTE_COLUMNS
+        )
+    );
+    assert_eq!(params, vec![json!("a"), json!([1])]);
+}
+
+#[test]
+fn ilike_is_lowercased_outside_postgres() {
+    let (sql, params) = render(
+        QueryBuilderUtil::<Note, Postgres>::new().where_ilike("title", "%A%"),
+        StatementKind::Select,
+    );
+    assert_eq!(
+        sql,
+        format!("SELECT {} FROM notes WHERE title ILIKE $1", NOTE_COLUMNS)
+    );
+    assert_eq!(params, vec![json!("%A%")]);
+
+    let (sql, _) = render(
+        QueryBuilderUtil::<Note, Sqlite>::new().where_ilike("title", "%A%"),
+        StatementKind::Select,
+    );
+    assert_eq!(
+        sql,
+        format!(
+            "SELECT {} FROM notes WHERE LOWER(title) LIKE LOWER(?)",
+            NOTE_COLUMNS
+        )
+    );
+}
+
+#[test]
+fn joined_columns_are_qualified() {
+    fn build<DB: Backend>() -> QueryResult<QueryBuilderUtil<Note, DB>> {
+        QueryBuilderUtil::new()
+            .without_tenant_scope()
+            .left_join::<Comment>("id", "note_id")?
+            .where_eq("title", "a")?
+            .where_eq("comments.body", "b")
+    }
+
+    assert_sql_on_every_db!(
+        build,
+        StatementKind::Select,
+        "SELECT notes.id, notes.title, notes.body, notes.created_at, notes.updated_at, notes.version FROM notes LEFT JOIN comments ON notes.id = comments.note_id WHERE notes.title = $1 AND comments.body = $2",
+        vec![json!("a"), json!("b")]
+    );
+}
+
+#[test]
+fn full_outer_join_is_refused_on_mysql() {
+    let query = QueryBuilderUtil::<Note, MySql>::new()
+        .without_tenant_scope()
+        .full_outer_join::<Comment>("id", "note_id");
+
+    assert!(matches!(query, Err(ApiError::InvalidQuery(_))));
+}
+
+#[test]
+fn subquery_placeholders_follow_the_outer_ones() {
+    fn build<DB: Backend>() -> QueryResult<QueryBuilderUtil<Note, DB>> {
+        let comments = QueryBuilderUtil::<Comment, DB>::new()
+            .without_tenant_scope()
+            .select(["note_id"])?
+            .where_eq("body", "b")?;
+
+        QueryBuilderUtil::new()
+            .where_eq("title", "a")?
+            .where_in_subquery("id", comments)?
+            .where_eq("version", 3)
+    }
+
+    assert_sql_on_every_db!(
+        build,
+        StatementKind::Select,
+        &format!(
+            "SELECT {} FROM notes WHERE title = $1 AND id IN (SELECT note_id FROM comments WHERE (body = $2) AND deleted_at IS NULL) AND version = $3",
+            NOTE_COLUMNS
+        ),
+        vec![json!("a"), json!("b"), json!(3)]
+    );
+}
+
+#[test]
+fn insert_columns_are_sorted() {
+    fn build<DB: Backend>() -> QueryResult<QueryBuilderUtil<Note, DB>> {
+        QueryBuilderUtil::new().values_many(vec![note_row("a", "1"), note_row("b", "2")])
+    }
+
+    assert_sql_on_every_db!(
+        build,
+        StatementKind::Insert,
+        "INSERT INTO notes (body, title, version) VALUES ($1, $2, $3), ($4, $5, $6)",
+        vec![
+            json!("1"),
+            json!("a"),
+            json!(1),
+            json!("2"),
+            json!("b"),
+            json!(1)
+        ]
+    );
+}
+
+#[test]
+fn upsert_depends_on_the_database() {
+    fn build<DB: Backend>() -> QueryResult<QueryBuilderUtil<Note, DB>> {
+        QueryBuilderUtil::new()
+            .values(note_row("a", "1"))?
+            .on_conflict(["title"])?
+            .do_update(["body", "version"])
+    }
+    let params = vec![json!("1"), json!("a"), json!(1)];
+    let conflict = "INSERT INTO notes (body, title, version) VALUES ($1, $2, $3) ON CONFLICT (title) DO UPDATE SET body = EXCLUDED.body, version = EXCLUDED.version";
+
+    assert_eq!(
+        render(build::<Postgres>(), StatementKind::Insert),
+        (conflict.to_string(), params.clone())
+    );
+    assert_eq!(
+        render(build::<Sqlite>(), StatementKind::Insert),
+        (question_marks(conflict), params.clone())
+    );
+    assert_eq!(
+        render(build::<MySql>(), StatementKind::Insert),
+        (
+            "INSERT INTO notes (body, title, version) VALUES (?, ?, ?) ON DUPLICATE KEY UPDATE body = VALUES(body), version = VALUES(version)".to_string(),
+            params
+        )
+    );
+}
+
+#[test]
+fn upsert_do_nothing_is_insert_ignore_on_mysql() {
+    fn build<DB: Backend>() -> QueryResult<QueryBuilderUtil<Note, DB>> {
+        Ok(QueryBuilderUtil::new()
+            .values(note_row("a", "1"))?
+            .on_conflict(["title"])?
+            .do_nothing())
+    }
+
+    assert_eq!(
+        render(build::<Postgres>(), StatementKind::Insert).0,
+        "INSERT INTO notes (body, title, version) VALUES ($1, $2, $3) ON CONFLICT (title) DO NOTHING"
+    );
+    assert_eq!(
+        render(build::<MySql>(), StatementKind::Insert).0,
+        "INSERT IGNORE INTO notes (body, title, version) VALUES (?, ?, ?)"
+    );
+}
+
+#[test]
+fn update_columns_are_sorted() {
+    fn build<DB: Backend>() -> QueryResult<QueryBuilderUtil<Note, DB>> {
+        QueryBuilderUtil::new()
+            .where_eq("id", 7)?
+            .set("title", "a")?
+            .set("body", "b")
+    }
+
+    assert_sql_on_every_db!(
+        build,
+        StatementKind::Update,
+        "UPDATE notes SET body = $1, title = $2 WHERE id = $3",
+        vec![json!("b"), json!("a"), json!(7)]
+    );
+}
+
+#[test]
+fn set_raw_follows_the_bound_values() {
+    fn build<DB: Backend>() -> QueryResult<QueryBuilderUtil<Note, DB>> {
+        QueryBuilderUtil::new()
+            .where_eq("id", 7)?
+            .set("title", "a")?
+            .set_raw("version", "version + {}", [1])
+    }
+
+    assert_sql_on_every_db!(
+        build,
+        StatementKind::Update,
+        "UPDATE notes SET title = $1, version = version + $2 WHERE id = $3",
+        vec![json!("a"), json!(1), json!(7)]
+    );
+}
+
+#[test]
+fn keyset_with_one_direction_compares_row_values() {
+    fn build<DB: Backend>() -> QueryResult<QueryBuilderUtil<Note, DB>> {
+        let cursor = Cursor {
+            values: vec![json!("m"), json!(7)],
+            backward: false,
+        };
+        QueryBuilderUtil::new().cursor_paginate(&["title"], OrderDirection::Desc, Some(cursor), 5)
+    }
+
+    // One more row than the page is fetched, to know whether there is a next page
+    assert_sql_on_every_db!(
+        build,
+        StatementKind::Select,
+        &format!(
+            "SELECT {} FROM notes WHERE (title, id) < ($1, $2) ORDER BY title DESC, id DESC LIMIT 6",
+            NOTE_COLUMNS
+        ),
+        vec![json!("m"), json!(7)]
+    );
+}
+
+/// Entry with a column whose type is not declared.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, sqlx::FromRow, Entry)]
+#[entry(table = "attachments")]
+struct Attachment {
+    #[entry(primary_key)]
+    id: i64,
+    content: Vec<u8>,
+}
+
+#[test]
+fn keyset_columns_must_have_a_declared_type() {
+    let result = QueryBuilderUtil::<Attachment, Postgres>::new().cursor_paginate(
+        &["content"],
+        OrderDirection::Asc,
+        None,
+        5,
+    );
+
+    assert!(matches!(result, Err(ApiError::InvalidColumn(_))));
+}
+
+#[test]
+fn keyset_with_mixed_directions_expands_the_comparison() {
+    fn build<DB: Backend>() -> QueryResult<QueryBuilderUtil<Note, DB>> {
+        let cursor = Cursor {
+            values: vec![json!("m"), json!(7)],
+            backward: false,
+        };
+        QueryBuilderUtil::new()
+            .where_eq("body", "b")?
+            .cursor_paginate_sorted(
+                &[("title", OrderDirection::Desc), ("id", OrderDirection::Asc)],
+                Some(cursor),
+                5,
+            )
+    }
+
+    assert_sql_on_every_db!(
+        build,
+        StatementKind::Select,
+        &format!(
+            "SELECT {} FROM notes WHERE (body = $1) AND ((title < $2) OR (title = $3 AND id > $4)) ORDER BY title DESC, id ASC LIMIT 6",
+            NOTE_COLUMNS
+        ),
+        vec![json!("b"), json!("m"), json!("m"), json!(7)]
+    );
+}
+
+#[test]
+fn trashed_rows_are_excluded_by_default() {
+    fn build<DB: Backend>() -> QueryResult<QueryBuilderUtil<Comment, DB>> {
+        QueryBuilderUtil::new()
+            .without_tenant_scope()
+            .where_eq("body", "a")?
+            .or()
+            .where_eq("body", "b")
+    }
+    fn with_trashed<DB: Backend>() -> QueryResult<QueryBuilderUtil<Comment, DB>> {
+        Ok(QueryBuilderUtil::new()
+            .without_tenant_scope()
+            .with_trashed())
+    }
+    fn only_trashed<DB: Backend>() -> QueryResult<QueryBuilderUtil<Comment, DB>> {
+        Ok(QueryBuilderUtil::new()
+            .without_tenant_scope()
+            .only_trashed())
+    }
+
+    // The conditions are parenthesized, so that an OR cannot reach the deleted rows
+    assert_sql_on_every_db!(
+        build,
+        StatementKind::Select,
+        &format!(
+            "SELECT {} FROM comments WHERE (body = $1 OR body = $2) AND deleted_at IS NULL",
+            COMMENT_COLUMNS
+        ),
+        vec![json!("a"), json!("b")]
+    );
+    assert_sql_on_every_db!(
+        with_trashed,
+        StatementKind::Select,
+        &format!("SELECT {} FROM comments", COMMENT_COLUMNS),
+        vec![]
+    );
+    assert_sql_on_every_db!(
+        only_trashed,
+        StatementKind::Select,
+        &format!(
+            "SELECT {} FROM comments WHERE deleted_at IS NOT NULL",
+            COMMENT_COLUMNS
+        ),
+        vec![]
+    );
+}
+
+#[test]
+fn delete_of_a_soft_deleted_entry_sets_its_column() {
+    fn build<DB: Backend>() -> QueryResult<QueryBuilderUtil<Comment, DB>> {
+        QueryBuilderUtil::new()
+            .without_tenant_scope()
+            .where_eq("body", "a")
+    }
+
+    let sql = "UPDATE comments SET deleted_at = $1 WHERE (body = $2) AND deleted_at IS NULL";
+    for (rendered, expected) in [
+        (
+            render(build::<Postgres>(), StatementKind::Delete),
+            sql.to_string(),
+        ),
+        (
+            render(build::<MySql>(), StatementKind::Delete),
+            question_marks(sql),
+        ),
+        (
+            render(build::<Sqlite>(), StatementKind::Delete),
+            question_marks(sql),
+        ),
+    ] {
+        let (sql, params) = rendered;
+        assert_eq!(sql, expected);
+        // The deletion time, then the condition
+        assert!(params[0].is_string());
+        assert_eq!(params[1..], [json!("a")]);
+    }
+}
+
+#[test]
+fn tenant_tables_need_a_tenant_in_scope() {
+    let query = QueryBuilderUtil::<Comment, Postgres>::new().where_eq("body", "a");
+    assert!(matches!(
+        query.and_then(|query| query.to_sql(StatementKind::Select)),
+        Err(ApiError::Authorization(_))
+    ));
+
+    let join = QueryBuilderUtil::<Note, Postgres>::new()
+        .inner_join::<Comment>("id", "note_id")
+        .and_then(|query| query.to_sql(StatementKind::Select));
+    assert!(matches!(join, Err(ApiError::Authorization(_))));
+}
+
+#[tokio::test]
+async fn tenant_predicates_come_after_the_conditions() {
+    fn select<DB: Backend>() -> QueryResult<QueryBuilderUtil<Comment, DB>> {
+        QueryBuilderUtil::new()
+            .where_eq("body", "a")?
+            .or()
+            .where_eq("body", "b")
+    }
+    fn insert<DB: Backend>() -> QueryResult<QueryBuilderUtil<Comment, DB>> {
+        QueryBuilderUtil::new()
+            .value("note_id", 1)?
+            .value("body", "a")
+    }
+    fn update<DB: Backend>() -> QueryResult<QueryBuilderUtil<Comment, DB>> {
+        QueryBuilderUtil::new()
+            .where_eq("id", 3)?
+            .set("body", "a")?
+            .set("tenant_id", "other")
+    }
+    fn join<DB: Backend>() -> QueryResult<QueryBuilderUtil<Note, DB>> {
+        QueryBuilderUtil::new()
+            .inner_join::<Comment>("id", "note_id")?
+            .where_eq("title", "a")
+    }
+
+    with_tenant(TenantContext::new("acme"), async {
+        assert_sql_on_every_db!(
+            select,
+            StatementKind::Select,
+            &format!(
+                "SELECT {} FROM comments WHERE (body = $1 OR body = $2) AND deleted_at IS NULL AND tenant_id = $3",
+                COMMENT_COLUMNS
+            ),
+            vec![json!("a"), json!("b"), json!("acme")]
+        );
+        // The tenant is written on insert, and an update cannot move a row to another
+        assert_sql_on_every_db!(
+            insert,
+            StatementKind::Insert,
+            "INSERT INTO comments (body, note_id, tenant_id) VALUES ($1, $2, $3)",
+            vec![json!("a"), json!(1), json!("acme")]
+        );
+        assert_sql_on_every_db!(
+            update,
+            StatementKind::Update,
+            "UPDATE comments SET body = $1, tenant_id = $2 WHERE (id = $3) AND deleted_at IS NULL AND tenant_id = $4",
+            vec![json!("a"), json!("acme"), json!(3), json!("acme")]
+        );
+        assert_sql_on_every_db!(
+            join,
+            StatementKind::Select,
+            "SELECT notes.id, notes.title, notes.body, notes.created_at, notes.updated_at, notes.version FROM notes INNER JOIN comments ON notes.id = comments.note_id AND comments.tenant_id = $1 WHERE notes.title = $2",
+            vec![json!("acme"), json!("a")]
+        );
+    })
+    .await;
+}
@@ -0,0 +1,84 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use tracing::{Span, field};
+
+static SLOW_QUERY_THRESHOLD_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Statements running longer than `threshold` are logged at warn level, with their SQL
+/// but not their parameters. A zero threshold disables the log.
+pub fn set_slow_query_threshold(threshold: Duration) {
+    SLOW_QUERY_THRESHOLD_MS.store(threshold.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Span of one statement run by the query builder, nested under the current span (the
+/// repository call, itself under the request). It records the table, the kind of
+/// statement, the number of rows returned or affected and the duration.
+pub struct QueryTrace {
+    span: Span,
+    table: &'static str,
+    kind: &'static str,
+    start: Instant,
+}
+
+impl QueryTrace {
+    pub fn start(table: Option<&'static str>, sql: &str) -> Self {
+        let table = table.unwrap_or("unknown");
+        let kind = statement_kind(sql);
+        let span = tracing::info_span!(
+            "db.query",
+            table,
+            kind,
+            rows = field::Empty,
+            duration_ms = field::Empty,
+        );
+        Self {
+            span,
+            table,
+            kind,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        self.span.clone()
+    }
+
+    /// Records the outcome of the statement; `rows` is None when it failed.
+    pub fn finish(self, sql: &str, params: usize, rows: Option<u64>) {
+        let duration = self.start.elapsed();
+        let duration_ms = duration.as_millis() as u64;
+        if let Some(rows) = rows {
+            self.span.record("rows", rows);
+        }
+        self.span.record("duration_ms", duration_ms);
+
+        let _entered = self.span.enter();
+        tracing::debug!(rows = ?rows, duration_ms, "Query");
+
+        let threshold = SLOW_QUERY_THRESHOLD_MS.load(Ordering::Relaxed);
+        if threshold > 0 && duration_ms >= threshold {
+            tracing::warn!(
+                table = self.table,
+                kind = self.kind,
+                duration_ms,
+                sql,
+                params = format_args!("{} redacted", params),
+                "Slow_Query"
+            );
+        }
+    }
+}
+
+// SELECT, INSERT, UPDATE, DELETE... from the first keyword of the statement
+fn statement_kind(sql: &str) -> &'static str {
+    let keyword = sql.split_whitespace().next().unwrap_or("");
+    [
+        "SELECT", "INSERT", "UPDATE", "DELETE", "CREATE", "EXPLAIN", "COPY",
+    ]
+    .into_iter()
+    .find(|kind| keyword.eq_ignore_ascii_case(kind))
+    .unwrap_or("OTHER")
+}
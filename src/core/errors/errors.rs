@@ -21,6 +21,9 @@ pub enum ApiError {
     #[error("Conflict error: {0}")]
     Conflict(String),
 
+    #[error("Precondition failed: {0}")]
+    PreconditionFailed(String),
+
     #[error("Internal server error: {0}")]
     InternalServer(String),
 
@@ -36,6 +39,9 @@ pub enum ApiError {
     #[error("Invalid query: {0}")]
     InvalidQuery(String),
 
+    #[error("Query timed out: {0}")]
+    QueryTimeout(String),
+
     #[error(
         "Rate limit exceeded for client {client_id}: max {max_requests} requests in {window_duration:?}"
     )]
@@ -96,6 +102,13 @@ impl ResponseError for ApiError {
                 };
                 HttpResponse::Conflict().json(error_response)
             }
+            ApiError::PreconditionFailed(message) => {
+                let error_response = ErrorResponse {
+                    status: 409,
+                    message: message.to_string(),
+                };
+                HttpResponse::Conflict().json(error_response)
+            }
             ApiError::InternalServer(message) => {
                 let error_response = ErrorResponse {
                     status: 500,
@@ -131,6 +144,13 @@ impl ResponseError for ApiError {
                 };
                 HttpResponse::BadRequest().json(error_response)
             }
+            ApiError::QueryTimeout(message) => {
+                let error_response = ErrorResponse {
+                    status: 504,
+                    message: format!("Query timed out: {}", message),
+                };
+                HttpResponse::GatewayTimeout().json(error_response)
+            }
             ApiError::RateLimitExceeded {
                 client_id,
                 max_requests,
@@ -151,9 +171,30 @@ impl ResponseError for ApiError {
     }
 }
 
+// SQLSTATE of a statement cancelled by the server, e.g. past its statement_timeout (Postgres)
+const QUERY_CANCELED: &str = "57014";
+
+fn is_query_canceled(err: &sqlx::Error) -> bool {
+    matches!(err, sqlx::Error::Database(db_err) if db_err.code().as_deref() == Some(QUERY_CANCELED))
+}
+
+impl ApiError {
+    /// Error of a statement run by the query builder: `QueryTimeout` when the database
+    /// cancelled it, `Database` otherwise.
+    pub fn database(err: sqlx::Error) -> Self {
+        if is_query_canceled(&err) {
+            return ApiError::QueryTimeout(err.to_string());
+        }
+        ApiError::Database(err)
+    }
+}
+
 // Implementation of conversions to ease usage with ApiError
 impl From<sqlx::Error> for ApiError {
     fn from(err: sqlx::Error) -> Self {
+        if is_query_canceled(&err) {
+            return ApiError::QueryTimeout(err.to_string());
+        }
         match err {
             sqlx::Error::RowNotFound => ApiError::NotFound("Not found".to_string()),
             sqlx::Error::Database(db_err) => {
@@ -4,7 +4,7 @@ use actix_web::{
     dev::{ServiceRequest, ServiceResponse},
     middleware::Next,
 };
-use tracing::{error, info, warn};
+use tracing::{Instrument, error, info, info_span, warn};
 
 pub async fn logger_middleware(
     req: ServiceRequest,
@@ -33,7 +33,9 @@ pub async fn logger_middleware(
         "Incoming_request"
     );
 
-    let res = next.call(req).await;
+    // Spans opened while handling the request (repository calls, queries) nest under it
+    let span = info_span!("request", method = %method, path = %path);
+    let res = next.call(req).instrument(span).await;
 
     match &res {
         Ok(response) => {
@@ -1,2 +1,4 @@
 pub mod logger;
 pub mod rate_limiter;
+pub mod read_your_writes;
+pub mod tenant;
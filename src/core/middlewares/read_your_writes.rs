@@ -0,0 +1,17 @@
+use actix_web::{
+    Error,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+};
+
+use crate::core::base::generic_repository::pools::read_your_writes;
+
+/// Once a request has used the primary database (a write or a transaction), its later
+/// reads go to the primary instead of a read replica that may lag behind.
+pub async fn read_your_writes_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    read_your_writes(next.call(req)).await
+}
@@ -0,0 +1,156 @@
+use actix_web::{
+    Error, HttpMessage,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web,
+};
+
+use crate::{
+    config::config::Config,
+    core::base::generic_repository::tenant::{TenantContext, with_tenant},
+    modules::auth::auth_models::Claims,
+};
+
+/// Runs the request in the scope of its tenant: the `tenant_id` claim of its token (put
+/// in the request by `auth_middleware`, which must run first), else the header named by
+/// `TENANT_HEADER` when it is configured. The header is trusted as is: only enable it
+/// behind a gateway that sets it. Without a tenant, queries of tenant tables fail.
+pub async fn tenant_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let claimed = req
+        .extensions()
+        .get::<Claims>()
+        .and_then(|claims| claims.tenant_id.clone());
+    let tenant = claimed.or_else(|| {
+        let header = req
+            .app_data::<web::Data<Config>>()?
+            .tenant
+            .header
+            .as_deref()?;
+        let value = req.headers().get(header)?.to_str().ok()?;
+        Some(value.to_string()).filter(|value| !value.is_empty())
+    });
+
+    match tenant {
+        Some(id) => with_tenant(TenantContext::new(id), next.call(req)).await,
+        None => next.call(req).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{App, HttpResponse, middleware, test};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        config::models::{
+            CacheConfig, DatabaseConfig, JwtConfig, PaginationConfig, ServerConfig, TenantConfig,
+        },
+        core::base::generic_repository::tenant::TenantScope,
+        modules::auth::{
+            auth_helpers::generate_jwt, auth_middleware::auth_middleware, auth_models::Sub,
+        },
+    };
+
+    fn config(tenant_header: Option<&str>) -> Config {
+        Config {
+            server: ServerConfig {
+                host: "127.0.0.1".to_string(),
+                port: 8080,
+                environment: "test".to_string(),
+            },
+            database: DatabaseConfig {
+                max_connections: 1,
+                acquire_timeout: 3,
+                idle_timeout: 600,
+                max_lifetime: 1800,
+                statement_timeout: 30,
+                url: "postgres://localhost/test".to_string(),
+                log_statements: false,
+                slow_query_threshold: 500,
+                replica_urls: Vec::new(),
+                read_your_writes: true,
+            },
+            cache: CacheConfig {
+                capacity: 0,
+                ttl: 60,
+            },
+            tenant: TenantConfig {
+                header: tenant_header.map(String::from),
+            },
+            pagination: PaginationConfig {
+                cursor_secret: "cursor".to_string(),
+            },
+            jwt: JwtConfig {
+                secret: "secret".to_string(),
+                expiration: 60,
+                refresh_secret: "refresh".to_string(),
+                refresh_expiration: 60,
+            },
+        }
+    }
+
+    fn bearer(config: &Config, tenant_id: Option<&str>) -> String {
+        let sub = Sub {
+            id: Uuid::new_v4(),
+            email: "user@example.com".to_string(),
+            is_admin: None,
+        };
+        let token = generate_jwt(sub, tenant_id.map(String::from), &config.jwt.secret, 60).unwrap();
+        format!("Bearer {}", token)
+    }
+
+    async fn current_tenant() -> HttpResponse {
+        match TenantScope::current() {
+            Some(TenantScope::Tenant(tenant)) => HttpResponse::Ok().json(tenant.id),
+            _ => HttpResponse::Ok().json(None::<String>),
+        }
+    }
+
+    // Tenant the handler of `request` runs for, behind the middlewares of the API
+    async fn tenant_of(config: Config, request: test::TestRequest) -> Option<String> {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .route("/", web::get().to(current_tenant))
+                .wrap(middleware::from_fn(tenant_middleware))
+                .wrap(middleware::from_fn(auth_middleware)),
+        )
+        .await;
+        test::call_and_read_body_json(&app, request.to_request()).await
+    }
+
+    #[actix_web::test]
+    async fn the_claim_of_the_token_wins_over_the_header() {
+        let config = config(Some("X-Tenant-Id"));
+        let request = test::TestRequest::get()
+            .insert_header(("Authorization", bearer(&config, Some("acme"))))
+            .insert_header(("X-Tenant-Id", "other"));
+
+        assert_eq!(tenant_of(config, request).await.as_deref(), Some("acme"));
+    }
+
+    #[actix_web::test]
+    async fn the_header_names_the_tenant_of_tokens_without_a_claim() {
+        let config = config(Some("X-Tenant-Id"));
+        let request = test::TestRequest::get()
+            .insert_header(("Authorization", bearer(&config, None)))
+            .insert_header(("X-Tenant-Id", "acme"));
+
+        assert_eq!(tenant_of(config, request).await.as_deref(), Some("acme"));
+    }
+
+    #[actix_web::test]
+    async fn the_header_is_ignored_unless_configured() {
+        let config = config(None);
+        let request = test::TestRequest::get()
+            .insert_header(("Authorization", bearer(&config, None)))
+            .insert_header(("X-Tenant-Id", "acme"));
+
+        assert_eq!(tenant_of(config, request).await, None);
+    }
+}
@@ -0,0 +1,25 @@
+use serde_json::Value;
+
+/// Formats values as one CSV line, terminated by a newline.
+pub fn csv_line<'a>(values: impl IntoIterator<Item = &'a Value>) -> String {
+    let mut line = values
+        .into_iter()
+        .map(csv_field)
+        .collect::<Vec<_>>()
+        .join(",");
+    line.push('\n');
+    line
+}
+
+/// Formats a value as a CSV field. Strings are always quoted, so that an unquoted
+/// empty field means NULL (as COPY reads it).
+pub fn csv_field(value: &Value) -> String {
+    let text = match value {
+        Value::Null => return String::new(),
+        Value::Bool(b) => return b.to_string(),
+        Value::Number(n) => return n.to_string(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
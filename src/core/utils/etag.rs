@@ -0,0 +1,67 @@
+use actix_web::http::header::{ETag, EntityTag, IfMatch};
+
+use crate::core::errors::errors::ApiError;
+
+/// `ETag` header of a resource at the given version.
+pub fn version_etag(version: i64) -> ETag {
+    ETag(EntityTag::new_strong(version.to_string()))
+}
+
+/// Version a request expects the resource to be at, from its `If-Match` header.
+///
+/// Returns `None` without header or for `If-Match: *`. A header that does not name
+/// exactly one version produced by `version_etag` (weak tag, several tags, tag that is
+/// not a version) is a malformed request, rejected as such; a version that no longer
+/// matches only fails when the update runs.
+pub fn if_match_version(if_match: Option<&IfMatch>) -> Result<Option<i64>, ApiError> {
+    let tags = match if_match {
+        None | Some(IfMatch::Any) => return Ok(None),
+        Some(IfMatch::Items(tags)) => tags,
+    };
+
+    match tags.as_slice() {
+        [tag] if !tag.weak => tag.tag().parse().map(Some).map_err(|_| invalid_if_match()),
+        _ => Err(invalid_if_match()),
+    }
+}
+
+fn invalid_if_match() -> ApiError {
+    ApiError::BadRequest(
+        "L'en-tête If-Match doit contenir un seul ETag fort renvoyé par l'API".to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn if_match(tags: Vec<EntityTag>) -> Option<IfMatch> {
+        Some(IfMatch::Items(tags))
+    }
+
+    #[test]
+    fn a_single_strong_version_is_expected() {
+        assert_eq!(if_match_version(None).unwrap(), None);
+        assert_eq!(if_match_version(Some(&IfMatch::Any)).unwrap(), None);
+        let header = if_match(vec![EntityTag::new_strong("3".to_string())]);
+        assert_eq!(if_match_version(header.as_ref()).unwrap(), Some(3));
+    }
+
+    #[test]
+    fn weak_several_or_foreign_tags_are_bad_requests() {
+        let headers = [
+            if_match(vec![EntityTag::new_weak("3".to_string())]),
+            if_match(vec![
+                EntityTag::new_strong("3".to_string()),
+                EntityTag::new_strong("4".to_string()),
+            ]),
+            if_match(vec![EntityTag::new_strong("abc".to_string())]),
+        ];
+        for header in &headers {
+            assert!(matches!(
+                if_match_version(header.as_ref()),
+                Err(ApiError::BadRequest(_))
+            ));
+        }
+    }
+}
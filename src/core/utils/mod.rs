@@ -1 +1,4 @@
+pub mod csv;
+pub mod etag;
 pub mod image;
+pub mod streaming;
@@ -0,0 +1,64 @@
+use actix_web::{HttpResponse, web::Bytes};
+use futures_util::{Stream, StreamExt, stream};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::core::{errors::errors::ApiError, utils::csv::csv_line};
+
+/// Sends the items of `items` as newline-delimited JSON, one chunk per item.
+///
+/// Nothing is buffered: each item is serialized when the client is ready to receive it.
+/// As the status is sent before the first item, an error in the middle of the stream
+/// aborts the response instead, which the client sees as a truncated body.
+pub fn ndjson_response<S, T>(items: S) -> HttpResponse
+where
+    S: Stream<Item = Result<T, ApiError>> + 'static,
+    T: Serialize,
+{
+    let body = items.map(|item| {
+        let mut line = serde_json::to_vec(&item?)?;
+        line.push(b'\n');
+        Ok::<_, ApiError>(Bytes::from(line))
+    });
+
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(body.map(log_stream_error))
+}
+
+/// Sends the items of `items` as CSV, with a header line naming `columns`.
+///
+/// Each item is serialized to a JSON object and its `columns` fields are written in
+/// order; a missing field is written as an empty value. Errors behave as in
+/// `ndjson_response`.
+pub fn csv_response<S, T>(items: S, columns: &[&str]) -> HttpResponse
+where
+    S: Stream<Item = Result<T, ApiError>> + 'static,
+    T: Serialize,
+{
+    let header: Vec<Value> = columns.iter().map(|&column| column.into()).collect();
+    let header = Bytes::from(csv_line(&header));
+
+    let columns: Vec<String> = columns.iter().map(|&column| column.to_string()).collect();
+    let rows = items.map(move |item| {
+        let item = serde_json::to_value(item?)?;
+        let line = csv_line(
+            columns
+                .iter()
+                .map(|column| item.get(column).unwrap_or(&Value::Null)),
+        );
+        Ok::<_, ApiError>(Bytes::from(line))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .streaming(
+            stream::once(async { Ok(header) })
+                .chain(rows)
+                .map(log_stream_error),
+        )
+}
+
+fn log_stream_error(chunk: Result<Bytes, ApiError>) -> Result<Bytes, ApiError> {
+    chunk.inspect_err(|e| tracing::error!("Streamed response aborted: {}", e))
+}
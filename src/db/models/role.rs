@@ -0,0 +1,13 @@
+use crate::core::base::generic_repository::entry_trait::Entry;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Role given to users through `user_roles`, see the `roles` relation of `User`.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, Entry)]
+#[entry(table = "roles")]
+pub struct Role {
+    #[entry(primary_key)]
+    pub id: Uuid,
+    pub name: String,
+}
@@ -1,18 +1,35 @@
 use crate::core::base::generic_repository::entry_trait::Entry;
+use crate::db::models::role::Role;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema, Entry)]
+#[entry(table = "users")]
+#[entry(many_to_many(
+    name = "roles",
+    entry = Role,
+    pivot = "user_roles",
+    local_key = "user_id",
+    foreign_key = "role_id"
+))]
 pub struct User {
+    #[entry(primary_key)]
     pub id: Uuid,
     pub username: String,
     pub email: String,
     pub password_hash: String,
+    #[entry(timestamp = "created")]
     pub created_at: DateTime<Utc>,
+    #[entry(timestamp = "updated")]
     pub updated_at: DateTime<Utc>,
+    #[entry(version)]
+    pub version: i64,
+    /// Tenant the user belongs to, given to its tokens at login. Users are looked up
+    /// before their tenant is known, so the table is not scoped by it.
+    pub tenant_id: Option<String>,
 }
 
 impl User {
@@ -25,33 +42,8 @@ impl User {
             password_hash,
             created_at: now,
             updated_at: now,
+            version: 1,
+            tenant_id: None,
         }
     }
 }
-
-impl Entry for User {
-    type Id = Uuid;
-
-    fn set_created_at(&mut self, created_at: DateTime<Utc>) {
-        self.created_at = created_at;
-    }
-
-    fn set_updated_at(&mut self, updated_at: DateTime<Utc>) {
-        self.updated_at = updated_at;
-    }
-
-    fn table_name() -> &'static str {
-        "users"
-    }
-
-    fn columns() -> Vec<&'static str> {
-        vec![
-            "id",
-            "username",
-            "email",
-            "password_hash",
-            "created_at",
-            "updated_at",
-        ]
-    }
-}
@@ -1,23 +1,30 @@
+use crate::core::base::generic_repository::cached_repository::CachedRepository;
+use crate::core::base::generic_repository::pools::{DbPools, pin_to_primary};
 use crate::core::base::generic_repository::repository_trait::RepositoryTrait;
+use crate::core::base::query_builder::cursor::CursorPage;
+use crate::core::base::query_builder::query_models::{Agg, TimeBucket};
+use crate::core::base::query_builder::relation::WithRelations;
+use crate::core::base::query_builder::search::SearchResult;
 use crate::core::errors::errors::ApiError;
 use crate::db::models::user::User;
-use chrono::Utc;
-use sqlx::{Pool, Postgres};
+use crate::modules::user::user_models::UserFilter;
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
+use sqlx::{PgConnection, Pool, Postgres};
+use tracing::instrument;
 use uuid::Uuid;
 
 #[derive(Clone)]
 pub struct UserRepository {
-    pool: Pool<Postgres>,
+    pools: DbPools,
 }
 
 impl UserRepository {
-    pub fn new(pool: Pool<Postgres>) -> Self {
-        Self { pool }
-    }
-
-    pub async fn find_by_email(&self, email: &str) -> Result<Option<User>, ApiError> {
-        let user = self.find_by_column("email", email).await?;
-        Ok(user.into_iter().next())
+    /// Takes a pool, or a `DbPools` to read from replicas.
+    pub fn new(pools: impl Into<DbPools>) -> Self {
+        Self {
+            pools: pools.into(),
+        }
     }
 
     pub async fn find_by_username(&self, username: &str) -> Result<Option<User>, ApiError> {
@@ -35,19 +42,19 @@ impl UserRepository {
         id: Uuid,
         new_password_hash: &str,
     ) -> Result<User, ApiError> {
-        let user = sqlx::query_as::<_, User>(
-            r#"
-            UPDATE users
-            SET password_hash = $1, updated_at = $2
-            WHERE id = $3
-            RETURNING *
-            "#,
-        )
-        .bind(new_password_hash)
-        .bind(Utc::now())
-        .bind(id)
-        .fetch_one(&self.pool)
-        .await?;
+        pin_to_primary();
+        let users = self
+            .query()
+            .set("password_hash", new_password_hash)?
+            .set("updated_at", Utc::now().to_rfc3339())?
+            .set_raw("version", "version + {}", [1])?
+            .where_eq("id", id.to_string())?
+            .update_returning(self.pools.primary())
+            .await?;
+        let user = users
+            .into_iter()
+            .next()
+            .ok_or_else(|| ApiError::NotFound(format!("No record found with id: {:?}", id)))?;
 
         Ok(user)
     }
@@ -56,39 +63,132 @@ impl UserRepository {
 // Implementation of the RepositoryTrait for UserRepository
 impl RepositoryTrait<User> for UserRepository {
     fn get_pool(&self) -> &Pool<Postgres> {
-        &self.pool
+        self.pools.primary()
+    }
+
+    fn get_read_pool(&self) -> &Pool<Postgres> {
+        self.pools.read()
     }
 
     // You can override trait methods if needed
     // For example, to customize find_all with a specific ordering:
+    #[instrument(skip_all, fields(table = "users"))]
     async fn find_all(&self) -> Result<Vec<User>, ApiError> {
-        let users = sqlx::query_as::<_, User>("SELECT * FROM users ORDER BY created_at DESC")
-            .fetch_all(&self.pool)
-            .await?;
-
-        Ok(users)
+        self.query()
+            .order_by_desc("created_at")?
+            .fetch_all(self.pools.read())
+            .await
     }
 }
 
-// Facade implementation for UserRepository
-impl UserRepository {
-    pub async fn find_all_users(&self) -> Result<Vec<User>, ApiError> {
-        self.find_all().await
+/// `UserRepository` behind the entity cache, as used by the services.
+pub type CachedUserRepository = CachedRepository<UserRepository, User>;
+
+// Facade implementation for the cached UserRepository
+impl CachedUserRepository {
+    pub async fn find_by_email(&self, email: &str) -> Result<Option<User>, ApiError> {
+        self.find_by_unique("email", email).await
+    }
+
+    pub fn stream_all_users(&self) -> impl Stream<Item = Result<User, ApiError>> + 'static {
+        self.stream_all()
+    }
+
+    pub async fn find_users_page(
+        &self,
+        filter: UserFilter,
+        cursor: Option<&str>,
+        secret: &[u8],
+    ) -> Result<CursorPage<User>, ApiError> {
+        self.paginate_filtered(filter, cursor, secret).await
+    }
+
+    /// Best matches of `query` in the usernames and emails.
+    pub async fn search_users(
+        &self,
+        query: &str,
+        limit: u32,
+    ) -> Result<Vec<SearchResult<User>>, ApiError> {
+        self.full_text_search(&["username", "email"], query, "simple", Some(limit))
+            .await
+    }
+
+    /// Number of users created per day, oldest day first.
+    pub async fn count_signups_per_day(&self) -> Result<Vec<(DateTime<Utc>, i64)>, ApiError> {
+        self.query()
+            .group_by_date_trunc("created_at", TimeBucket::Day, "day")?
+            .aggregate(Agg::count_all().alias("signups"))?
+            .order_by_asc("day")?
+            .fetch_all_as(self.get_read_pool())
+            .await
     }
 
-    pub async fn find_user_by_id(&self, id: Uuid) -> Result<Option<User>, ApiError> {
-        self.find_by_id(id).await
+    /// The user with its `roles`. The user may come from the cache, the roles are
+    /// read on every call.
+    pub async fn find_user_with_roles(
+        &self,
+        id: Uuid,
+    ) -> Result<Option<WithRelations<User>>, ApiError> {
+        let Some(user) = self.find_by_id(id).await? else {
+            return Ok(None);
+        };
+        let mut conn = self.get_read_pool().acquire().await?;
+        let mut users = self
+            .query()
+            .with("roles")?
+            .load_relations(vec![user], &mut conn)
+            .await?;
+        Ok(users.pop())
     }
 
+    /// Creates the user atomically: a concurrent registration with the same email
+    /// is rejected by the unique constraint instead of racing with `find_by_email`.
     pub async fn create_user(&self, user: User) -> Result<User, ApiError> {
-        self.create(user).await
+        let email = user.email.clone();
+        self.create_if_absent(user, &["email"])
+            .await?
+            .ok_or_else(|| {
+                ApiError::Conflict(format!("Un utilisateur avec l'email {} existe déjà", email))
+            })
+    }
+
+    /// Reads the user on `conn`, locked until the end of its transaction.
+    pub async fn find_user_for_update(
+        &self,
+        conn: &mut PgConnection,
+        id: Uuid,
+    ) -> Result<Option<User>, ApiError> {
+        let users = self
+            .query()
+            .where_eq("id", id.to_string())?
+            .fetch_for_update(conn)
+            .await?;
+        Ok(users.into_iter().next())
+    }
+
+    pub async fn find_by_email_in(
+        &self,
+        conn: &mut PgConnection,
+        email: &str,
+    ) -> Result<Option<User>, ApiError> {
+        let user = self.find_by_column_in(conn, "email", email).await?;
+        Ok(user.into_iter().next())
     }
 
-    pub async fn update_user(&self, id: Uuid, user: User) -> Result<User, ApiError> {
-        self.update(id, user).await
+    pub async fn update_user_in(
+        &self,
+        conn: &mut PgConnection,
+        id: Uuid,
+        user: User,
+    ) -> Result<User, ApiError> {
+        self.update_in(conn, id, user).await
     }
 
-    pub async fn delete_user(&self, id: Uuid) -> Result<bool, ApiError> {
-        self.delete(id).await
+    pub async fn delete_user_in(
+        &self,
+        conn: &mut PgConnection,
+        id: Uuid,
+    ) -> Result<bool, ApiError> {
+        self.delete_in(conn, id).await
     }
 }
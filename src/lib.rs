@@ -0,0 +1,5 @@
+pub mod api;
+pub mod config;
+pub mod core;
+pub mod db;
+pub mod modules;
@@ -1,18 +1,18 @@
-mod api;
-mod config;
-mod core;
-mod db;
-mod modules;
-
-use config::{config::Config, server::Server};
 use dotenv::dotenv;
+use rust_api_template::config::{config::Config, server::Server};
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
 
     // Server configuration from environment variables
-    let config = Config::from_env().expect("Server configuration failed");
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("Configuration du serveur invalide : {:#}", error);
+            std::process::exit(1);
+        }
+    };
 
     // Create server instance
     let server = Server::new(config);
@@ -4,8 +4,10 @@ use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
 
 use crate::{core::errors::errors::ApiError, modules::auth::auth_models::Claims};
 
+/// Signs a token for `user`, scoped to `tenant_id` if the user belongs to a tenant.
 pub fn generate_jwt(
     user: crate::modules::auth::auth_models::Sub,
+    tenant_id: Option<String>,
     secret: &str,
     expiration_seconds: u32,
 ) -> Result<String, jsonwebtoken::errors::Error> {
@@ -17,6 +19,7 @@ pub fn generate_jwt(
         user,
         exp: expiration.timestamp(),
         iat: now.timestamp(),
+        tenant_id,
     };
 
     let header = Header::new(Algorithm::HS256);
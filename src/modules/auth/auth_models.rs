@@ -10,6 +10,9 @@ pub struct Claims {
     pub sub: String,
     pub exp: i64,
     pub iat: i64,
+    /// Tenant the token gives access to, see `tenant_middleware`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
 }
 #[derive(Debug, Serialize, Deserialize, Clone)]
 
@@ -1,4 +1,14 @@
+use crate::core::base::query_builder::cursor::CursorPage;
+use crate::core::base::query_builder::query_filter::{
+    FilterField, FilterOperator, FilterSpec, FilterType, QueryFilter,
+};
+use crate::core::base::query_builder::query_models::OrderDirection;
+use crate::core::base::query_builder::relation::WithRelations;
+use crate::core::base::query_builder::search::SearchResult;
+use crate::core::errors::errors::ApiError;
+use crate::db::models::role::Role;
 use crate::db::models::user::User;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
@@ -11,6 +21,8 @@ pub struct UserResponse {
     pub email: String,
     pub created_at: String,
     pub updated_at: String,
+    /// Incremented on every update, also sent as `ETag`
+    pub version: i64,
 }
 
 impl From<User> for UserResponse {
@@ -21,6 +33,79 @@ impl From<User> for UserResponse {
             email: user.email,
             created_at: user.created_at.to_rfc3339(),
             updated_at: user.updated_at.to_rfc3339(),
+            version: user.version,
+        }
+    }
+}
+
+/// A user with the names of its roles.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserDetailResponse {
+    #[serde(flatten)]
+    pub user: UserResponse,
+    pub roles: Vec<String>,
+}
+
+impl TryFrom<WithRelations<User>> for UserDetailResponse {
+    type Error = ApiError;
+
+    fn try_from(user: WithRelations<User>) -> Result<Self, Self::Error> {
+        let roles = user.many::<Role>("roles")?;
+        Ok(Self {
+            user: UserResponse::from(user.entry),
+            roles: roles.into_iter().map(|role| role.name).collect(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserPageResponse {
+    pub items: Vec<UserResponse>,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+impl From<CursorPage<User>> for UserPageResponse {
+    fn from(page: CursorPage<User>) -> Self {
+        Self {
+            items: page.items.into_iter().map(UserResponse::from).collect(),
+            next_cursor: page.next_cursor,
+            prev_cursor: page.prev_cursor,
+        }
+    }
+}
+
+/// A user found by a search, with its relevance and the matching text highlighted.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserSearchResponse {
+    #[serde(flatten)]
+    pub user: UserResponse,
+    pub rank: f32,
+    pub headline: String,
+}
+
+impl From<SearchResult<User>> for UserSearchResponse {
+    fn from(result: SearchResult<User>) -> Self {
+        Self {
+            user: UserResponse::from(result.item),
+            rank: result.rank,
+            headline: result.headline,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SignupCountResponse {
+    /// Start of the day, UTC
+    pub day: String,
+    pub signups: i64,
+}
+
+impl From<(DateTime<Utc>, i64)> for SignupCountResponse {
+    fn from((day, signups): (DateTime<Utc>, i64)) -> Self {
+        Self {
+            day: day.to_rfc3339(),
+            signups,
         }
     }
 }
@@ -45,7 +130,251 @@ pub struct UpdateUserRequest {
     pub password: Option<String>,
 }
 
+#[derive(Debug, Deserialize, Validate, IntoParams)]
+pub struct UserListQuery {
+    /// Opaque cursor returned as `next_cursor` or `prev_cursor` by a previous page,
+    /// valid for the same filters and sort only
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate, IntoParams)]
+pub struct UserSearchQuery {
+    /// Words searched in the usernames and emails: quoted phrases, `or` and `-word`
+    /// are supported
+    #[validate(length(min = 1, max = 100))]
+    pub q: String,
+    /// Maximum number of results, 20 by default
+    #[validate(range(min = 1, max = 100))]
+    pub limit: Option<u32>,
+}
+
+/// Filters and sorts accepted by the user list
+pub struct UserFilters;
+
+impl FilterSpec for UserFilters {
+    type Entry = User;
+
+    const FILTERS: &'static [FilterField] = &[
+        FilterField::new(
+            "id",
+            FilterType::Uuid,
+            &[FilterOperator::Eq, FilterOperator::In],
+        ),
+        FilterField::new(
+            "username",
+            FilterType::Text,
+            &[
+                FilterOperator::Eq,
+                FilterOperator::Like,
+                FilterOperator::Ilike,
+            ],
+        ),
+        FilterField::new(
+            "email",
+            FilterType::Text,
+            &[
+                FilterOperator::Eq,
+                FilterOperator::Like,
+                FilterOperator::Ilike,
+                FilterOperator::In,
+            ],
+        ),
+        FilterField::new(
+            "version",
+            FilterType::Integer,
+            &[
+                FilterOperator::Eq,
+                FilterOperator::Gt,
+                FilterOperator::Gte,
+                FilterOperator::Lt,
+                FilterOperator::Lte,
+            ],
+        ),
+        FilterField::new(
+            "created_at",
+            FilterType::Timestamp,
+            &[
+                FilterOperator::Gt,
+                FilterOperator::Gte,
+                FilterOperator::Lt,
+                FilterOperator::Lte,
+            ],
+        ),
+        FilterField::new(
+            "updated_at",
+            FilterType::Timestamp,
+            &[
+                FilterOperator::Gt,
+                FilterOperator::Gte,
+                FilterOperator::Lt,
+                FilterOperator::Lte,
+            ],
+        ),
+    ];
+
+    const SORTS: &'static [&'static str] = &["created_at", "updated_at", "username", "email"];
+
+    const DEFAULT_SORT: &'static [(&'static str, OrderDirection)] =
+        &[("created_at", OrderDirection::Desc)];
+}
+
+pub type UserFilter = QueryFilter<UserFilters>;
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Ndjson,
+    Csv,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct UserExportQuery {
+    /// Output format: `ndjson` (default) or `csv`
+    pub format: Option<ExportFormat>,
+}
+
+/// Columns of a user export, in CSV order
+pub const USER_EXPORT_COLUMNS: [&str; 5] = ["id", "username", "email", "created_at", "updated_at"];
+
 #[derive(Debug, Deserialize, IntoParams)]
 pub struct UserIdPath {
     pub id: Uuid,
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        App, HttpResponse,
+        test::{TestRequest, call_service, init_service},
+        web,
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::core::{base::query_builder::query_models::StatementKind, errors::errors::ApiError};
+
+    fn rejection(query_string: &str) -> ApiError {
+        UserFilter::from_query_string(query_string)
+            .err()
+            .expect("the filter should be rejected")
+    }
+
+    #[test]
+    fn values_are_parsed_by_filter_type() {
+        let filter = UserFilter::from_query_string(
+            "filter[id][in]=67e55044-10b1-426f-9247-bb680e5fe0c8\
+             &filter[version][gte]=2\
+             &filter[username]=bob\
+             &filter[created_at][gte]=2024-01-01\
+             &page[size]=5",
+        )
+        .unwrap();
+
+        let (sql, params) = filter
+            .into_cursor_query(None)
+            .and_then(|query| query.to_sql(StatementKind::Select))
+            .unwrap();
+
+        assert_eq!(
+            sql,
+            "SELECT id, username, email, password_hash, created_at, updated_at, version, tenant_id \
+             FROM users WHERE id IN ($1) AND version >= $2 AND username = $3 \
+             AND (created_at >= CAST($4 AS timestamptz)) \
+             ORDER BY created_at DESC, id DESC LIMIT 6"
+        );
+        assert_eq!(
+            params,
+            vec![
+                json!("67e55044-10b1-426f-9247-bb680e5fe0c8"),
+                json!(2),
+                json!("bob"),
+                json!("2024-01-01T00:00:00+00:00"),
+            ]
+        );
+    }
+
+    #[test]
+    fn limit_is_an_alias_of_the_page_size() {
+        let filter = UserFilter::from_query_string("limit=5").unwrap();
+
+        assert_eq!(filter.page_size(), 5);
+        assert!(matches!(rejection("limit=101"), ApiError::BadRequest(_)));
+    }
+
+    #[test]
+    fn timestamps_keep_their_time_in_utc() {
+        let filter =
+            UserFilter::from_query_string("filter[updated_at][lt]=2024-01-01T12:30:00%2B02:00")
+                .unwrap();
+
+        let (_, params) = filter
+            .into_cursor_query(None)
+            .and_then(|query| query.to_sql(StatementKind::Select))
+            .unwrap();
+
+        assert_eq!(params, vec![json!("2024-01-01T10:30:00+00:00")]);
+    }
+
+    #[test]
+    fn values_not_matching_the_filter_type_are_rejected() {
+        for query_string in [
+            "filter[version]=two",
+            "filter[version][gt]=1.5",
+            "filter[id]=42",
+            "filter[id][in]=67e55044-10b1-426f-9247-bb680e5fe0c8,42",
+            "filter[created_at][gte]=yesterday",
+            "page[size]=0",
+            "page[size]=101",
+        ] {
+            assert!(
+                matches!(rejection(query_string), ApiError::BadRequest(_)),
+                "{}",
+                query_string
+            );
+        }
+    }
+
+    #[test]
+    fn columns_and_operators_outside_the_whitelist_are_rejected() {
+        for query_string in [
+            "filter[password_hash]=secret",
+            "filter[username][gt]=a",
+            "filter[created_at][like]=2024%25",
+            "filter[email][unknown]=a",
+            "sort=password_hash",
+            "sort=-version",
+        ] {
+            assert!(
+                matches!(rejection(query_string), ApiError::InvalidColumn(_)),
+                "{}",
+                query_string
+            );
+        }
+        assert!(matches!(
+            rejection("filter[email=a"),
+            ApiError::BadRequest(_)
+        ));
+    }
+
+    #[actix_web::test]
+    async fn rejected_filters_are_answered_with_a_400() {
+        let app = init_service(App::new().route(
+            "/",
+            web::get().to(|_: UserFilter| async { HttpResponse::Ok().finish() }),
+        ))
+        .await;
+
+        for (query_string, status) in [
+            ("filter[email][ilike]=%25foo%25&sort=-created_at", 200),
+            ("filter[password_hash]=secret", 400),
+            ("filter[version]=two", 400),
+        ] {
+            let request = TestRequest::get()
+                .uri(&format!("/?{}", query_string))
+                .to_request();
+            let response = call_service(&app, request).await;
+            assert_eq!(response.status().as_u16(), status, "{}", query_string);
+        }
+    }
+}
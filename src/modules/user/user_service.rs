@@ -1,7 +1,15 @@
 use crate::config::models::Repositories;
+use crate::core::base::query_builder::cursor::CursorPage;
+use crate::core::base::query_builder::relation::WithRelations;
+use crate::core::base::query_builder::search::SearchResult;
 use crate::db::models::user::User;
-use crate::{core::errors::errors::ApiError, modules::user::user_models::CreateUserRequest};
+use crate::{
+    core::errors::errors::ApiError,
+    modules::user::user_models::{CreateUserRequest, UserFilter},
+};
 use bcrypt::{DEFAULT_COST, hash};
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
 use std::sync::Arc;
 use uuid::Uuid;
 
@@ -15,15 +23,45 @@ impl UserService {
         UserService { repositories }
     }
 
-    pub async fn get_users(&self) -> Result<Vec<User>, ApiError> {
-        self.repositories.user_repository.find_all_users().await
+    pub async fn get_users(
+        &self,
+        filter: UserFilter,
+        cursor: Option<&str>,
+        secret: &[u8],
+    ) -> Result<CursorPage<User>, ApiError> {
+        self.repositories
+            .user_repository
+            .find_users_page(filter, cursor, secret)
+            .await
+    }
+
+    pub async fn search_users(
+        &self,
+        query: &str,
+        limit: u32,
+    ) -> Result<Vec<SearchResult<User>>, ApiError> {
+        self.repositories
+            .user_repository
+            .search_users(query, limit)
+            .await
     }
 
-    pub async fn get_user_by_id(&self, id: Uuid) -> Result<User, ApiError> {
+    pub async fn get_signups_per_day(&self) -> Result<Vec<(DateTime<Utc>, i64)>, ApiError> {
+        self.repositories
+            .user_repository
+            .count_signups_per_day()
+            .await
+    }
+
+    pub fn export_users(&self) -> impl Stream<Item = Result<User, ApiError>> + 'static {
+        self.repositories.user_repository.stream_all_users()
+    }
+
+    pub async fn get_user_by_id(&self, id: Uuid) -> Result<WithRelations<User>, ApiError> {
         let user = self
             .repositories
             .user_repository
-            .find_user_by_id(id)
+            .find_user_with_roles(id)
             .await?;
 
         match user {
@@ -37,11 +75,12 @@ impl UserService {
 
     pub async fn create_user(&self, user: CreateUserRequest) -> Result<User, ApiError> {
         // Check if the email already exists
-        if let Some(_) = self
+        if self
             .repositories
             .user_repository
             .find_by_email(&user.email)
             .await?
+            .is_some()
         {
             return Err(ApiError::Conflict(format!(
                 "Un utilisateur avec l'email {} existe déjà",
@@ -65,52 +104,81 @@ impl UserService {
         username: Option<String>,
         email: Option<String>,
         password: Option<String>,
+        expected_version: Option<i64>,
     ) -> Result<User, ApiError> {
-        // Retrieve the existing user
-        let mut user = self.get_user_by_id(id).await?;
+        // Password hashing, before the transaction as it is slow
+        let password_hash = password.as_deref().map(hash_password).transpose()?;
+        let user_repository = &self.repositories.user_repository;
 
-        // Update fields if provided
-        if let Some(new_username) = username {
-            user.username = new_username;
-        }
-
-        if let Some(new_email) = email {
-            // Check if the new email is already used by another user
-            if new_email != user.email {
-                if let Some(existing) = self
-                    .repositories
-                    .user_repository
-                    .find_by_email(&new_email)
+        self.repositories
+            .transaction(async |uow| {
+                // Retrieve the existing user from the primary, locked until the update
+                let mut user = user_repository
+                    .find_user_for_update(uow.conn(), id)
                     .await?
+                    .ok_or_else(|| {
+                        ApiError::NotFound(format!("Utilisateur avec l'ID {} non trouvé", id))
+                    })?;
+
+                // The update is refused if the user changed since the version the client
+                // edited
+                if let Some(expected_version) = expected_version {
+                    user.version = expected_version;
+                }
+
+                // Update fields if provided
+                if let Some(new_username) = username {
+                    user.username = new_username;
+                }
+
+                if let Some(new_email) = email
+                    && new_email != user.email
                 {
-                    if existing.id != id {
+                    // Check if the new email is already used by another user
+                    if let Some(existing) = user_repository
+                        .find_by_email_in(uow.conn(), &new_email)
+                        .await?
+                        && existing.id != id
+                    {
                         return Err(ApiError::Conflict(format!(
                             "Un utilisateur avec l'email {} existe déjà",
                             new_email
                         )));
                     }
+                    user.email = new_email;
                 }
-                user.email = new_email;
-            }
-        }
 
-        if let Some(new_password) = password {
-            user.password_hash = hash_password(&new_password)?;
-        }
+                if let Some(password_hash) = password_hash {
+                    user.password_hash = password_hash;
+                }
 
-        // Update the user
-        self.repositories
-            .user_repository
-            .update_user(id, user)
+                // Update the user
+                user_repository.update_user_in(uow.conn(), id, user).await
+            })
             .await
     }
 
     pub async fn delete_user(&self, id: Uuid) -> Result<bool, ApiError> {
-        // Check if the user exists
-        self.get_user_by_id(id).await?;
+        let user_repository = &self.repositories.user_repository;
+
+        self.repositories
+            .transaction(async |uow| {
+                // Check if the user exists, locked until it is deleted
+                if user_repository
+                    .find_user_for_update(uow.conn(), id)
+                    .await?
+                    .is_none()
+                {
+                    return Err(ApiError::NotFound(format!(
+                        "Utilisateur avec l'ID {} non trouvé",
+                        id
+                    )));
+                }
 
-        // Delete the user
-        self.repositories.user_repository.delete_user(id).await
+                // Delete the user
+                user_repository.delete_user_in(uow.conn(), id).await
+            })
+            .await
     }
 }
 